@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface Account { id: number, name: string, account_type: string, currency: string, product: string | null, cash_account_type: string | null, status: string, details: string, balance: number, owner_name: string | null, icon: string | null, institution_name: string, created_at: string, updated_at: string, config: string | null, number: string | null, }
\ No newline at end of file
+export interface Account { id: number, name: string, account_type: string, currency: string, product: string | null, cash_account_type: string | null, status: string, details: string, balance: string, owner_name: string | null, icon: string | null, institution_name: string, created_at: string, updated_at: string, config: string | null, number: string | null, }
\ No newline at end of file
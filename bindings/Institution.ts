@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface Institution { id: string, name: string, bic: string, transaction_total_days: string, countries: Array<string>, logo: string, }
\ No newline at end of file
+export interface Institution { id: string, name: string, bic: string, transaction_total_days: string, max_access_valid_for_days: string | null, countries: Array<string>, logo: string, }
\ No newline at end of file
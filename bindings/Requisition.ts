@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface Requisition { id: string, status: string, redirect: string, accounts: Array<string>, link: string, }
\ No newline at end of file
+export interface Requisition { id: string, status: string, institution_id: string, redirect: string, accounts: Array<string>, link: string, agreement: string | null, }
\ No newline at end of file
@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface Session { id: number, user_id: number, expires_at: string, created_at: string, updated_at: string, }
\ No newline at end of file
@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface Transaction { id: number, externalId: string, creditorName: string | null, debtorName: string | null, remittanceInformation: string | null, bookingDate: string, bookingDatetime: string | null, transactionAmount: string, transactionAmountCurrency: string, proprietaryBankTransactionCode: string | null, currencyExchangeRate: string | null, currencyExchangeSourceCurrency: string | null, currencyExchangeTargetCurrency: string | null, merchantId: number | null, accountId: number, createdAt: string, updatedAt: string, }
\ No newline at end of file
+export interface Transaction { id: number, externalId: string, status: string, creditorName: string | null, debtorName: string | null, creditorAccount: string | null, debtorAccount: string | null, remittanceInformation: string | null, bookingDate: string, bookingDatetime: string | null, valueDate: string | null, transactionAmount: string, transactionAmountCurrency: string, convertedAmount: string | null, convertedCurrency: string | null, proprietaryBankTransactionCode: string | null, currencyExchangeRate: string | null, currencyExchangeSourceCurrency: string | null, currencyExchangeTargetCurrency: string | null, merchantId: number | null, labels: string | null, recurrence: string | null, mcc: string | null, accountId: number, createdAt: string, updatedAt: string, }
\ No newline at end of file
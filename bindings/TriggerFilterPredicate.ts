@@ -1,3 +1,4 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+import type { TextField } from "./TextField";
 
-export type TriggerFilterPredicate = { Account: Array<number> };
\ No newline at end of file
+export type TriggerFilterPredicate = { Account: Array<number> } | "Incoming" | "Outgoing" | { Amount: { min: number | null, max: number | null, } } | { AbsoluteAmount: { min: number | null, max: number | null, } } | { Currency: Array<string> } | { Contains: { field: TextField, text: string, } } | { Regex: { field: TextField, pattern: string, } } | { MerchantId: Array<number> } | { MerchantLabel: string } | { Weekday: Array<string> } | { BookingDate: { from: string | null, to: string | null, } } | { Any: Array<TriggerFilterPredicate> } | { All: Array<TriggerFilterPredicate> } | { Not: TriggerFilterPredicate };
\ No newline at end of file
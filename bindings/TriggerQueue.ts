@@ -1,3 +1,3 @@
 // This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
 
-export interface TriggerQueue { id: number, payload: string, user_id: number, trigger_id: number, created_at: string, updated_at: string, }
\ No newline at end of file
+export interface TriggerQueue { id: number, payload: string, status: string, attempts: number, next_attempt_at: string, last_error: string | null, user_id: number, trigger_id: number, created_at: string, updated_at: string, }
\ No newline at end of file
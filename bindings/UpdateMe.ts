@@ -0,0 +1,3 @@
+// This file was generated by [ts-rs](https://github.com/Aleph-Alpha/ts-rs). Do not edit this file manually.
+
+export interface UpdateMe { name: string | null, email: string | null, password: string | null, primary_currency: string | null, current_password: string | null, }
\ No newline at end of file
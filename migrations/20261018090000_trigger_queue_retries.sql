@@ -0,0 +1,8 @@
+-- Queue entries are retried with exponential backoff and parked as `failed`
+-- once they run out of attempts.
+ALTER TABLE trigger_queue
+    ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'pending' AFTER payload,
+    ADD COLUMN attempts INT UNSIGNED NOT NULL DEFAULT 0 AFTER status,
+    ADD COLUMN next_attempt_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP AFTER attempts,
+    ADD COLUMN last_error TEXT NULL AFTER next_attempt_at,
+    ADD INDEX trigger_queue_status_next_attempt_at (status, next_attempt_at);
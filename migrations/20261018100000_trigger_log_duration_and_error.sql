@@ -0,0 +1,5 @@
+ALTER TABLE trigger_log
+    ADD COLUMN duration_ms INT UNSIGNED NOT NULL DEFAULT 0 AFTER status,
+    ADD COLUMN error TEXT NULL AFTER duration_ms,
+    ADD INDEX trigger_log_trigger_id_created_at (trigger_id, created_at),
+    ADD INDEX trigger_log_user_id_created_at (user_id, created_at);
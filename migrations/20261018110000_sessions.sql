@@ -0,0 +1,11 @@
+-- Login sessions. Only a SHA-256 hash of the bearer token is stored.
+CREATE TABLE sessions (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    token_hash CHAR(64) NOT NULL,
+    user_id INT UNSIGNED NOT NULL,
+    expires_at DATETIME NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    UNIQUE KEY sessions_token_hash (token_hash),
+    INDEX sessions_user_id (user_id)
+);
@@ -0,0 +1,15 @@
+-- Schedule state for `ultrafinance daemon`. Jobs with no account (enrichment,
+-- the trigger queue) run once for the whole instance. A NULL interval uses the
+-- default passed to `daemon start`.
+CREATE TABLE daemon_jobs (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    job VARCHAR(32) NOT NULL,
+    account_id INT UNSIGNED NULL,
+    interval_seconds INT UNSIGNED NULL,
+    last_run_at DATETIME NULL,
+    next_run_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    last_error TEXT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    INDEX daemon_jobs_job_account_id (job, account_id)
+);
@@ -0,0 +1,4 @@
+ALTER TABLE transactions
+    ADD COLUMN creditor_account VARCHAR(64) NULL AFTER debtor_name,
+    ADD COLUMN debtor_account VARCHAR(64) NULL AFTER creditor_account,
+    ADD COLUMN value_date DATE NULL AFTER booking_datetime;
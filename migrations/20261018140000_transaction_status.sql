@@ -0,0 +1,3 @@
+ALTER TABLE transactions
+    ADD COLUMN status VARCHAR(16) NOT NULL DEFAULT 'booked' AFTER external_id,
+    ADD INDEX transactions_account_id_status (account_id, status);
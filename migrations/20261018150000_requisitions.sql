@@ -0,0 +1,26 @@
+-- GoCardless (Nordigen) requisitions and the accounts they grant access to.
+-- Consent lapses `access_valid_for_days` after the requisition is linked.
+CREATE TABLE requisitions (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    nordigen_id VARCHAR(64) NOT NULL,
+    institution_id VARCHAR(128) NOT NULL,
+    status VARCHAR(8) NOT NULL,
+    link VARCHAR(1024) NOT NULL,
+    access_valid_for_days INT UNSIGNED NOT NULL DEFAULT 90,
+    linked_at DATETIME NULL,
+    expires_at DATETIME NULL,
+    expiring_notified_at DATETIME NULL,
+    user_id INT UNSIGNED NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    updated_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP ON UPDATE CURRENT_TIMESTAMP,
+    UNIQUE KEY requisitions_nordigen_id (nordigen_id),
+    INDEX requisitions_user_id (user_id),
+    INDEX requisitions_status_expires_at (status, expires_at)
+);
+
+CREATE TABLE requisition_accounts (
+    requisition_id INT UNSIGNED NOT NULL,
+    account_id INT UNSIGNED NOT NULL,
+    PRIMARY KEY (requisition_id, account_id),
+    INDEX requisition_accounts_account_id (account_id)
+);
@@ -0,0 +1,5 @@
+-- Terms of the end user agreement each requisition was created with.
+ALTER TABLE requisitions
+    ADD COLUMN agreement_id VARCHAR(64) NULL AFTER institution_id,
+    ADD COLUMN max_historical_days INT UNSIGNED NOT NULL DEFAULT 90 AFTER link,
+    ADD COLUMN access_scope VARCHAR(64) NOT NULL DEFAULT 'balances,details,transactions' AFTER access_valid_for_days;
@@ -0,0 +1,12 @@
+-- Every balance fetched for an account, one row per balance type per fetch.
+-- `accounts.balance` keeps the latest value of the account's main balance.
+CREATE TABLE account_balances (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    account_id INT UNSIGNED NOT NULL,
+    balance_type VARCHAR(32) NOT NULL,
+    amount VARCHAR(32) NOT NULL,
+    currency VARCHAR(3) NOT NULL,
+    reference_date DATE NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    INDEX account_balances_account_id_balance_type (account_id, balance_type, created_at)
+);
@@ -0,0 +1,7 @@
+-- Balances were stored as FLOAT, which loses cents on large balances. Store the exact
+-- decimal as text, like transaction amounts. Going through DECIMAL first keeps large
+-- balances from being written out with an exponent.
+ALTER TABLE accounts ADD COLUMN exact_balance VARCHAR(32) NOT NULL DEFAULT '0' AFTER balance;
+UPDATE accounts SET exact_balance = CAST(CAST(balance AS DECIMAL(20,2)) AS CHAR);
+ALTER TABLE accounts DROP COLUMN balance;
+ALTER TABLE accounts CHANGE exact_balance balance VARCHAR(32) NOT NULL DEFAULT '0';
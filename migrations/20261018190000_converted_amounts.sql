@@ -0,0 +1,9 @@
+-- Amounts converted into the user's primary currency with the exchange rates at the time
+-- they were imported. NULL when there was no rate for the currency.
+ALTER TABLE transactions
+    ADD COLUMN converted_amount VARCHAR(32) NULL AFTER transaction_amount_currency,
+    ADD COLUMN converted_currency VARCHAR(3) NULL AFTER converted_amount;
+
+ALTER TABLE account_balances
+    ADD COLUMN converted_amount VARCHAR(32) NULL AFTER currency,
+    ADD COLUMN converted_currency VARCHAR(3) NULL AFTER converted_amount;
@@ -0,0 +1,16 @@
+-- Keep a snapshot of the rates for every day they're fetched, rather than only the latest
+-- per base currency, so amounts are converted at the rate of the day they were booked.
+CREATE TABLE exchange_rates_by_date (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    base_code VARCHAR(3) NOT NULL,
+    date DATE NOT NULL,
+    conversion_rates TEXT NOT NULL,
+    last_update DATETIME NOT NULL,
+    UNIQUE INDEX exchange_rates_base_code_date (base_code, date)
+);
+
+INSERT INTO exchange_rates_by_date (base_code, date, conversion_rates, last_update)
+SELECT base_code, DATE(last_update), conversion_rates, last_update FROM exchange_rates;
+
+DROP TABLE exchange_rates;
+RENAME TABLE exchange_rates_by_date TO exchange_rates;
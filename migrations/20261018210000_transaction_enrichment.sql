@@ -0,0 +1,5 @@
+-- What the merchant enricher found out about the transaction itself, as opposed to its merchant.
+ALTER TABLE transactions
+    ADD COLUMN labels TEXT NULL AFTER merchant_id,
+    ADD COLUMN recurrence VARCHAR(32) NULL AFTER labels,
+    ADD COLUMN mcc VARCHAR(64) NULL AFTER recurrence;
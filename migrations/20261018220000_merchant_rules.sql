@@ -0,0 +1,12 @@
+-- Text patterns that match transactions to a merchant locally, before paying an enricher to.
+-- Rules without a user apply to everyone's transactions.
+CREATE TABLE merchant_rules (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    pattern VARCHAR(255) NOT NULL,
+    field VARCHAR(32) NOT NULL DEFAULT 'any',
+    merchant_id INT UNSIGNED NOT NULL,
+    user_id INT UNSIGNED NULL,
+    learned TINYINT(1) NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    UNIQUE INDEX merchant_rules_user_id_field_pattern (user_id, field, pattern)
+);
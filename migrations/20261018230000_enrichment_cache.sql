@@ -0,0 +1,15 @@
+-- What each merchant enricher returned for a normalized description, so repeats don't pay for
+-- another lookup. `enrichment` is NULL when the provider found nothing. `lookups` counts the
+-- calls made to the provider for the key and `hits` the ones the cache saved.
+CREATE TABLE enrichment_cache (
+    id INT UNSIGNED NOT NULL AUTO_INCREMENT PRIMARY KEY,
+    provider VARCHAR(32) NOT NULL,
+    cache_key CHAR(64) NOT NULL,
+    description VARCHAR(255) NOT NULL,
+    enrichment TEXT NULL,
+    lookups INT UNSIGNED NOT NULL DEFAULT 1,
+    hits INT UNSIGNED NOT NULL DEFAULT 0,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    expires_at DATETIME NOT NULL,
+    UNIQUE INDEX enrichment_cache_provider_cache_key (provider, cache_key)
+);
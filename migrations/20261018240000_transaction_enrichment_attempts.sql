@@ -0,0 +1,5 @@
+-- When the daemon last tried to find a merchant for a transaction, so ones no provider
+-- knows don't keep newer transactions from being tried.
+ALTER TABLE transactions
+    ADD COLUMN enrichment_attempted_at DATETIME NULL AFTER mcc,
+    ADD INDEX transactions_merchant_id_enrichment_attempted_at (merchant_id, enrichment_attempted_at);
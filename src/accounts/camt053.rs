@@ -0,0 +1,628 @@
+use anyhow::anyhow;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::accounts::{
+    read_statements, SourceAccountDetails, SourceBalance, SourceTransaction, StatementIds,
+};
+use crate::ultrafinance::Money;
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Account {
+    /// A camt.053 file, or a directory of them.
+    pub path: String,
+    /// IBAN (or other account id) of the statements to import, for files holding more than one
+    /// account.
+    pub iban: Option<String>,
+    pub institution_name: Option<String>,
+    pub owner_name: Option<String>,
+}
+
+// The subset of the camt.053 schema we import. Element names are kept as in the schema, and
+// the optional elements cover the differences between camt.053.001.02 and later versions.
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Document {
+    BkToCstmrStmt: BankToCustomerStatement,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct BankToCustomerStatement {
+    #[serde(default)]
+    Stmt: Vec<Statement>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Statement {
+    Acct: CashAccount,
+    #[serde(default)]
+    Bal: Vec<Balance>,
+    #[serde(default)]
+    Ntry: Vec<Entry>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct CashAccount {
+    Id: AccountId,
+    Ccy: Option<String>,
+    Ownr: Option<Party>,
+    Svcr: Option<Servicer>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct AccountId {
+    IBAN: Option<String>,
+    Othr: Option<OtherId>,
+}
+
+impl AccountId {
+    fn id(&self) -> Option<&str> {
+        self.IBAN.as_deref().or(self.Othr.as_ref().map(|o| o.Id.as_str()))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct OtherId {
+    Id: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Servicer {
+    FinInstnId: FinancialInstitution,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct FinancialInstitution {
+    BIC: Option<String>,
+    BICFI: Option<String>,
+    Nm: Option<String>,
+}
+
+/// A party's name is `Nm` in version 2, and `Pty/Nm` from version 8.
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Party {
+    Nm: Option<String>,
+    Pty: Option<Box<Party>>,
+}
+
+impl Party {
+    fn name(&self) -> Option<String> {
+        self.Nm.clone().or(self.Pty.as_ref().and_then(|p| p.name()))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct PartyAccount {
+    Id: AccountId,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct XmlAmount {
+    #[serde(rename = "@Ccy")]
+    Ccy: String,
+    #[serde(rename = "$text")]
+    value: String,
+}
+
+impl XmlAmount {
+    fn signed(&self, credit_debit: &str) -> Result<Money, anyhow::Error> {
+        let amount = match credit_debit {
+            "DBIT" => format!("-{}", self.value.trim()),
+            _ => self.value.trim().to_string(),
+        };
+        Money::parse(&amount, &self.Ccy)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct DateAndTime {
+    Dt: Option<NaiveDate>,
+    DtTm: Option<String>,
+}
+
+impl DateAndTime {
+    fn date(&self) -> Option<NaiveDate> {
+        self.Dt.or(self.datetime().map(|d| d.date()))
+    }
+
+    /// `DtTm` may or may not have a timezone offset, which is converted to UTC.
+    fn datetime(&self) -> Option<NaiveDateTime> {
+        let datetime = self.DtTm.as_deref()?;
+        chrono::DateTime::parse_from_rfc3339(datetime)
+            .map(|d| d.naive_utc())
+            .or(NaiveDateTime::parse_from_str(datetime, "%Y-%m-%dT%H:%M:%S%.f"))
+            .ok()
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Balance {
+    Tp: BalanceType,
+    Amt: XmlAmount,
+    CdtDbtInd: String,
+    Dt: DateAndTime,
+}
+
+/// The Berlin Group name for an ISO 20022 balance code.
+fn balance_type(code: &str) -> Option<&'static str> {
+    match code {
+        "CLBD" => Some(SourceBalance::CLOSING_BOOKED),
+        "CLAV" => Some(SourceBalance::CLOSING_AVAILABLE),
+        "ITBD" => Some(SourceBalance::INTERIM_BOOKED),
+        "ITAV" => Some(SourceBalance::INTERIM_AVAILABLE),
+        "OPBD" => Some(SourceBalance::OPENING_BOOKED),
+        "FWAV" => Some(SourceBalance::FORWARD_AVAILABLE),
+        _ => None,
+    }
+}
+
+/// The latest balance of each type across the statements, with the closing balance first.
+fn balances_from(statements: &[Statement]) -> Result<Vec<SourceBalance>, anyhow::Error> {
+    let mut balances: Vec<SourceBalance> = vec![];
+    for balance in statements.iter().flat_map(|s| &s.Bal) {
+        let Some(balance_type) = balance.Tp.CdOrPrtry.Cd.as_deref().and_then(balance_type) else {
+            continue;
+        };
+        let balance = SourceBalance {
+            balance_type: balance_type.to_string(),
+            amount: balance.Amt.signed(&balance.CdtDbtInd)?,
+            reference_date: balance.Dt.date(),
+        };
+        match balances.iter_mut().find(|b| b.balance_type == balance.balance_type) {
+            Some(existing) if existing.reference_date > balance.reference_date => {}
+            Some(existing) => *existing = balance,
+            None => balances.push(balance),
+        }
+    }
+    balances.sort_by_key(|b| b.balance_type != SourceBalance::CLOSING_BOOKED);
+    Ok(balances)
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct BalanceType {
+    CdOrPrtry: Code,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Code {
+    Cd: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Entry {
+    NtryRef: Option<String>,
+    Amt: XmlAmount,
+    CdtDbtInd: String,
+    Sts: EntryStatus,
+    BookgDt: Option<DateAndTime>,
+    ValDt: Option<DateAndTime>,
+    AcctSvcrRef: Option<String>,
+    BkTxCd: Option<BankTransactionCode>,
+    #[serde(default)]
+    NtryDtls: Vec<EntryDetails>,
+    AddtlNtryInf: Option<String>,
+}
+
+/// `<Sts>BOOK</Sts>` in version 2, `<Sts><Cd>BOOK</Cd></Sts>` from version 8.
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct EntryStatus {
+    #[serde(rename = "$text")]
+    text: Option<String>,
+    Cd: Option<String>,
+}
+
+impl EntryStatus {
+    fn is_booked(&self) -> bool {
+        self.text.as_deref().or(self.Cd.as_deref()).map(str::trim) == Some("BOOK")
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct BankTransactionCode {
+    Domn: Option<Domain>,
+    Prtry: Option<Code>,
+}
+
+impl BankTransactionCode {
+    /// The proprietary code if there is one, otherwise the ISO domain, family and sub family.
+    fn code(&self) -> Option<String> {
+        self.Prtry.as_ref().and_then(|p| p.Cd.clone()).or(self
+            .Domn
+            .as_ref()
+            .map(|d| format!("{}-{}-{}", d.Cd, d.Fmly.Cd, d.Fmly.SubFmlyCd)))
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Domain {
+    Cd: String,
+    Fmly: Family,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Family {
+    Cd: String,
+    SubFmlyCd: String,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct EntryDetails {
+    #[serde(default)]
+    TxDtls: Vec<TransactionDetails>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct TransactionDetails {
+    Refs: Option<References>,
+    RltdPties: Option<RelatedParties>,
+    RmtInf: Option<RemittanceInformation>,
+    AddtlTxInf: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct References {
+    AcctSvcrRef: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct RelatedParties {
+    Dbtr: Option<Party>,
+    DbtrAcct: Option<PartyAccount>,
+    Cdtr: Option<Party>,
+    CdtrAcct: Option<PartyAccount>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct RemittanceInformation {
+    #[serde(default)]
+    Ustrd: Vec<String>,
+    #[serde(default)]
+    Strd: Vec<StructuredRemittance>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct StructuredRemittance {
+    #[serde(default)]
+    RfrdDocInf: Vec<ReferredDocument>,
+    CdtrRefInf: Option<CreditorReference>,
+    #[serde(default)]
+    AddtlRmtInf: Vec<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct ReferredDocument {
+    Nb: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct CreditorReference {
+    Ref: Option<String>,
+}
+
+impl RemittanceInformation {
+    /// Unstructured lines, then structured references (document numbers, creditor references
+    /// such as RF references, and their additional information).
+    fn text(&self) -> String {
+        let structured = self.Strd.iter().flat_map(|s| {
+            s.RfrdDocInf
+                .iter()
+                .filter_map(|d| d.Nb.clone())
+                .chain(s.CdtrRefInf.as_ref().and_then(|c| c.Ref.clone()))
+                .chain(s.AddtlRmtInf.iter().cloned())
+        });
+        self.Ustrd
+            .iter()
+            .cloned()
+            .chain(structured)
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+fn parse(data: &str) -> Result<Vec<Statement>, anyhow::Error> {
+    let document: Document =
+        quick_xml::de::from_str(data).map_err(|e| anyhow!("Invalid camt.053 statement: {}", e))?;
+    Ok(document.BkToCstmrStmt.Stmt)
+}
+
+impl Account {
+    fn statements(&self) -> Result<Vec<Statement>, anyhow::Error> {
+        let mut statements = vec![];
+        for data in read_statements(&self.path)? {
+            statements.extend(parse(&data)?);
+        }
+        let iban = match &self.iban {
+            Some(iban) => iban.clone(),
+            None => statements
+                .first()
+                .and_then(|s| s.Acct.Id.id())
+                .map(str::to_string)
+                .ok_or(anyhow!("No statements found in {}", self.path))?,
+        };
+        statements.retain(|s| s.Acct.Id.id() == Some(iban.as_str()));
+        if statements.is_empty() {
+            return Err(anyhow!("No statements for account {} found in {}", iban, self.path));
+        }
+        Ok(statements)
+    }
+
+    fn transactions_from(&self, statements: &[Statement]) -> Result<Vec<SourceTransaction>, anyhow::Error> {
+        let mut ids = StatementIds::default();
+        statements
+            .iter()
+            .flat_map(|s| s.Ntry.iter().map(move |entry| (s, entry)))
+            .filter(|(_, entry)| entry.Sts.is_booked())
+            .map(|(statement, entry)| {
+                let booking_date = entry
+                    .BookgDt
+                    .as_ref()
+                    .and_then(|d| d.date())
+                    .ok_or(anyhow!("Booked entry without a booking date"))?;
+                let amount = entry.Amt.signed(&entry.CdtDbtInd)?;
+                // Batch bookings have a line per transaction; the first one has the counterparty
+                // for single transactions, which are by far the most common.
+                let details = entry.NtryDtls.iter().flat_map(|d| &d.TxDtls).next();
+                let parties = details.and_then(|d| d.RltdPties.as_ref());
+                let remittance_information = details
+                    .and_then(|d| d.RmtInf.as_ref())
+                    .map(|r| r.text())
+                    .filter(|r| !r.is_empty())
+                    .or(details.and_then(|d| d.AddtlTxInf.clone()))
+                    .or(entry.AddtlNtryInf.clone());
+                let id = entry
+                    .AcctSvcrRef
+                    .clone()
+                    .or(details.and_then(|d| d.Refs.as_ref()).and_then(|r| r.AcctSvcrRef.clone()))
+                    // Entry references are only unique within a statement.
+                    .or(entry.NtryRef.as_ref().map(|r| {
+                        format!("{}-{}-{}", statement.Acct.Id.id().unwrap_or_default(), booking_date, r)
+                    }))
+                    .unwrap_or_else(|| ids.generate(booking_date, &amount.amount.to_string(), remittance_information.as_deref()));
+
+                Ok(SourceTransaction {
+                    id,
+                    pending: false,
+                    creditor_name: parties.and_then(|p| p.Cdtr.as_ref()).and_then(|p| p.name()),
+                    debtor_name: parties.and_then(|p| p.Dbtr.as_ref()).and_then(|p| p.name()),
+                    creditor_account: parties
+                        .and_then(|p| p.CdtrAcct.as_ref())
+                        .and_then(|a| a.Id.id())
+                        .map(str::to_string),
+                    debtor_account: parties
+                        .and_then(|p| p.DbtrAcct.as_ref())
+                        .and_then(|a| a.Id.id())
+                        .map(str::to_string),
+                    remittance_information,
+                    booking_date,
+                    booking_datetime: entry.BookgDt.as_ref().and_then(|d| d.datetime()),
+                    value_date: entry.ValDt.as_ref().and_then(|d| d.date()),
+                    transaction_amount: amount,
+                    currency_exchange_rate: None,
+                    proprietary_bank_transaction_code: entry.BkTxCd.as_ref().and_then(|c| c.code()),
+                    currency_exchange_source_currency: None,
+                    currency_exchange_target_currency: None,
+                })
+            })
+            .collect()
+    }
+}
+
+impl crate::accounts::SourceAccount for Account {
+    async fn details(&self) -> Result<SourceAccountDetails, anyhow::Error> {
+        let statements = self.statements()?;
+        let account = &statements.first().unwrap().Acct;
+        let id = account.Id.id().unwrap_or_default().to_string();
+        let institution = account.Svcr.as_ref().map(|s| &s.FinInstnId);
+        Ok(SourceAccountDetails {
+            id: id.clone(),
+            number: id,
+            currency: account
+                .Ccy
+                .clone()
+                .or(statements.iter().flat_map(|s| &s.Bal).map(|b| b.Amt.Ccy.clone()).next())
+                .unwrap_or_default(),
+            details: format!("camt.053 statement {}", self.path),
+            owner_name: self.owner_name.clone().or(account.Ownr.as_ref().and_then(|o| o.name())),
+            icon: None,
+            institution_name: self
+                .institution_name
+                .clone()
+                .or(institution.and_then(|i| i.Nm.clone()))
+                .or(institution.and_then(|i| i.BICFI.clone().or(i.BIC.clone())))
+                .unwrap_or("camt.053".into()),
+        })
+    }
+
+    /// The closing booked balance of the most recent statement.
+    async fn balances(&self) -> Result<Vec<SourceBalance>, anyhow::Error> {
+        let balances = balances_from(&self.statements()?)?;
+        if !balances.iter().any(|b| b.balance_type == SourceBalance::CLOSING_BOOKED) {
+            return Err(anyhow!("No closing balance found in {}", self.path));
+        }
+        Ok(balances)
+    }
+
+    async fn transactions(
+        &self,
+        date_from: &Option<NaiveDate>,
+        date_to: &Option<NaiveDate>,
+    ) -> Result<Vec<SourceTransaction>, anyhow::Error> {
+        Ok(self
+            .transactions_from(&self.statements()?)?
+            .into_iter()
+            .filter(|t| date_from.is_none_or(|from| t.booking_date >= from))
+            .filter(|t| date_to.is_none_or(|to| t.booking_date <= to))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATEMENT: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <GrpHdr><MsgId>MSG1</MsgId><CreDtTm>2024-01-04T06:00:00</CreDtTm></GrpHdr>
+    <Stmt>
+      <Id>STMT1</Id>
+      <Acct>
+        <Id><IBAN>NL91ABNA0417164300</IBAN></Id>
+        <Ccy>EUR</Ccy>
+        <Ownr><Nm>Example BV</Nm></Ownr>
+        <Svcr><FinInstnId><BIC>ABNANL2A</BIC></FinInstnId></Svcr>
+      </Acct>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">1000.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2024-01-01</Dt></Dt>
+      </Bal>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>CLBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">2487.50</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Dt><Dt>2024-01-03</Dt></Dt>
+      </Bal>
+      <Ntry>
+        <Amt Ccy="EUR">12.50</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><Dt>2024-01-02</Dt></BookgDt>
+        <ValDt><Dt>2024-01-01</Dt></ValDt>
+        <AcctSvcrRef>REF-1</AcctSvcrRef>
+        <BkTxCd><Domn><Cd>PMNT</Cd><Fmly><Cd>ICDT</Cd><SubFmlyCd>ESCT</SubFmlyCd></Fmly></Domn></BkTxCd>
+        <NtryDtls>
+          <TxDtls>
+            <RltdPties>
+              <Dbtr><Nm>Example BV</Nm></Dbtr>
+              <DbtrAcct><Id><IBAN>NL91ABNA0417164300</IBAN></Id></DbtrAcct>
+              <Cdtr><Nm>Coffee &amp; Co</Nm></Cdtr>
+              <CdtrAcct><Id><IBAN>NL12RABO0123456789</IBAN></Id></CdtrAcct>
+            </RltdPties>
+            <RmtInf>
+              <Strd><CdtrRefInf><Tp><CdOrPrtry><Cd>SCOR</Cd></CdOrPrtry></Tp><Ref>RF18539007547034</Ref></CdtrRefInf></Strd>
+            </RmtInf>
+          </TxDtls>
+        </NtryDtls>
+      </Ntry>
+      <Ntry>
+        <NtryRef>2</NtryRef>
+        <Amt Ccy="EUR">1500.00</Amt>
+        <CdtDbtInd>CRDT</CdtDbtInd>
+        <Sts>BOOK</Sts>
+        <BookgDt><DtTm>2024-01-03T09:30:00+01:00</DtTm></BookgDt>
+        <ValDt><Dt>2024-01-03</Dt></ValDt>
+        <NtryDtls>
+          <TxDtls>
+            <RltdPties>
+              <Dbtr><Nm>ACME GmbH</Nm></Dbtr>
+              <DbtrAcct><Id><IBAN>DE89370400440532013000</IBAN></Id></DbtrAcct>
+            </RltdPties>
+            <RmtInf><Ustrd>Invoice 2023-12</Ustrd><Ustrd>Thank you</Ustrd></RmtInf>
+          </TxDtls>
+        </NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">99.00</Amt>
+        <CdtDbtInd>DBIT</CdtDbtInd>
+        <Sts>PDNG</Sts>
+        <BookgDt><Dt>2024-01-03</Dt></BookgDt>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>"#;
+
+    fn account() -> Account {
+        Account {
+            path: "statement.xml".into(),
+            iban: None,
+            institution_name: None,
+            owner_name: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_statement() {
+        let statements = parse(STATEMENT).unwrap();
+        assert_eq!(statements.len(), 1);
+        let transactions = account().transactions_from(&statements).unwrap();
+        // The pending entry isn't imported.
+        assert_eq!(transactions.len(), 2);
+
+        let coffee = &transactions[0];
+        assert_eq!(coffee.id, "REF-1");
+        assert_eq!(coffee.transaction_amount.amount.to_string(), "-12.50");
+        assert_eq!(coffee.creditor_name.as_deref(), Some("Coffee & Co"));
+        assert_eq!(coffee.creditor_account.as_deref(), Some("NL12RABO0123456789"));
+        assert_eq!(coffee.remittance_information.as_deref(), Some("RF18539007547034"));
+        assert_eq!(coffee.value_date, NaiveDate::from_ymd_opt(2024, 1, 1));
+        assert_eq!(coffee.proprietary_bank_transaction_code.as_deref(), Some("PMNT-ICDT-ESCT"));
+
+        let salary = &transactions[1];
+        assert_eq!(salary.transaction_amount.amount.to_string(), "1500.00");
+        assert_eq!(salary.debtor_name.as_deref(), Some("ACME GmbH"));
+        assert_eq!(salary.debtor_account.as_deref(), Some("DE89370400440532013000"));
+        assert_eq!(salary.remittance_information.as_deref(), Some("Invoice 2023-12 Thank you"));
+        assert_eq!(salary.booking_date, NaiveDate::from_ymd_opt(2024, 1, 3).unwrap());
+        assert_eq!(
+            salary.booking_datetime,
+            NaiveDate::from_ymd_opt(2024, 1, 3).unwrap().and_hms_opt(8, 30, 0)
+        );
+        assert_eq!(salary.id, "NL91ABNA0417164300-2024-01-03-2");
+
+        let balances = balances_from(&statements).unwrap();
+        assert_eq!(balances.len(), 2);
+        assert_eq!(balances[0].balance_type, SourceBalance::CLOSING_BOOKED);
+        assert_eq!(balances[0].amount.amount.to_string(), "2487.50");
+        assert_eq!(balances[0].reference_date, NaiveDate::from_ymd_opt(2024, 1, 3));
+        assert_eq!(balances[1].balance_type, SourceBalance::OPENING_BOOKED);
+    }
+
+    #[test]
+    fn test_parse_version_8_status_and_parties() {
+        let entry: Entry = quick_xml::de::from_str(
+            r#"<Ntry>
+                <Amt Ccy="EUR">5.00</Amt>
+                <CdtDbtInd>DBIT</CdtDbtInd>
+                <Sts><Cd>BOOK</Cd></Sts>
+                <BookgDt><Dt>2024-02-01</Dt></BookgDt>
+                <NtryDtls><TxDtls><RltdPties><Cdtr><Pty><Nm>Shop</Nm></Pty></Cdtr></RltdPties></TxDtls></NtryDtls>
+            </Ntry>"#,
+        )
+        .unwrap();
+        assert!(entry.Sts.is_booked());
+        let parties = entry.NtryDtls[0].TxDtls[0].RltdPties.as_ref().unwrap();
+        assert_eq!(parties.Cdtr.as_ref().unwrap().name().as_deref(), Some("Shop"));
+    }
+}
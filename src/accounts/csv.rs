@@ -0,0 +1,425 @@
+use anyhow::anyhow;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::accounts::{SourceAccountDetails, SourceBalance, SourceTransaction, StatementIds};
+use crate::ultrafinance::{Amount, Money};
+
+/// A column, either by its header name or its zero-based index.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AmountSign {
+    /// Negative amounts are money going out, as on most bank statements.
+    #[default]
+    Normal,
+    /// Positive amounts are money going out, as on most credit card statements.
+    Inverted,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Columns {
+    pub date: Column,
+    /// Single signed amount column. Use `debit` and `credit` instead for split columns.
+    pub amount: Option<Column>,
+    pub debit: Option<Column>,
+    pub credit: Option<Column>,
+    /// Joined with a space to make the remittance information.
+    #[serde(default)]
+    pub description: Vec<Column>,
+    /// The other party: the creditor for outgoing transactions, the debtor for incoming ones.
+    pub counterparty: Option<Column>,
+    pub currency: Option<Column>,
+    pub balance: Option<Column>,
+    /// Bank reference, used as the transaction id instead of a hash when present.
+    pub id: Option<Column>,
+}
+
+impl Default for Column {
+    fn default() -> Self {
+        Column::Index(0)
+    }
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+fn default_has_headers() -> bool {
+    true
+}
+
+fn default_date_format() -> String {
+    "%Y-%m-%d".to_string()
+}
+
+fn default_decimal_separator() -> char {
+    '.'
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Account {
+    pub path: String,
+    pub currency: String,
+    pub number: Option<String>,
+    pub institution_name: Option<String>,
+    pub owner_name: Option<String>,
+    pub columns: Columns,
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    #[serde(default)]
+    pub amount_sign: AmountSign,
+    #[serde(default = "default_decimal_separator")]
+    pub decimal_separator: char,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    #[serde(default = "default_has_headers")]
+    pub has_headers: bool,
+    /// Lines to skip before the header row, for banks that put a preamble above it.
+    #[serde(default)]
+    pub skip_rows: usize,
+}
+
+struct Row {
+    transaction: SourceTransaction,
+    balance: Option<String>,
+}
+
+impl Account {
+    fn read(&self) -> Result<String, anyhow::Error> {
+        std::fs::read_to_string(&self.path)
+            .map_err(|e| anyhow!("Unable to read statement {}: {}", self.path, e))
+    }
+
+    fn parse(&self, data: &str) -> Result<Vec<Row>, anyhow::Error> {
+        let data = data.lines().skip(self.skip_rows).collect::<Vec<_>>().join("\n");
+        let mut reader = ::csv::ReaderBuilder::new()
+            .delimiter(self.delimiter as u8)
+            .has_headers(self.has_headers)
+            .flexible(true)
+            .trim(::csv::Trim::All)
+            .from_reader(data.as_bytes());
+
+        let headers = match self.has_headers {
+            true => Some(reader.headers()?.clone()),
+            false => None,
+        };
+        let index = |column: &Column| -> Result<usize, anyhow::Error> {
+            match column {
+                Column::Index(index) => Ok(*index),
+                Column::Name(name) => headers
+                    .as_ref()
+                    .and_then(|h| h.iter().position(|h| h == name))
+                    .ok_or(anyhow!("Column \"{}\" not found in {}", name, self.path)),
+            }
+        };
+
+        let columns = &self.columns;
+        let date = index(&columns.date)?;
+        let amount = columns.amount.as_ref().map(index).transpose()?;
+        let debit = columns.debit.as_ref().map(index).transpose()?;
+        let credit = columns.credit.as_ref().map(index).transpose()?;
+        if amount.is_none() && debit.is_none() && credit.is_none() {
+            return Err(anyhow!("Either an amount column or debit/credit columns are required"));
+        }
+        let description = columns.description.iter().map(index).collect::<Result<Vec<_>, _>>()?;
+        let counterparty = columns.counterparty.as_ref().map(index).transpose()?;
+        let currency = columns.currency.as_ref().map(index).transpose()?;
+        let balance = columns.balance.as_ref().map(index).transpose()?;
+        let id = columns.id.as_ref().map(index).transpose()?;
+
+        let mut ids = StatementIds::default();
+        let mut rows = vec![];
+        for (line, record) in reader.records().enumerate() {
+            let record = record?;
+            let field = |index: Option<usize>| {
+                index
+                    .and_then(|i| record.get(i))
+                    .filter(|v| !v.is_empty())
+                    .map(str::to_string)
+            };
+            let Some(raw_date) = field(Some(date)) else {
+                // Footers and blank lines.
+                continue;
+            };
+            let booking_date = NaiveDate::parse_from_str(&raw_date, &self.date_format)
+                .map_err(|e| anyhow!("Invalid date \"{}\" on row {}: {}", raw_date, line + 1, e))?;
+
+            // Some banks fill the unused debit or credit cell with zero, so go by the non-zero one.
+            let split = |index: Option<usize>| -> Result<Option<String>, anyhow::Error> {
+                field(index)
+                    .map(|v| Ok(self.normalize_amount(&v)?.trim_start_matches('-').to_string()))
+                    .transpose()
+            };
+            let is_zero = |value: &str| value.parse::<Amount>().is_ok_and(|a| a == Amount::ZERO);
+            let amount = match field(amount) {
+                Some(amount) => {
+                    let amount = self.normalize_amount(&amount)?;
+                    match self.amount_sign {
+                        AmountSign::Normal => amount,
+                        AmountSign::Inverted => negate(&amount),
+                    }
+                }
+                None => match (split(debit)?, split(credit)?) {
+                    (Some(debit), Some(credit)) if is_zero(&debit) => credit,
+                    (Some(debit), Some(credit)) if is_zero(&credit) => negate(&debit),
+                    (Some(_), Some(_)) => return Err(anyhow!("Both a debit and a credit on row {}", line + 1)),
+                    (Some(debit), None) => negate(&debit),
+                    (None, Some(credit)) => credit,
+                    (None, None) => return Err(anyhow!("No amount on row {}", line + 1)),
+                },
+            };
+
+            let remittance_information = description
+                .iter()
+                .filter_map(|i| field(Some(*i)))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let remittance_information = Some(remittance_information).filter(|r| !r.is_empty());
+            let is_outgoing = amount.starts_with('-');
+            let counterparty = field(counterparty);
+
+            let id = match field(id) {
+                Some(id) => id,
+                None => ids.generate(booking_date, &amount, remittance_information.as_deref()),
+            };
+
+            rows.push(Row {
+                transaction: SourceTransaction {
+                    id,
+                    pending: false,
+                    creditor_name: counterparty.clone().filter(|_| is_outgoing),
+                    debtor_name: counterparty.filter(|_| !is_outgoing),
+                    creditor_account: None,
+                    debtor_account: None,
+                    remittance_information,
+                    booking_date,
+                    booking_datetime: None,
+                    value_date: None,
+                    transaction_amount: Money::parse(&amount, &field(currency).unwrap_or(self.currency.clone()))
+                        .map_err(|e| anyhow!("{} on row {}", e, line + 1))?,
+                    currency_exchange_rate: None,
+                    proprietary_bank_transaction_code: None,
+                    currency_exchange_source_currency: None,
+                    currency_exchange_target_currency: None,
+                },
+                balance: field(balance)
+                    .map(|b| self.normalize_amount(&b))
+                    .transpose()?,
+            });
+        }
+        Ok(rows)
+    }
+
+    /// Strip currency symbols and thousands separators, leaving a plain decimal like `-1234.56`.
+    /// The sign can lead or trail the digits, like `-1.234,56` or `1.234,56-`.
+    fn normalize_amount(&self, raw: &str) -> Result<String, anyhow::Error> {
+        let invalid = || anyhow!("Invalid amount \"{}\"", raw);
+        let first_digit = raw.find(|c: char| c.is_ascii_digit()).ok_or_else(invalid)?;
+        let last_digit = raw.rfind(|c: char| c.is_ascii_digit()).ok_or_else(invalid)?;
+        if raw[first_digit..last_digit].contains('-') {
+            return Err(invalid());
+        }
+        let negative = raw[..first_digit].contains('-')
+            || raw[last_digit..].contains('-')
+            || (raw.starts_with('(') && raw.ends_with(')'));
+        let digits = raw
+            .chars()
+            .filter(|c| c.is_ascii_digit() || *c == self.decimal_separator)
+            .map(|c| if c == self.decimal_separator { '.' } else { c })
+            .collect::<String>();
+        digits.parse::<Amount>().map_err(|_| invalid())?;
+        Ok(match negative {
+            true => format!("-{}", digits),
+            false => digits,
+        })
+    }
+}
+
+/// The row with the balance after the newest transaction. Several rows can share the date, so
+/// it's the one the file lists last, or first for files listing the newest transactions first.
+fn latest_balance(rows: &[Row]) -> Option<&Row> {
+    let rows = rows.iter().filter(|r| r.balance.is_some()).collect::<Vec<_>>();
+    let newest_first = rows.first()?.transaction.booking_date > rows.last()?.transaction.booking_date;
+    let latest = rows.iter().map(|r| r.transaction.booking_date).max()?;
+    let mut on_latest = rows.into_iter().filter(|r| r.transaction.booking_date == latest);
+    match newest_first {
+        true => on_latest.next(),
+        false => on_latest.next_back(),
+    }
+}
+
+fn negate(amount: &str) -> String {
+    match amount.strip_prefix('-') {
+        Some(amount) => amount.to_string(),
+        None => format!("-{}", amount),
+    }
+}
+
+impl crate::accounts::SourceAccount for Account {
+    async fn details(&self) -> Result<SourceAccountDetails, anyhow::Error> {
+        let number = self.number.clone().unwrap_or_else(|| {
+            std::path::Path::new(&self.path)
+                .file_stem()
+                .map(|s| s.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+        Ok(SourceAccountDetails {
+            id: number.clone(),
+            number,
+            currency: self.currency.clone(),
+            details: format!("CSV statement {}", self.path),
+            owner_name: self.owner_name.clone(),
+            icon: None,
+            institution_name: self.institution_name.clone().unwrap_or("CSV".into()),
+        })
+    }
+
+    /// The balance column of the most recent row.
+    async fn balances(&self) -> Result<Vec<SourceBalance>, anyhow::Error> {
+        let rows = self.parse(&self.read()?)?;
+        let latest = latest_balance(&rows).ok_or(anyhow!("No balance column configured for {}", self.path))?;
+        Ok(vec![SourceBalance {
+            balance_type: SourceBalance::CLOSING_BOOKED.to_string(),
+            amount: Money::new(
+                latest.balance.as_deref().unwrap_or_default().parse()?,
+                latest.transaction.transaction_amount.currency,
+            ),
+            reference_date: Some(latest.transaction.booking_date),
+        }])
+    }
+
+    async fn transactions(
+        &self,
+        date_from: &Option<NaiveDate>,
+        date_to: &Option<NaiveDate>,
+    ) -> Result<Vec<SourceTransaction>, anyhow::Error> {
+        Ok(self
+            .parse(&self.read()?)?
+            .into_iter()
+            .map(|r| r.transaction)
+            .filter(|t| date_from.is_none_or(|from| t.booking_date >= from))
+            .filter(|t| date_to.is_none_or(|to| t.booking_date <= to))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(columns: Columns) -> Account {
+        Account {
+            path: "statement.csv".into(),
+            currency: "EUR".into(),
+            number: None,
+            institution_name: None,
+            owner_name: None,
+            columns,
+            date_format: "%d/%m/%Y".into(),
+            amount_sign: AmountSign::Normal,
+            decimal_separator: ',',
+            delimiter: ';',
+            has_headers: true,
+            skip_rows: 1,
+        }
+    }
+
+    #[test]
+    fn test_parse_signed_amounts() {
+        let account = account(Columns {
+            date: Column::Name("Date".into()),
+            amount: Some(Column::Name("Amount".into())),
+            description: vec![Column::Name("Description".into()), Column::Index(3)],
+            counterparty: Some(Column::Name("Payee".into())),
+            balance: Some(Column::Name("Balance".into())),
+            ..Default::default()
+        });
+        let data = "Account statement\n\
+            Date;Payee;Description;Note;Amount;Balance\n\
+            01/02/2024;Coffee Co;Card payment;;-3,50;1.096,50\n\
+            01/02/2024;Coffee Co;Card payment;;-3,50;1.093,00\n\
+            02/02/2024;ACME;Salary;February;2.000,00;3.093,00\n";
+        let rows = account.parse(data).unwrap();
+        assert_eq!(rows.len(), 3);
+
+        let coffee = &rows[0].transaction;
+        assert_eq!(coffee.booking_date, NaiveDate::from_ymd_opt(2024, 2, 1).unwrap());
+        assert_eq!(coffee.transaction_amount.amount.to_string(), "-3.50");
+        assert_eq!(coffee.creditor_name.as_deref(), Some("Coffee Co"));
+        assert_eq!(coffee.debtor_name, None);
+        assert_eq!(coffee.remittance_information.as_deref(), Some("Card payment"));
+        assert_ne!(coffee.id, rows[1].transaction.id);
+        assert_eq!(coffee.id, account.parse(data).unwrap()[0].transaction.id);
+
+        let salary = &rows[2].transaction;
+        assert_eq!(salary.transaction_amount.amount.to_string(), "2000.00");
+        assert_eq!(salary.debtor_name.as_deref(), Some("ACME"));
+        assert_eq!(salary.remittance_information.as_deref(), Some("Salary February"));
+        assert_eq!(rows[2].balance.as_deref(), Some("3093.00"));
+        assert_eq!(latest_balance(&rows).unwrap().balance.as_deref(), Some("3093.00"));
+
+        // Newest first, so the first of the rows on the last day has the closing balance.
+        let data = "Account statement\n\
+            Date;Payee;Description;Note;Amount;Balance\n\
+            01/02/2024;Coffee Co;Card payment;;-3,50;1.093,00\n\
+            01/02/2024;Coffee Co;Card payment;;-3,50;1.096,50\n\
+            31/01/2024;ACME;Salary;January;2.000,00;1.100,00\n";
+        let rows = account.parse(data).unwrap();
+        assert_eq!(latest_balance(&rows).unwrap().balance.as_deref(), Some("1093.00"));
+    }
+
+    #[test]
+    fn test_normalize_amount() {
+        let account = account(Columns::default());
+        assert_eq!(account.normalize_amount("-1.234,56").unwrap(), "-1234.56");
+        assert_eq!(account.normalize_amount("1.234,56-").unwrap(), "-1234.56");
+        assert_eq!(account.normalize_amount("€ -3,50").unwrap(), "-3.50");
+        assert_eq!(account.normalize_amount("(3,50)").unwrap(), "-3.50");
+        assert_eq!(account.normalize_amount("3,50").unwrap(), "3.50");
+        assert!(account.normalize_amount("12-34").is_err());
+    }
+
+    #[test]
+    fn test_parse_debit_credit_columns() {
+        let mut account = account(Columns {
+            date: Column::Index(0),
+            debit: Some(Column::Index(2)),
+            credit: Some(Column::Index(3)),
+            description: vec![Column::Index(1)],
+            id: Some(Column::Index(4)),
+            ..Default::default()
+        });
+        account.has_headers = false;
+        account.skip_rows = 0;
+        let rows = account
+            .parse("03/02/2024;Rent;950,00;;R-1\n04/02/2024;Refund;;12,00;R-2\n05/02/2024;Interest;0,00;3,50;R-3\n")
+            .unwrap();
+        assert_eq!(rows[0].transaction.transaction_amount.amount.to_string(), "-950.00");
+        assert_eq!(rows[0].transaction.id, "R-1");
+        assert_eq!(rows[1].transaction.transaction_amount.amount.to_string(), "12.00");
+        assert_eq!(rows[2].transaction.transaction_amount.amount.to_string(), "3.50");
+        assert!(account.parse("06/02/2024;Fee;1,00;2,00;R-4\n").is_err());
+    }
+
+    #[test]
+    fn test_inverted_sign() {
+        let mut account = account(Columns {
+            date: Column::Index(0),
+            amount: Some(Column::Index(1)),
+            ..Default::default()
+        });
+        account.amount_sign = AmountSign::Inverted;
+        account.has_headers = false;
+        account.skip_rows = 0;
+        let rows = account.parse("05/02/2024;25,00\n06/02/2024;-5,00\n").unwrap();
+        assert_eq!(rows[0].transaction.transaction_amount.amount.to_string(), "-25.00");
+        assert_eq!(rows[1].transaction.transaction_amount.amount.to_string(), "5.00");
+    }
+}
@@ -1,25 +1,35 @@
-use std::fmt::Display;
-
-use crate::{utils::display_option};
+use crate::{ultrafinance::Money, utils::display_option};
 use chrono::NaiveDate;
 use cli_table::Table;
-use serde::{Deserialize, Serialize};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
 
+pub mod camt053;
+pub mod csv;
+pub mod mt940;
 pub mod nordigen;
+pub mod ofx;
 
-#[derive(Deserialize, Debug, Serialize, Clone)]
-pub struct Amount {
-    pub amount: String,
-    pub currency: String,
+/// A balance reported by the source. Types follow the Berlin Group names GoCardless uses,
+/// like `closingBooked` or `interimAvailable`.
+#[derive(Debug, Clone)]
+pub struct SourceBalance {
+    pub balance_type: String,
+    pub amount: Money,
+    pub reference_date: Option<NaiveDate>,
 }
 
-impl Display for Amount {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}{}", self.amount, self.currency)
-    }
+impl SourceBalance {
+    pub const CLOSING_BOOKED: &'static str = "closingBooked";
+    pub const CLOSING_AVAILABLE: &'static str = "closingAvailable";
+    pub const INTERIM_BOOKED: &'static str = "interimBooked";
+    pub const INTERIM_AVAILABLE: &'static str = "interimAvailable";
+    pub const OPENING_BOOKED: &'static str = "openingBooked";
+    pub const FORWARD_AVAILABLE: &'static str = "forwardAvailable";
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SourceAccountDetails {
     pub id: String,
     pub number: String,
@@ -33,16 +43,24 @@ pub struct SourceAccountDetails {
 #[derive(Debug, Serialize, Table)]
 pub struct SourceTransaction {
     pub id: String,
+    /// Not yet booked by the bank, like a card payment that hasn't settled.
+    pub pending: bool,
     #[table(display_fn = "display_option")]
     pub creditor_name: Option<String>,
     #[table(display_fn = "display_option")]
     pub debtor_name: Option<String>,
     #[table(display_fn = "display_option")]
+    pub creditor_account: Option<String>,
+    #[table(display_fn = "display_option")]
+    pub debtor_account: Option<String>,
+    #[table(display_fn = "display_option")]
     pub remittance_information: Option<String>,
     pub booking_date: chrono::NaiveDate,
     #[table(display_fn = "display_option")]
     pub booking_datetime: Option<chrono::NaiveDateTime>,
-    pub transaction_amount: Amount,
+    #[table(display_fn = "display_option")]
+    pub value_date: Option<chrono::NaiveDate>,
+    pub transaction_amount: Money,
     #[table(display_fn = "display_option")]
     pub currency_exchange_rate: Option<String>,
     #[table(display_fn = "display_option")]
@@ -53,8 +71,54 @@ pub struct SourceTransaction {
     pub currency_exchange_target_currency: Option<String>,
 }
 
+/// Ids for statement lines that don't carry a bank reference, derived from the line itself so
+/// re-importing the same statement doesn't duplicate transactions.
+#[derive(Default)]
+pub struct StatementIds {
+    seen: HashMap<String, u32>,
+}
+
+impl StatementIds {
+    pub fn generate(&mut self, booking_date: NaiveDate, amount: &str, remittance_information: Option<&str>) -> String {
+        let key = format!(
+            "{}:{}:{}",
+            booking_date.format("%Y-%m-%d"),
+            amount,
+            remittance_information.unwrap_or_default()
+        );
+        // Identical lines on the same day are told apart by their order in the statement.
+        let occurrence = self.seen.entry(key.clone()).or_insert(0);
+        let key = match *occurrence {
+            0 => key,
+            n => format!("{}:{}", key, n),
+        };
+        *occurrence += 1;
+        format!("{:x}", Sha256::digest(key.as_bytes()))
+    }
+}
+
+/// Read a statement file, or every file in a directory of statements in name order, as banks
+/// often deliver one file per day.
+pub fn read_statements(path: &str) -> Result<Vec<String>, anyhow::Error> {
+    let path = std::path::Path::new(path);
+    if !path.is_dir() {
+        return Ok(vec![std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Unable to read {}: {}", path.display(), e))?]);
+    }
+    let mut files = std::fs::read_dir(path)?
+        .map(|entry| entry.map(|e| e.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    files.retain(|f| f.is_file());
+    files.sort();
+    files
+        .iter()
+        .map(|f| std::fs::read_to_string(f).map_err(|e| anyhow::anyhow!("Unable to read {}: {}", f.display(), e)))
+        .collect()
+}
+
 pub trait SourceAccount {
-    fn balance(&self) -> impl std::future::Future<Output = Result<Amount, anyhow::Error>> + Send;
+    /// Every balance the source reports, with the one to show for the account first.
+    fn balances(&self) -> impl std::future::Future<Output = Result<Vec<SourceBalance>, anyhow::Error>> + Send;
     fn transactions(
         &self,
         date_from: &Option<NaiveDate>,
@@ -63,11 +127,60 @@ pub trait SourceAccount {
     fn details(&self) -> impl std::future::Future<Output = Result<SourceAccountDetails, anyhow::Error>> + Send;
 }
 
-pub fn get_source_account(type_: &str, config: &str) -> Option<Box<impl SourceAccount>> {
-    match type_ {
-        "nordigen" => Some(Box::new(
-            serde_json::from_str::<nordigen::Account>(config).unwrap(),
-        )),
-        _ => None,
+/// The source for an account, by `Account::account_type`. `SourceAccount` can't be used as a
+/// trait object, so this delegates to the concrete source instead.
+pub enum Source {
+    Nordigen(nordigen::Account),
+    Csv(Box<csv::Account>),
+    Ofx(ofx::Account),
+    Camt053(camt053::Account),
+    Mt940(mt940::Account),
+}
+
+impl SourceAccount for Source {
+    async fn balances(&self) -> Result<Vec<SourceBalance>, anyhow::Error> {
+        match self {
+            Source::Nordigen(account) => account.balances().await,
+            Source::Csv(account) => account.balances().await,
+            Source::Ofx(account) => account.balances().await,
+            Source::Camt053(account) => account.balances().await,
+            Source::Mt940(account) => account.balances().await,
+        }
+    }
+
+    async fn transactions(
+        &self,
+        date_from: &Option<NaiveDate>,
+        date_to: &Option<NaiveDate>,
+    ) -> Result<Vec<SourceTransaction>, anyhow::Error> {
+        match self {
+            Source::Nordigen(account) => account.transactions(date_from, date_to).await,
+            Source::Csv(account) => account.transactions(date_from, date_to).await,
+            Source::Ofx(account) => account.transactions(date_from, date_to).await,
+            Source::Camt053(account) => account.transactions(date_from, date_to).await,
+            Source::Mt940(account) => account.transactions(date_from, date_to).await,
+        }
+    }
+
+    async fn details(&self) -> Result<SourceAccountDetails, anyhow::Error> {
+        match self {
+            Source::Nordigen(account) => account.details().await,
+            Source::Csv(account) => account.details().await,
+            Source::Ofx(account) => account.details().await,
+            Source::Camt053(account) => account.details().await,
+            Source::Mt940(account) => account.details().await,
+        }
     }
 }
+
+pub fn get_source_account(type_: &str, config: &str) -> Result<Box<impl SourceAccount>, anyhow::Error> {
+    let source = match type_ {
+        "nordigen" => Source::Nordigen(serde_json::from_str(config)?),
+        "csv" => Source::Csv(serde_json::from_str(config)?),
+        "ofx" => Source::Ofx(serde_json::from_str(config)?),
+        "camt053" => Source::Camt053(serde_json::from_str(config)?),
+        "mt940" => Source::Mt940(serde_json::from_str(config)?),
+        _ => return Err(anyhow::anyhow!("No source found for account type {}", type_)),
+    };
+    Ok(Box::new(source))
+}
@@ -0,0 +1,483 @@
+use anyhow::anyhow;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::accounts::{
+    read_statements, SourceAccountDetails, SourceBalance, SourceTransaction, StatementIds,
+};
+use crate::ultrafinance::{Amount, Money};
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Account {
+    /// An MT940 file, or a directory of them.
+    pub path: String,
+    /// Account identification (`:25:`) of the statements to import, for files holding more than
+    /// one account. Matches if the identification contains it, so an IBAN matches `BIC/IBAN`.
+    pub account_id: Option<String>,
+    pub institution_name: Option<String>,
+    pub owner_name: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Statement {
+    account_id: String,
+    currency: String,
+    lines: Vec<Line>,
+    closing_balance: Option<Balance>,
+    closing_available_balance: Option<Balance>,
+}
+
+#[derive(Debug)]
+struct Balance {
+    date: NaiveDate,
+    amount: Amount,
+    currency: String,
+}
+
+/// A `:61:` statement line with the `:86:` information that follows it.
+#[derive(Debug)]
+struct Line {
+    value_date: NaiveDate,
+    booking_date: NaiveDate,
+    amount: Amount,
+    transaction_type: String,
+    bank_reference: Option<String>,
+    supplementary_details: Option<String>,
+    information: Option<Information>,
+}
+
+/// Counterparty and remittance details from a `:86:` field.
+#[derive(Debug, Default, PartialEq)]
+struct Information {
+    name: Option<String>,
+    account: Option<String>,
+    remittance: Option<String>,
+}
+
+/// Split a message into its `:tag:value` fields, joining continuation lines with `\n`.
+fn fields(data: &str) -> Vec<(String, String)> {
+    let mut fields: Vec<(String, String)> = vec![];
+    for line in data.lines().map(|l| l.trim_end()) {
+        // Skip SWIFT block headers and trailers, and message separators.
+        if line.starts_with('{') || line == "-" || line == "-}" || line.is_empty() {
+            continue;
+        }
+        let tag = line
+            .strip_prefix(':')
+            .and_then(|l| l.split_once(':'))
+            .filter(|(tag, _)| tag.len() <= 3 && tag.starts_with(|c: char| c.is_ascii_digit()));
+        match (tag, fields.last_mut()) {
+            (Some((tag, value)), _) => fields.push((tag.to_string(), value.to_string())),
+            (None, Some((_, value))) => {
+                value.push('\n');
+                value.push_str(line);
+            }
+            (None, None) => {}
+        }
+    }
+    fields
+}
+
+fn parse(data: &str) -> Result<Vec<Statement>, anyhow::Error> {
+    let mut statements: Vec<Statement> = vec![];
+    for (tag, value) in fields(data) {
+        if tag == "20" {
+            statements.push(Statement::default());
+            continue;
+        }
+        let statement = statements
+            .last_mut()
+            .ok_or(anyhow!("Not an MT940 file, field :{}: before any :20: field", tag))?;
+        match tag.as_str() {
+            "25" => statement.account_id = value.trim().to_string(),
+            "60F" | "60M" => statement.currency = parse_balance(&value)?.currency,
+            "61" => statement.lines.push(parse_line(&value)?),
+            "86" => {
+                if let Some(line) = statement.lines.last_mut() {
+                    line.information = Some(parse_information(&value));
+                }
+            }
+            // `:62M:` is an intermediate closing balance, `:62F:` follows it in the last message.
+            "62F" | "62M" => statement.closing_balance = Some(parse_balance(&value)?),
+            "64" => statement.closing_available_balance = Some(parse_balance(&value)?),
+            _ => {}
+        }
+    }
+    Ok(statements)
+}
+
+fn parse_date(s: &str) -> Result<NaiveDate, anyhow::Error> {
+    NaiveDate::parse_from_str(s, "%y%m%d").map_err(|e| anyhow!("Invalid MT940 date \"{}\": {}", s, e))
+}
+
+/// Amounts use a decimal comma and have no sign, which comes from the debit/credit mark.
+fn parse_amount(s: &str, debit: bool) -> Result<Amount, anyhow::Error> {
+    let amount = s.replace(',', ".");
+    let amount = amount
+        .strip_suffix('.')
+        .unwrap_or(&amount)
+        .parse::<Amount>()
+        .map_err(|_| anyhow!("Invalid MT940 amount \"{}\"", s))?;
+    Ok(match debit {
+        true => -amount,
+        false => amount,
+    })
+}
+
+/// A balance like `C240131EUR987,50`.
+fn parse_balance(s: &str) -> Result<Balance, anyhow::Error> {
+    let s = s.trim();
+    if s.len() < 11 || !s.is_char_boundary(10) {
+        return Err(anyhow!("Invalid MT940 balance \"{}\"", s));
+    }
+    Ok(Balance {
+        date: parse_date(&s[1..7])?,
+        currency: s[7..10].to_string(),
+        amount: parse_amount(&s[10..], s.starts_with('D'))?,
+    })
+}
+
+/// A statement line like `2401020102D12,50NTRFNONREF//B4A02\nsupplementary details`.
+fn parse_line(s: &str) -> Result<Line, anyhow::Error> {
+    let invalid = || anyhow!("Invalid MT940 statement line \"{}\"", s);
+    let (first, supplementary_details) = match s.split_once('\n') {
+        Some((first, rest)) => (first, Some(rest.replace('\n', " ")).filter(|r| !r.trim().is_empty())),
+        None => (s, None),
+    };
+    if !first.is_ascii() || first.len() < 6 {
+        return Err(invalid());
+    }
+    let value_date = parse_date(&first[..6])?;
+    let mut rest = &first[6..];
+
+    // The optional entry date has no year, so take the one closest to the value date.
+    let mut booking_date = value_date;
+    if rest.len() >= 4 && rest[..4].chars().all(|c| c.is_ascii_digit()) {
+        booking_date = [-1, 0, 1]
+            .iter()
+            .filter_map(|offset| {
+                NaiveDate::parse_from_str(
+                    &format!("{}{}", chrono::Datelike::year(&value_date) + offset, &rest[..4]),
+                    "%Y%m%d",
+                )
+                .ok()
+            })
+            .min_by_key(|date| (*date - value_date).num_days().abs())
+            .ok_or_else(invalid)?;
+        rest = &rest[4..];
+    }
+
+    // `RC` (reversal of credit) and `RD` (reversal of debit) move money the opposite way.
+    let (debit, mark_len) = match rest {
+        r if r.starts_with("RC") => (true, 2),
+        r if r.starts_with("RD") => (false, 2),
+        r if r.starts_with('D') => (true, 1),
+        r if r.starts_with('C') => (false, 1),
+        _ => return Err(invalid()),
+    };
+    rest = &rest[mark_len..];
+    // Skip the funds code, the third letter of the currency.
+    if rest.starts_with(|c: char| c.is_ascii_alphabetic()) {
+        rest = &rest[1..];
+    }
+    let amount_len = rest
+        .find(|c: char| !c.is_ascii_digit() && c != ',')
+        .unwrap_or(rest.len());
+    let amount = parse_amount(&rest[..amount_len], debit)?;
+    rest = &rest[amount_len..];
+    if rest.len() < 4 {
+        return Err(invalid());
+    }
+    let transaction_type = rest[..4].to_string();
+    let bank_reference = rest[4..]
+        .split_once("//")
+        .map(|(_, bank_reference)| bank_reference.trim().to_string())
+        .filter(|r| !r.is_empty() && r != "NONREF");
+
+    Ok(Line {
+        value_date,
+        booking_date,
+        amount,
+        transaction_type,
+        bank_reference,
+        supplementary_details,
+        information: None,
+    })
+}
+
+/// Banks put structured data in `:86:` in one of two common layouts: German `?nn` subfields,
+/// or `/KEY/value` pairs. Anything else is taken as free text.
+fn parse_information(s: &str) -> Information {
+    let non_empty = |s: String| Some(s.trim().to_string()).filter(|s| !s.is_empty());
+
+    if s.get(..3).is_some_and(|code| code.chars().all(|c| c.is_ascii_digit())) && s[3..].starts_with('?') {
+        let joined = s.replace('\n', "");
+        let subfields = joined[4..]
+            .split('?')
+            .filter(|f| f.len() >= 2 && f.is_char_boundary(2))
+            .map(|f| (&f[..2], &f[2..]))
+            .collect::<Vec<_>>();
+        let collect = |codes: &[&str]| {
+            subfields
+                .iter()
+                .filter(|(code, _)| codes.contains(code))
+                .map(|(_, value)| *value)
+                .collect::<String>()
+        };
+        let remittance = collect(&[
+            "20", "21", "22", "23", "24", "25", "26", "27", "28", "29", "60", "61", "62", "63",
+        ]);
+        // SEPA payments tag their purpose with `SVWZ+`, the rest is references.
+        let remittance = sepa_field(&remittance, "SVWZ+").unwrap_or(remittance);
+        return Information {
+            name: non_empty(collect(&["32", "33"])),
+            account: non_empty(collect(&["31"])),
+            remittance: non_empty(remittance),
+        };
+    }
+
+    const KEYS: [&str; 16] = [
+        "TRTP", "CNTP", "NAME", "IBAN", "BIC", "REMI", "EREF", "MARF", "CSID", "ORDP", "BENM",
+        "ADDR", "PURP", "ULTC", "ULTD", "RTRN",
+    ];
+    if s.starts_with('/') && KEYS.iter().any(|k| s.starts_with(&format!("/{}/", k))) {
+        let joined = s.replace('\n', "");
+        let mut values: Vec<(&str, Vec<&str>)> = vec![];
+        for token in joined.split('/').skip(1) {
+            match values.last_mut() {
+                Some((_, value)) if !KEYS.contains(&token) => value.push(token),
+                _ if KEYS.contains(&token) => values.push((token, vec![])),
+                _ => {}
+            }
+        }
+        let value = |key: &str| values.iter().find(|(k, _)| *k == key).map(|(_, v)| v.clone());
+        let cntp = value("CNTP").unwrap_or_default();
+        let remittance = value("REMI").map(|remi| {
+            remi.into_iter()
+                .filter(|t| !["USTD", "STRD", "CUR", "ISO"].contains(t))
+                .filter(|t| !t.is_empty())
+                .collect::<Vec<_>>()
+                .join(" ")
+        });
+        return Information {
+            name: value("NAME")
+                .map(|v| v.join("/"))
+                .or(cntp.get(2).map(|n| n.to_string()))
+                .and_then(non_empty),
+            account: value("IBAN")
+                .map(|v| v.join("/"))
+                .or(cntp.first().map(|a| a.to_string()))
+                .and_then(non_empty),
+            remittance: remittance.and_then(non_empty),
+        };
+    }
+
+    Information {
+        remittance: non_empty(s.lines().map(str::trim).collect::<Vec<_>>().join(" ")),
+        ..Default::default()
+    }
+}
+
+/// The value of a SEPA key like `SVWZ+` up to the next key.
+fn sepa_field(s: &str, key: &str) -> Option<String> {
+    const KEYS: [&str; 10] = [
+        "EREF+", "KREF+", "MREF+", "CRED+", "DEBT+", "SVWZ+", "ABWA+", "ABWE+", "IBAN+", "BIC+",
+    ];
+    let start = s.find(key)? + key.len();
+    let rest = &s[start..];
+    let end = KEYS.iter().filter_map(|k| rest.find(k)).min().unwrap_or(rest.len());
+    Some(rest[..end].trim().to_string())
+}
+
+impl Account {
+    fn statements(&self) -> Result<Vec<Statement>, anyhow::Error> {
+        let mut statements = vec![];
+        for data in read_statements(&self.path)? {
+            statements.extend(parse(&data)?);
+        }
+        let account_id = match &self.account_id {
+            Some(account_id) => account_id.clone(),
+            None => statements
+                .first()
+                .map(|s| s.account_id.clone())
+                .ok_or(anyhow!("No statements found in {}", self.path))?,
+        };
+        statements.retain(|s| s.account_id.contains(&account_id));
+        if statements.is_empty() {
+            return Err(anyhow!("No statements for account {} found in {}", account_id, self.path));
+        }
+        Ok(statements)
+    }
+
+    fn transactions_from(&self, statements: Vec<Statement>) -> Result<Vec<SourceTransaction>, anyhow::Error> {
+        let mut ids = StatementIds::default();
+        statements
+            .into_iter()
+            .flat_map(|statement| {
+                let currency = statement.currency;
+                statement.lines.into_iter().map(move |line| (currency.clone(), line))
+            })
+            .map(|(currency, line)| {
+                let information = line.information.unwrap_or_default();
+                let remittance_information = information.remittance.or(line.supplementary_details);
+                let is_outgoing = line.amount.is_negative();
+                let id = line.bank_reference.unwrap_or_else(|| {
+                    ids.generate(line.booking_date, &line.amount.to_string(), remittance_information.as_deref())
+                });
+                Ok(SourceTransaction {
+                    id,
+                    pending: false,
+                    creditor_name: information.name.clone().filter(|_| is_outgoing),
+                    debtor_name: information.name.filter(|_| !is_outgoing),
+                    creditor_account: information.account.clone().filter(|_| is_outgoing),
+                    debtor_account: information.account.filter(|_| !is_outgoing),
+                    remittance_information,
+                    booking_date: line.booking_date,
+                    booking_datetime: None,
+                    value_date: Some(line.value_date),
+                    transaction_amount: Money::new(line.amount, currency.parse()?),
+                    currency_exchange_rate: None,
+                    proprietary_bank_transaction_code: Some(line.transaction_type),
+                    currency_exchange_source_currency: None,
+                    currency_exchange_target_currency: None,
+                })
+            })
+            .collect()
+    }
+}
+
+impl crate::accounts::SourceAccount for Account {
+    async fn details(&self) -> Result<SourceAccountDetails, anyhow::Error> {
+        let statements = self.statements()?;
+        let statement = statements.first().unwrap();
+        // `:25:` is often `BIC/account`.
+        let number = statement
+            .account_id
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+            .to_string();
+        Ok(SourceAccountDetails {
+            id: statement.account_id.clone(),
+            number,
+            currency: statement.currency.clone(),
+            details: format!("MT940 statement {}", self.path),
+            owner_name: self.owner_name.clone(),
+            icon: None,
+            institution_name: self.institution_name.clone().unwrap_or("MT940".into()),
+        })
+    }
+
+    /// The closing balance of the most recent statement.
+    async fn balances(&self) -> Result<Vec<SourceBalance>, anyhow::Error> {
+        let statements = self.statements()?;
+        let latest = |balances: Vec<Option<Balance>>, balance_type: &str| {
+            balances
+                .into_iter()
+                .flatten()
+                .max_by_key(|b| b.date)
+                .map(|b| {
+                    Ok::<_, anyhow::Error>(SourceBalance {
+                        balance_type: balance_type.to_string(),
+                        amount: Money::new(b.amount, b.currency.parse()?),
+                        reference_date: Some(b.date),
+                    })
+                })
+                .transpose()
+        };
+        let (closing, available): (Vec<_>, Vec<_>) = statements
+            .into_iter()
+            .map(|s| (s.closing_balance, s.closing_available_balance))
+            .unzip();
+        let closing = latest(closing, SourceBalance::CLOSING_BOOKED)?
+            .ok_or(anyhow!("No closing balance found in {}", self.path))?;
+        Ok(std::iter::once(closing)
+            .chain(latest(available, SourceBalance::CLOSING_AVAILABLE)?)
+            .collect())
+    }
+
+    async fn transactions(
+        &self,
+        date_from: &Option<NaiveDate>,
+        date_to: &Option<NaiveDate>,
+    ) -> Result<Vec<SourceTransaction>, anyhow::Error> {
+        Ok(self
+            .transactions_from(self.statements()?)?
+            .into_iter()
+            .filter(|t| date_from.is_none_or(|from| t.booking_date >= from))
+            .filter(|t| date_to.is_none_or(|to| t.booking_date <= to))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATEMENT: &str = "{1:F01ABNANL2AXXXX0000000000}{2:O940ABNANL2AXXXXN}{4:
+:20:ABN AMRO BANK NV
+:25:ABNANL2A/NL91ABNA0417164300
+:28C:12/1
+:60F:C231229EUR1000,00
+:61:2401020102D12,50NTRFNONREF//B4A02
+:86:/TRTP/SEPA OVERBOEKING/IBAN/NL12RABO0123456789/BIC/RABONL2U/NAME/
+COFFEE CO/REMI/USTD//Invoice 123/EREF/NOTPROVIDED
+:61:2312310101C1500,NTRFNONREF
+:86:166?00GUTSCHRIFT?20EREF+E2E-1?21SVWZ+Salary Decem?22ber?31DE89370400440532013000?32ACME
+?33 GMBH
+:61:240103D2,00NCHGNONREF
+:86:Account fee
+:62F:C240103EUR2485,50
+-}";
+
+    fn account() -> Account {
+        Account {
+            path: "statement.sta".into(),
+            account_id: None,
+            institution_name: None,
+            owner_name: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_statement() {
+        let statements = parse(STATEMENT).unwrap();
+        assert_eq!(statements.len(), 1);
+        assert_eq!(statements[0].account_id, "ABNANL2A/NL91ABNA0417164300");
+        assert_eq!(statements[0].closing_balance.as_ref().unwrap().amount.to_string(), "2485.50");
+
+        let transactions = account().transactions_from(statements).unwrap();
+        assert_eq!(transactions.len(), 3);
+
+        let coffee = &transactions[0];
+        assert_eq!(coffee.id, "B4A02");
+        assert_eq!(coffee.transaction_amount.amount.to_string(), "-12.50");
+        assert_eq!(coffee.transaction_amount.currency.to_string(), "EUR");
+        assert_eq!(coffee.creditor_name.as_deref(), Some("COFFEE CO"));
+        assert_eq!(coffee.creditor_account.as_deref(), Some("NL12RABO0123456789"));
+        assert_eq!(coffee.remittance_information.as_deref(), Some("Invoice 123"));
+        assert_eq!(coffee.proprietary_bank_transaction_code.as_deref(), Some("NTRF"));
+
+        // The entry date is in the year before the value date.
+        let salary = &transactions[1];
+        assert_eq!(salary.booking_date, NaiveDate::from_ymd_opt(2024, 1, 1).unwrap());
+        assert_eq!(salary.value_date, NaiveDate::from_ymd_opt(2023, 12, 31));
+        assert_eq!(salary.transaction_amount.amount.to_string(), "1500");
+        assert_eq!(salary.debtor_name.as_deref(), Some("ACME GMBH"));
+        assert_eq!(salary.debtor_account.as_deref(), Some("DE89370400440532013000"));
+        assert_eq!(salary.remittance_information.as_deref(), Some("Salary December"));
+
+        let fee = &transactions[2];
+        assert_eq!(fee.remittance_information.as_deref(), Some("Account fee"));
+        assert_eq!(fee.id.len(), 64);
+    }
+
+    #[test]
+    fn test_parse_line() {
+        let line = parse_line("240105RD3,20NTRF123//REF1").unwrap();
+        assert_eq!(line.amount.to_string(), "3.20");
+        assert_eq!(line.booking_date, NaiveDate::from_ymd_opt(2024, 1, 5).unwrap());
+        assert_eq!(line.bank_reference.as_deref(), Some("REF1"));
+        assert!(parse_line("240105X3,20NTRF").is_err());
+    }
+}
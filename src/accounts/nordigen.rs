@@ -1,5 +1,6 @@
 use crate::{
-    accounts::{Amount, SourceTransaction},
+    accounts::SourceTransaction,
+    ultrafinance::Money,
     utils::display_option,
 };
 use anyhow::anyhow;
@@ -7,7 +8,12 @@ use chrono::{naive::NaiveDate, DateTime, Utc};
 use cli_table::Table;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
-use std::{collections::HashMap, env};
+use std::{
+    collections::HashMap,
+    env,
+    path::{Path, PathBuf},
+    sync::{Arc, OnceLock},
+};
 
 #[derive(Deserialize, Table, Serialize)]
 
@@ -15,9 +21,12 @@ pub struct Institution {
     pub id: String,
     pub name: String,
     pub bic: String,
-    #[table(skip)]
-    #[allow(dead_code)]
+    #[table(title = "History Days")]
     pub transaction_total_days: String,
+    /// Not returned for every institution, in which case the API's limit applies.
+    #[table(title = "Access Days", display_fn = "display_option")]
+    #[serde(default)]
+    pub max_access_valid_for_days: Option<String>,
     #[table(skip)]
     #[allow(dead_code)]
     pub countries: Vec<String>,
@@ -31,19 +40,112 @@ pub struct Institution {
 pub struct Requisition {
     pub id: String,
     pub status: String,
+    pub institution_id: String,
     pub redirect: String,
     #[table(skip)]
     pub accounts: Vec<String>,
     pub link: String,
+    #[table(skip)]
+    #[serde(default)]
+    pub agreement: Option<String>,
+    #[table(skip)]
+    #[serde(default)]
+    pub created: Option<DateTime<Utc>>,
+}
+
+/// An end user agreement sets how much history can be fetched and for how long access lasts.
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct EndUserAgreement {
+    pub id: String,
+    pub institution_id: String,
+    pub max_historical_days: u32,
+    pub access_valid_for_days: u32,
+    pub access_scope: Vec<String>,
+    /// When the end user accepted the agreement, `None` until they have.
+    #[serde(default)]
+    pub accepted: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AgreementTerms {
+    pub max_historical_days: u32,
+    pub access_valid_for_days: u32,
+    pub access_scope: Vec<String>,
+}
+
+impl Default for AgreementTerms {
+    fn default() -> Self {
+        Self {
+            max_historical_days: DEFAULT_MAX_HISTORICAL_DAYS,
+            access_valid_for_days: DEFAULT_ACCESS_VALID_FOR_DAYS,
+            access_scope: ACCESS_SCOPES.iter().map(|s| s.to_string()).collect(),
+        }
+    }
+}
+
+impl From<&EndUserAgreement> for AgreementTerms {
+    fn from(agreement: &EndUserAgreement) -> Self {
+        Self {
+            max_historical_days: agreement.max_historical_days,
+            access_valid_for_days: agreement.access_valid_for_days,
+            access_scope: agreement.access_scope.clone(),
+        }
+    }
+}
+
+impl AgreementTerms {
+    /// Check the terms are within what the institution allows, as the API rejects the agreement otherwise.
+    pub fn validate(&self, institution: &Institution) -> anyhow::Result<()> {
+        let total_days: u32 = institution.transaction_total_days.parse()?;
+        if self.max_historical_days == 0 || self.max_historical_days > total_days {
+            return Err(anyhow!(
+                "max_historical_days must be between 1 and {} for {}",
+                total_days,
+                institution.name
+            ));
+        }
+
+        let max_access_days = match &institution.max_access_valid_for_days {
+            Some(days) => days.parse()?,
+            None => MAX_ACCESS_VALID_FOR_DAYS,
+        };
+        if self.access_valid_for_days == 0 || self.access_valid_for_days > max_access_days {
+            return Err(anyhow!(
+                "access_valid_for_days must be between 1 and {} for {}",
+                max_access_days,
+                institution.name
+            ));
+        }
+
+        if self.access_scope.is_empty() {
+            return Err(anyhow!("access_scope can't be empty"));
+        }
+        if let Some(scope) = self.access_scope.iter().find(|s| !ACCESS_SCOPES.contains(&s.as_str())) {
+            return Err(anyhow!(
+                "Invalid access scope \"{}\", expected one of {}",
+                scope,
+                ACCESS_SCOPES.join(", ")
+            ));
+        }
+        Ok(())
+    }
 }
 
 pub struct Nordigen {
-    key: String,
-    secret: String,
-    token: Option<AccessToken>,
+    tokens: Arc<TokenManager>,
+    rate_limits: Arc<RateLimits>,
     base_url: String,
 }
 
+const BASE_URL: &str = "https://ob.gocardless.com/api/v2";
+/// Defaults for end user agreements, matching what GoCardless uses when no agreement is given.
+pub const DEFAULT_ACCESS_VALID_FOR_DAYS: u32 = 90;
+pub const DEFAULT_MAX_HISTORICAL_DAYS: u32 = 90;
+const MAX_ACCESS_VALID_FOR_DAYS: u32 = 180;
+pub const ACCESS_SCOPES: [&str; 3] = ["balances", "details", "transactions"];
+/// Tokens are renewed this long before they expire, so they don't expire mid-request.
+const TOKEN_EXPIRY_MARGIN_SECONDS: i64 = 60;
+
 #[derive(Deserialize)]
 struct ErrorResponse {
     summary: Option<String>,
@@ -52,7 +154,6 @@ struct ErrorResponse {
 }
 
 #[derive(Deserialize, Clone, Debug)]
-#[allow(dead_code)]
 pub struct AccessToken {
     access: String,
     access_expires: i64,
@@ -60,6 +161,322 @@ pub struct AccessToken {
     refresh_expires: i64,
 }
 
+#[derive(Deserialize)]
+struct RefreshedToken {
+    access: String,
+    access_expires: i64,
+}
+
+/// A token with its expiry times as timestamps, as stored in the token file.
+#[derive(Deserialize, Serialize, Clone, Debug)]
+struct StoredToken {
+    /// The secret id the token was created with, so changing credentials discards it.
+    secret_id: String,
+    access: String,
+    access_expires_at: i64,
+    refresh: String,
+    refresh_expires_at: i64,
+}
+
+/// Shares one access token between all Nordigen clients, as GoCardless limits how many tokens
+/// can be created. The token is refreshed before it expires and stored in `NORDIGEN_TOKEN_PATH`
+/// (`.nordigen_token.json` by default) so it can be reused by later runs.
+pub struct TokenManager {
+    key: String,
+    secret: String,
+    base_url: String,
+    path: Option<PathBuf>,
+    token: tokio::sync::Mutex<Option<StoredToken>>,
+}
+
+static TOKEN_MANAGER: OnceLock<Arc<TokenManager>> = OnceLock::new();
+
+impl TokenManager {
+    pub fn new(key: String, secret: String, base_url: String, path: Option<PathBuf>) -> Self {
+        Self {
+            key,
+            secret,
+            base_url,
+            path,
+            token: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// The token manager configured from the environment, shared by the whole process.
+    pub fn shared() -> Arc<TokenManager> {
+        TOKEN_MANAGER
+            .get_or_init(|| {
+                Arc::new(TokenManager::new(
+                    env::var("NORDIGEN_SECRET_ID").unwrap(),
+                    env::var("NORDIGEN_SECRET_KEY").unwrap(),
+                    BASE_URL.into(),
+                    Some(env::var("NORDIGEN_TOKEN_PATH").unwrap_or(".nordigen_token.json".into()).into()),
+                ))
+            })
+            .clone()
+    }
+
+    /// A valid access token, from the cache if possible, then by refreshing, then a new one.
+    pub async fn access_token(&self) -> anyhow::Result<String> {
+        let mut token = self.token.lock().await;
+        if token.is_none() {
+            *token = self.load();
+        }
+        let now = Utc::now().timestamp() + TOKEN_EXPIRY_MARGIN_SECONDS;
+        let current = match token.take() {
+            Some(t) if t.access_expires_at > now => t,
+            Some(t) if t.refresh_expires_at > now => match self.refresh(&t).await {
+                Ok(refreshed) => self.store(refreshed),
+                Err(e) => {
+                    log::warn!("Failed to refresh Nordigen token, creating a new one: {}", e);
+                    self.store(self.create().await?)
+                }
+            },
+            _ => self.store(self.create().await?),
+        };
+        let access = current.access.clone();
+        *token = Some(current);
+        Ok(access)
+    }
+
+    /// Forget the access token, if it's still the current one, for when it was rejected.
+    pub async fn invalidate(&self, access: &str) {
+        let mut token = self.token.lock().await;
+        if let Some(t) = token.as_mut().filter(|t| t.access == access) {
+            t.access_expires_at = 0;
+        }
+    }
+
+    async fn create(&self) -> anyhow::Result<StoredToken> {
+        let mut args: HashMap<String, String> = HashMap::new();
+        args.insert("secret_id".into(), self.key.clone());
+        args.insert("secret_key".into(), self.secret.clone());
+        let response = reqwest::Client::new()
+            .post(format!("{}/token/new/", self.base_url))
+            .json(&args)
+            .send()
+            .await?;
+        let token = check_response(response).await?.json::<AccessToken>().await?;
+        let now = Utc::now().timestamp();
+        Ok(StoredToken {
+            secret_id: self.key.clone(),
+            access: token.access,
+            access_expires_at: now + token.access_expires,
+            refresh: token.refresh,
+            refresh_expires_at: now + token.refresh_expires,
+        })
+    }
+
+    async fn refresh(&self, token: &StoredToken) -> anyhow::Result<StoredToken> {
+        let mut args: HashMap<String, String> = HashMap::new();
+        args.insert("refresh".into(), token.refresh.clone());
+        let response = reqwest::Client::new()
+            .post(format!("{}/token/refresh/", self.base_url))
+            .json(&args)
+            .send()
+            .await?;
+        let refreshed = check_response(response).await?.json::<RefreshedToken>().await?;
+        Ok(StoredToken {
+            access: refreshed.access,
+            access_expires_at: Utc::now().timestamp() + refreshed.access_expires,
+            ..token.clone()
+        })
+    }
+
+    fn load(&self) -> Option<StoredToken> {
+        let data = std::fs::read_to_string(self.path.as_ref()?).ok()?;
+        serde_json::from_str::<StoredToken>(&data)
+            .ok()
+            .filter(|t| t.secret_id == self.key)
+    }
+
+    /// Save the token for later runs. Failing to save only costs a new token next time.
+    fn store(&self, token: StoredToken) -> StoredToken {
+        if let Some(path) = &self.path {
+            let written = serde_json::to_string(&token)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| write_private(path, &data));
+            if let Err(e) = written {
+                log::warn!("Failed to store Nordigen token in {}: {}", path.display(), e);
+            }
+        }
+        token
+    }
+}
+
+/// Returned when GoCardless has no quota left for a call, or would have none left. GoCardless
+/// only allows a few calls a day for each account and endpoint on many banks.
+#[derive(Debug)]
+pub struct RateLimited {
+    pub until: DateTime<Utc>,
+    pub path: String,
+}
+
+impl std::fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Rate limited until {} for {}", self.until.format("%Y-%m-%d %H:%M:%S UTC"), self.path)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Remaining quota from GoCardless' rate limit headers.
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq)]
+pub struct Quota {
+    pub limit: Option<u32>,
+    pub remaining: u32,
+    pub reset_at: i64,
+}
+
+impl Quota {
+    /// Read the account quota headers, or the general ones for endpoints without an account
+    /// quota. Headers are documented as `HTTP_X_RATELIMIT_...` but sent in several spellings.
+    fn from_headers(headers: &reqwest::header::HeaderMap, now: i64) -> Option<Quota> {
+        let values = headers
+            .iter()
+            .filter_map(|(name, value)| {
+                let name = name.as_str().to_lowercase().replace('-', "_");
+                let name = name.strip_prefix("http_").unwrap_or(&name).to_string();
+                Some((name, value.to_str().ok()?.trim().parse::<i64>().ok()?))
+            })
+            .collect::<HashMap<_, _>>();
+        ["x_ratelimit_account_success_", "x_ratelimit_"].iter().find_map(|prefix| {
+            let remaining = values.get(&format!("{}remaining", prefix))?;
+            Some(Quota {
+                limit: values.get(&format!("{}limit", prefix)).map(|l| *l as u32),
+                remaining: (*remaining).max(0) as u32,
+                reset_at: now + values.get(&format!("{}reset", prefix)).copied().unwrap_or(0),
+            })
+        })
+    }
+}
+
+/// Remaining quota for each account and endpoint, stored in `NORDIGEN_RATE_LIMITS_PATH`
+/// (`.nordigen_rate_limits.json` by default) so separate runs don't use up the quota. The file
+/// is re-read on every check and update, as other processes (the daemon, one-off syncs) share it.
+pub struct RateLimits {
+    path: Option<PathBuf>,
+    quotas: std::sync::Mutex<HashMap<String, Quota>>,
+}
+
+static RATE_LIMITS: OnceLock<Arc<RateLimits>> = OnceLock::new();
+
+impl RateLimits {
+    pub fn new(path: Option<PathBuf>) -> Self {
+        Self {
+            path,
+            quotas: std::sync::Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn shared() -> Arc<RateLimits> {
+        RATE_LIMITS
+            .get_or_init(|| {
+                Arc::new(RateLimits::new(Some(
+                    env::var("NORDIGEN_RATE_LIMITS_PATH")
+                        .unwrap_or(".nordigen_rate_limits.json".into())
+                        .into(),
+                )))
+            })
+            .clone()
+    }
+
+    /// Quotas are per account and endpoint: `/accounts/{id}/transactions/` is keyed as
+    /// `{id}/transactions`. Other paths only have the general limit.
+    fn key(path: &str) -> String {
+        match path.trim_matches('/').split('/').collect::<Vec<_>>().as_slice() {
+            ["accounts", id, endpoint, ..] => format!("{}/{}", id, endpoint),
+            _ => "general".into(),
+        }
+    }
+
+    /// Merge the stored quotas into ours, keeping whichever of each is the more recent.
+    fn with_quotas<T>(&self, f: impl FnOnce(&mut HashMap<String, Quota>) -> T) -> T {
+        let mut quotas = self.quotas.lock().unwrap();
+        let stored: HashMap<String, Quota> = self
+            .path
+            .as_ref()
+            .and_then(|path| std::fs::read_to_string(path).ok())
+            .and_then(|data| serde_json::from_str(&data).ok())
+            .unwrap_or_default();
+        // A later reset, or less remaining before the same reset, is the more recent response.
+        let recency = |q: &Quota| (q.reset_at, std::cmp::Reverse(q.remaining));
+        for (key, quota) in stored {
+            if quotas.get(&key).is_none_or(|q| recency(&quota) > recency(q)) {
+                quotas.insert(key, quota);
+            }
+        }
+        f(&mut quotas)
+    }
+
+    pub fn quota(&self, path: &str) -> Option<Quota> {
+        self.with_quotas(|quotas| quotas.get(&Self::key(path)).cloned())
+    }
+
+    /// Fail without calling GoCardless when the quota for the path is used up.
+    pub fn check(&self, path: &str) -> Result<(), RateLimited> {
+        let now = Utc::now().timestamp();
+        match self.quota(path) {
+            Some(quota) if quota.remaining == 0 && quota.reset_at > now => Err(RateLimited {
+                until: DateTime::from_timestamp(quota.reset_at, 0).unwrap_or_default(),
+                path: path.to_string(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn update(&self, path: &str, quota: Quota) {
+        // Write while still holding the lock, so a concurrent update can't write an older map.
+        self.with_quotas(|quotas| {
+            let now = Utc::now().timestamp();
+            quotas.retain(|_, q| q.reset_at > now);
+            quotas.insert(Self::key(path), quota);
+            let Some(path) = &self.path else {
+                return;
+            };
+            let written = serde_json::to_string(quotas)
+                .map_err(anyhow::Error::from)
+                .and_then(|data| write_private(path, &data));
+            if let Err(e) = written {
+                log::warn!("Failed to store Nordigen rate limits in {}: {}", path.display(), e);
+            }
+        })
+    }
+}
+
+/// Write a file only the current user can read, as it may hold credentials. The data goes to a
+/// temporary file that replaces the old one, so concurrent readers never see a partial write.
+fn write_private(path: &Path, data: &str) -> anyhow::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", uuid::Uuid::new_v4()));
+    let temp = PathBuf::from(temp);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options
+        .open(&temp)
+        .and_then(|mut file| std::io::Write::write_all(&mut file, data.as_bytes()))
+        .and_then(|_| std::fs::rename(&temp, path));
+    if written.is_err() {
+        let _ = std::fs::remove_file(&temp);
+    }
+    Ok(written?)
+}
+
+async fn check_response(response: reqwest::Response) -> anyhow::Result<reqwest::Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+    let error = response.json::<ErrorResponse>().await?;
+    Err(anyhow::Error::msg(format!(
+        "{}. {} {}",
+        error.summary.unwrap_or("No summary".into()),
+        error.detail.unwrap_or("".into()),
+        error.status_code.unwrap_or(0)
+    )))
+}
+
 #[derive(Deserialize, Debug, Serialize)]
 #[serde(untagged)]
 pub enum BankAccount {
@@ -123,7 +540,7 @@ pub struct Transaction {
     #[table(title = "Creditor Account", display_fn = "display_option")]
     pub creditorAccount: Option<BankAccount>,
     #[table(title = "Amount", display_fn = "display_option")]
-    pub transactionAmount: Option<Amount>,
+    pub transactionAmount: Option<Money>,
     #[table(title = "Transaction Code", display_fn = "display_option")]
     pub bankTransactionCode: Option<String>,
     #[table(title = "Date", display_fn = "display_option")]
@@ -136,6 +553,10 @@ pub struct Transaction {
     pub remittanceInformationUnstructured: Option<String>,
     #[table(skip)]
     pub proprietaryBankTransactionCode: Option<String>,
+    /// Whether the transaction came from the `pending` list rather than `booked`.
+    #[serde(skip)]
+    #[table(title = "Pending")]
+    pub pending: bool,
     #[table(title = "Creditor Name", display_fn = "display_option")]
     pub creditorName: Option<String>,
     #[table(title = "Exchange", display_fn = "display_option")]
@@ -175,7 +596,7 @@ struct AccountResponse {
 #[derive(Deserialize, Debug, Clone)]
 #[allow(non_snake_case)]
 pub struct Balance {
-    pub balanceAmount: Amount,
+    pub balanceAmount: Money,
     pub balanceType: String,
     pub referenceDate: Option<chrono::NaiveDate>,
 }
@@ -188,6 +609,8 @@ struct BalancesResponse {
 #[derive(Deserialize)]
 struct Transactions {
     booked: Vec<Transaction>,
+    #[serde(default)]
+    pending: Vec<Transaction>,
 }
 
 #[derive(Deserialize)]
@@ -197,11 +620,14 @@ struct TransactionsResponse {
 
 impl Nordigen {
     pub fn new() -> Self {
+        Self::with_token_manager(TokenManager::shared(), RateLimits::shared())
+    }
+
+    pub fn with_token_manager(tokens: Arc<TokenManager>, rate_limits: Arc<RateLimits>) -> Self {
         Self {
-            key: env::var("NORDIGEN_SECRET_ID").unwrap().into(),
-            secret: env::var("NORDIGEN_SECRET_KEY").unwrap().into(),
-            token: None,
-            base_url: "https://ob.gocardless.com/api/v2".into(),
+            base_url: tokens.base_url.clone(),
+            tokens,
+            rate_limits,
         }
     }
 
@@ -214,7 +640,7 @@ impl Nordigen {
             args.insert("country".into(), country.as_ref().unwrap().clone());
         }
         let response = self
-            .request(reqwest::Method::GET, "/institutions/", Some(&args))
+            .request(reqwest::Method::GET, "/institutions/", Some(&serde_json::to_value(args)?))
             .await?;
         response
             .json::<Vec<Institution>>()
@@ -303,17 +729,24 @@ impl Nordigen {
             .request(
                 reqwest::Method::GET,
                 format!("/accounts/{}/transactions/", id).as_str(),
-                Some(&args),
+                Some(&serde_json::to_value(args)?),
             )
             .await?
             .text()
             .await?;
 
         let transactions: TransactionsResponse = serde_json::from_str(transactions.as_str())?;
+        let pending = transactions.transactions.pending.into_iter().map(|mut transaction| {
+            // Pending transactions often only have a value date.
+            transaction.bookingDate = transaction.bookingDate.or(transaction.valueDate);
+            transaction.pending = true;
+            transaction
+        });
         let transactions = transactions
             .transactions
             .booked
             .into_iter()
+            .chain(pending)
             .map(|mut transaction| {
                 if transaction.transactionId.is_none() {
                     let mut hasher = Sha256::new();
@@ -339,10 +772,12 @@ impl Nordigen {
                             hash_string = format!(
                                 "{}:{}:{}",
                                 date,
-                                transaction.transactionAmount.as_ref().unwrap_or(&Amount {
-                                    amount: "0".into(),
-                                    currency: "EUR".into()
-                                }),
+                                // Formatted the way ids have always been generated.
+                                transaction
+                                    .transactionAmount
+                                    .as_ref()
+                                    .map(|a| format!("{}{}", a.amount, a.currency))
+                                    .unwrap_or("0EUR".into()),
                                 transaction
                                     .remittanceInformationUnstructured
                                     .as_ref()
@@ -351,6 +786,11 @@ impl Nordigen {
                         }
                     }
 
+                    // Keep pending ids apart from booked ones, pending transactions are matched
+                    // to their booked counterpart on import instead.
+                    if transaction.pending {
+                        hash_string = format!("pending:{}", hash_string);
+                    }
                     hasher.update(hash_string);
                     let hash = hasher.finalize();
                     let hash = format!("{:x}", hash);
@@ -362,17 +802,70 @@ impl Nordigen {
         Ok(transactions)
     }
 
+    pub async fn create_agreement(
+        &self,
+        institution_id: &String,
+        terms: &AgreementTerms,
+    ) -> anyhow::Result<EndUserAgreement> {
+        let args = serde_json::json!({
+            "institution_id": institution_id,
+            "max_historical_days": terms.max_historical_days,
+            "access_valid_for_days": terms.access_valid_for_days,
+            "access_scope": terms.access_scope,
+        });
+
+        self.request(reqwest::Method::POST, "/agreements/enduser/", Some(&args))
+            .await?
+            .json::<EndUserAgreement>()
+            .await
+            .map_err(anyhow::Error::msg)
+    }
+
+    pub async fn get_agreement(&self, id: &String) -> anyhow::Result<EndUserAgreement> {
+        self.request(
+            reqwest::Method::GET,
+            format!("/agreements/enduser/{}/", id).as_str(),
+            None,
+        )
+        .await?
+        .json::<EndUserAgreement>()
+        .await
+        .map_err(anyhow::Error::msg)
+    }
+
+    /// When the end user granted access: the agreement's acceptance, or when the requisition
+    /// was created if that isn't known.
+    pub async fn access_granted_at(&self, requisition: &Requisition) -> Option<chrono::NaiveDateTime> {
+        let accepted = match &requisition.agreement {
+            Some(agreement) => match self.get_agreement(agreement).await {
+                Ok(agreement) => agreement.accepted,
+                Err(err) => {
+                    log::error!("Failed to get agreement {}: {:?}", agreement, err);
+                    None
+                }
+            },
+            None => None,
+        };
+        accepted
+            .or(requisition.created)
+            .map(|granted_at| granted_at.with_timezone(&chrono::Local).naive_local())
+    }
+
     pub async fn create_requisition(
         &self,
         redirect: &String,
         institution_id: &String,
+        agreement_id: Option<&String>,
     ) -> anyhow::Result<Requisition> {
         let mut args: HashMap<String, String> = HashMap::new();
         args.insert("redirect".into(), redirect.clone());
         args.insert("institution_id".into(), institution_id.clone());
+        if let Some(agreement_id) = agreement_id {
+            args.insert("agreement".into(), agreement_id.clone());
+        }
         // args.insert("account_selection".into(), "true".into());
 
-        self.request(reqwest::Method::POST, "/requisitions/", Some(&args))
+        self.request(reqwest::Method::POST, "/requisitions/", Some(&serde_json::to_value(args)?))
             .await?
             .json::<Requisition>()
             .await
@@ -395,61 +888,53 @@ impl Nordigen {
         &self,
         method: reqwest::Method,
         path: &str,
-        args: Option<&HashMap<String, String>>,
+        args: Option<&serde_json::Value>,
     ) -> anyhow::Result<reqwest::Response> {
-        let request = reqwest::Client::new();
-        let mut request = match method {
-            reqwest::Method::GET => {
-                let mut request = request.get(format!("{}{}", self.base_url, path));
-                if args.is_some() {
-                    request = request.query(&args);
+        let build = |token: &str| {
+            let request = reqwest::Client::new();
+            let request = match method {
+                reqwest::Method::GET => {
+                    let mut request = request.get(format!("{}{}", self.base_url, path));
+                    if args.is_some() {
+                        request = request.query(&args);
+                    }
+                    request
                 }
-                request
-            }
-            reqwest::Method::POST => {
-                let mut request = request.post(format!("{}{}", self.base_url, path));
-                if args.is_some() {
-                    request = request.json(&args);
+                reqwest::Method::POST => {
+                    let mut request = request.post(format!("{}{}", self.base_url, path));
+                    if args.is_some() {
+                        request = request.json(&args);
+                    }
+                    request
                 }
-                request
-            }
-            _ => return Err(anyhow!("Method not supported")),
+                _ => return Err(anyhow!("Method not supported")),
+            };
+            Ok(request.bearer_auth(token))
         };
 
-        if self.token.is_some() {
-            request = request.header(
-                reqwest::header::AUTHORIZATION,
-                format!("Bearer {}", self.token.as_ref().unwrap().access.clone()),
-            );
+        self.rate_limits.check(path)?;
+        let token = self.tokens.access_token().await?;
+        let mut response = build(&token)?.send().await?;
+        // Tokens can be revoked before they expire, so get a new one and try again once.
+        if response.status() == reqwest::StatusCode::UNAUTHORIZED {
+            self.tokens.invalidate(&token).await;
+            let token = self.tokens.access_token().await?;
+            response = build(&token)?.send().await?;
         }
-        match request.send().await {
-            Ok(r) => {
-                if r.status().is_success() {
-                    return Ok(r);
-                }
-                let error = r.json::<ErrorResponse>().await?;
-                Err(anyhow::Error::msg(format!(
-                    "{}. {} {}",
-                    error.summary.unwrap_or("No summary".into()),
-                    error.detail.unwrap_or("".into()),
-                    error.status_code.unwrap_or(0)
-                )))
+
+        let quota = Quota::from_headers(response.headers(), Utc::now().timestamp());
+        if let Some(quota) = &quota {
+            self.rate_limits.update(path, quota.clone());
+        }
+        if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let reset_at = quota.map(|q| q.reset_at).unwrap_or(Utc::now().timestamp());
+            return Err(RateLimited {
+                until: DateTime::from_timestamp(reset_at, 0).unwrap_or_default(),
+                path: path.to_string(),
             }
-            Err(e) => Err(e.into()),
+            .into());
         }
-    }
-
-    pub async fn populate_token(&mut self) -> anyhow::Result<AccessToken> {
-        let mut args: HashMap<String, String> = HashMap::new();
-        args.insert("secret_id".into(), self.key.clone());
-        args.insert("secret_key".into(), self.secret.clone());
-
-        let token = self
-            .request(reqwest::Method::POST, "/token/new/", Some(&args))
-            .await?;
-        let token = token.json::<AccessToken>().await?;
-        self.token = Some(token.clone());
-        Ok(token)
+        check_response(response).await
     }
 
 }
@@ -458,11 +943,15 @@ impl From<Transaction> for SourceTransaction {
     fn from(transaction: Transaction) -> Self {
         Self {
             id: transaction.transactionId.unwrap(),
+            pending: transaction.pending,
             creditor_name: transaction.creditorName,
             debtor_name: transaction.debtorName,
+            creditor_account: transaction.creditorAccount.map(|a| a.to_string()).filter(|a| !a.is_empty()),
+            debtor_account: transaction.debtorAccount.map(|a| a.to_string()).filter(|a| !a.is_empty()),
             remittance_information: transaction.remittanceInformationUnstructured,
             booking_date: transaction.bookingDate.unwrap(),
             booking_datetime: transaction.bookingDateTime.map(|d| d.naive_utc()),
+            value_date: transaction.valueDate,
             transaction_amount: transaction.transactionAmount.unwrap(),
             currency_exchange_rate: transaction
                 .currencyExchange
@@ -483,8 +972,7 @@ impl From<Transaction> for SourceTransaction {
 
 impl Account {
     pub async fn validate(&self) -> Result<(), anyhow::Error> {
-        let mut client = Nordigen::new();
-        client.populate_token().await?;
+        let client = Nordigen::new();
         dbg!(&client.get_account_details(&self.id).await?);
         Ok(())
     }
@@ -493,8 +981,7 @@ impl Account {
 impl crate::accounts::SourceAccount for Account {
 
     async fn details(&self) -> Result<crate::accounts::SourceAccountDetails, anyhow::Error> {
-        let mut client = Nordigen::new();
-        client.populate_token().await?;
+        let client = Nordigen::new();
         let account = client.get_account(&self.id).await?;
         let account_details = client.get_account_details(&self.id).await?;
         let institution = client.get_institution(&account.institution_id).await?;
@@ -517,16 +1004,18 @@ impl crate::accounts::SourceAccount for Account {
         })
     }
 
-    async fn balance(&self) -> Result<crate::accounts::Amount, anyhow::Error> {
-        let mut client = Nordigen::new();
-        client.populate_token().await?;
+    async fn balances(&self) -> Result<Vec<crate::accounts::SourceBalance>, anyhow::Error> {
+        let client = Nordigen::new();
         Ok(client
             .get_account_balances(&self.id)
             .await?
-            .first()
-            .ok_or(anyhow!("Account not found"))?
-            .balanceAmount
-            .clone())
+            .into_iter()
+            .map(|balance| crate::accounts::SourceBalance {
+                balance_type: balance.balanceType,
+                amount: balance.balanceAmount,
+                reference_date: balance.referenceDate,
+            })
+            .collect())
     }
 
     async fn transactions(
@@ -534,8 +1023,7 @@ impl crate::accounts::SourceAccount for Account {
         date_from: &Option<NaiveDate>,
         date_to: &Option<NaiveDate>,
     ) -> Result<Vec<SourceTransaction>, anyhow::Error> {
-        let mut client = Nordigen::new();
-        client.populate_token().await?;
+        let client = Nordigen::new();
 
         let transactions = client
             .get_account_transactions(&self.id, date_from, date_to)
@@ -543,3 +1031,115 @@ impl crate::accounts::SourceAccount for Account {
         Ok(transactions.into_iter().map(|t| t.into()).collect())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quota_from_headers() {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("http_x_ratelimit_remaining", "99".parse().unwrap());
+        headers.insert("http_x_ratelimit_reset", "60".parse().unwrap());
+        assert_eq!(
+            Quota::from_headers(&headers, 1000),
+            Some(Quota { limit: None, remaining: 99, reset_at: 1060 })
+        );
+
+        // The account quota is the one that matters when there is one.
+        headers.insert("X-RateLimit-Account-Success-Limit", "4".parse().unwrap());
+        headers.insert("X-RateLimit-Account-Success-Remaining", "0".parse().unwrap());
+        headers.insert("X-RateLimit-Account-Success-Reset", "3600".parse().unwrap());
+        assert_eq!(
+            Quota::from_headers(&headers, 1000),
+            Some(Quota { limit: Some(4), remaining: 0, reset_at: 4600 })
+        );
+        assert_eq!(Quota::from_headers(&reqwest::header::HeaderMap::new(), 1000), None);
+    }
+
+    #[test]
+    fn test_rate_limits_check() {
+        let limits = RateLimits::new(None);
+        let path = "/accounts/abc/transactions/";
+        let reset_at = Utc::now().timestamp() + 3600;
+        limits.update(path, Quota { limit: Some(4), remaining: 0, reset_at });
+        assert_eq!(limits.check(path).unwrap_err().until.timestamp(), reset_at);
+        // Other endpoints and accounts have their own quota.
+        assert!(limits.check("/accounts/abc/balances/").is_ok());
+        assert!(limits.check("/accounts/def/transactions/").is_ok());
+
+        limits.update(path, Quota { limit: Some(4), remaining: 1, reset_at });
+        assert!(limits.check(path).is_ok());
+    }
+
+    #[test]
+    fn test_rate_limits_shared_file() {
+        let file = env::temp_dir().join(format!("nordigen-rate-limits-{}.json", uuid::Uuid::new_v4()));
+        let (daemon, sync) = (RateLimits::new(Some(file.clone())), RateLimits::new(Some(file.clone())));
+        let reset_at = Utc::now().timestamp() + 3600;
+        assert!(sync.check("/accounts/abc/balances/").is_ok());
+
+        // Each sees the other's quotas, and neither overwrites them with its own.
+        daemon.update("/accounts/abc/balances/", Quota { limit: Some(4), remaining: 0, reset_at });
+        sync.update("/accounts/def/balances/", Quota { limit: Some(4), remaining: 0, reset_at });
+        assert!(sync.check("/accounts/abc/balances/").is_err());
+        assert!(daemon.check("/accounts/def/balances/").is_err());
+        assert!(RateLimits::new(Some(file.clone())).check("/accounts/abc/balances/").is_err());
+
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn test_agreement_terms_validate() {
+        let institution = Institution {
+            id: "BANK_XYZ".into(),
+            name: "Bank".into(),
+            bic: "XYZ".into(),
+            transaction_total_days: "540".into(),
+            max_access_valid_for_days: None,
+            countries: vec![],
+            logo: "".into(),
+        };
+        let terms = |max_historical_days, access_valid_for_days, access_scope: &[&str]| AgreementTerms {
+            max_historical_days,
+            access_valid_for_days,
+            access_scope: access_scope.iter().map(|s| s.to_string()).collect(),
+        };
+
+        assert!(AgreementTerms::default().validate(&institution).is_ok());
+        assert!(terms(540, 180, &["transactions"]).validate(&institution).is_ok());
+        assert!(terms(541, 90, &["transactions"]).validate(&institution).is_err());
+        assert!(terms(90, 181, &["transactions"]).validate(&institution).is_err());
+        assert!(terms(90, 90, &[]).validate(&institution).is_err());
+        assert!(terms(90, 90, &["payments"]).validate(&institution).is_err());
+
+        let institution = Institution {
+            max_access_valid_for_days: Some("90".into()),
+            ..institution
+        };
+        assert!(terms(90, 91, &["transactions"]).validate(&institution).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_token_manager_reuses_stored_token() {
+        let path = env::temp_dir().join(format!("nordigen-token-{}.json", uuid::Uuid::new_v4()));
+        let token = StoredToken {
+            secret_id: "id".into(),
+            access: "stored-access".into(),
+            access_expires_at: Utc::now().timestamp() + 3600,
+            refresh: "refresh".into(),
+            refresh_expires_at: Utc::now().timestamp() + 86400,
+        };
+        std::fs::write(&path, serde_json::to_string(&token).unwrap()).unwrap();
+
+        // The base url is unreachable, so any token request would fail.
+        let manager = |key: &str| {
+            TokenManager::new(key.into(), "secret".into(), "http://127.0.0.1:9".into(), Some(path.clone()))
+        };
+        assert_eq!(manager("id").access_token().await.unwrap(), "stored-access");
+        // Tokens for other credentials aren't used.
+        assert!(manager("other").access_token().await.is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
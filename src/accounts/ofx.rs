@@ -0,0 +1,393 @@
+use anyhow::anyhow;
+use chrono::{NaiveDate, NaiveDateTime};
+use serde::{Deserialize, Serialize};
+
+use crate::accounts::{SourceAccountDetails, SourceBalance, SourceTransaction};
+use crate::ultrafinance::{Amount, Money};
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Account {
+    pub path: String,
+    /// `ACCTID` of the statement to import, for files holding more than one account.
+    pub account_id: Option<String>,
+    pub institution_name: Option<String>,
+    pub owner_name: Option<String>,
+}
+
+/// An OFX element. Leaf elements have a value, aggregates have children.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    value: Option<String>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+
+    /// Value of a direct child leaf.
+    fn get(&self, name: &str) -> Option<&str> {
+        self.child(name).and_then(|c| c.value.as_deref())
+    }
+
+    /// Depth-first search for the first descendant with the name.
+    fn find(&self, name: &str) -> Option<&Element> {
+        self.children
+            .iter()
+            .find_map(|c| if c.name == name { Some(c) } else { c.find(name) })
+    }
+
+    fn find_all<'a>(&'a self, name: &str, found: &mut Vec<&'a Element>) {
+        for child in &self.children {
+            if child.name == name {
+                found.push(child);
+            } else {
+                child.find_all(name, found);
+            }
+        }
+    }
+}
+
+/// Parse OFX 1.x (SGML, where leaf elements aren't closed) or 2.x (XML) into an element tree.
+fn parse(data: &str) -> Result<Element, anyhow::Error> {
+    let start = data.find("<OFX>").ok_or(anyhow!("Not an OFX file, no <OFX> element found"))?;
+    let mut rest = &data[start..];
+    let mut stack = vec![Element::default()];
+
+    fn close(stack: &mut Vec<Element>) {
+        if stack.len() > 1 {
+            let element = stack.pop().unwrap();
+            stack.last_mut().unwrap().children.push(element);
+        }
+    }
+
+    // Close an element whose closing tag never came. Only SGML leaves go without one, so
+    // anything it holds came after an empty leaf (like `<MEMO>` and a newline) and belongs to
+    // its parent.
+    fn close_unterminated(stack: &mut Vec<Element>) {
+        if stack.len() > 1 {
+            let mut element = stack.pop().unwrap();
+            let children = std::mem::take(&mut element.children);
+            let parent = stack.last_mut().unwrap();
+            parent.children.push(element);
+            parent.children.extend(children);
+        }
+    }
+
+    while let Some(open) = rest.find('<') {
+        let text = rest[..open].trim();
+        if !text.is_empty() {
+            // Text belongs to the element just opened, which is a leaf: close it straight away
+            // so SGML files without closing tags nest correctly.
+            let leaf = stack.last_mut().unwrap();
+            if leaf.children.is_empty() && leaf.value.is_none() {
+                leaf.value = Some(unescape(text));
+                close(&mut stack);
+            }
+        }
+        let end = rest[open..].find('>').ok_or(anyhow!("Unterminated tag in OFX file"))? + open;
+        let tag = rest[open + 1..end].trim();
+        rest = &rest[end + 1..];
+
+        if tag.starts_with('?') || tag.starts_with('!') {
+            continue;
+        }
+        if let Some(name) = tag.strip_prefix('/') {
+            // XML closing tags for leaves were already handled when their text was read.
+            if let Some(depth) = stack.iter().rposition(|e| e.name == name) {
+                while stack.len() > depth + 1 {
+                    close_unterminated(&mut stack);
+                }
+                close(&mut stack);
+            }
+            continue;
+        }
+        let (name, self_closing) = match tag.strip_suffix('/') {
+            Some(name) => (name.trim(), true),
+            None => (tag, false),
+        };
+        stack.push(Element {
+            name: name.to_uppercase(),
+            ..Default::default()
+        });
+        if self_closing {
+            close(&mut stack);
+        }
+    }
+    while stack.len() > 1 {
+        close_unterminated(&mut stack);
+    }
+    stack
+        .pop()
+        .unwrap()
+        .children
+        .into_iter()
+        .find(|e| e.name == "OFX")
+        .ok_or(anyhow!("Not an OFX file, no <OFX> element found"))
+}
+
+fn unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+/// OFX dates are `YYYYMMDD[HHMMSS[.XXX]][[offset:TZ]]`. The timezone is ignored.
+fn parse_datetime(s: &str) -> Result<(NaiveDate, Option<NaiveDateTime>), anyhow::Error> {
+    let digits = s.chars().take_while(|c| c.is_ascii_digit()).collect::<String>();
+    let date = NaiveDate::parse_from_str(digits.get(..8).unwrap_or_default(), "%Y%m%d")
+        .map_err(|e| anyhow!("Invalid OFX date \"{}\": {}", s, e))?;
+    let datetime = digits
+        .get(..14)
+        .and_then(|d| NaiveDateTime::parse_from_str(d, "%Y%m%d%H%M%S").ok())
+        .filter(|d| d.time() != chrono::NaiveTime::MIN);
+    Ok((date, datetime))
+}
+
+fn parse_amount(s: &str) -> Result<Amount, anyhow::Error> {
+    s.replace(',', ".")
+        .parse::<Amount>()
+        .map_err(|_| anyhow!("Invalid OFX amount \"{}\"", s))
+}
+
+/// A single bank (`STMTRS`) or credit card (`CCSTMTRS`) statement from the file.
+struct Statement<'a> {
+    element: &'a Element,
+    account_id: Option<&'a str>,
+    currency: &'a str,
+}
+
+impl Account {
+    fn read(&self) -> Result<Element, anyhow::Error> {
+        let data = std::fs::read(&self.path)
+            .map_err(|e| anyhow!("Unable to read statement {}: {}", self.path, e))?;
+        parse(&String::from_utf8_lossy(&data))
+    }
+
+    fn statement<'a>(&self, ofx: &'a Element) -> Result<Statement<'a>, anyhow::Error> {
+        let mut statements = vec![];
+        ofx.find_all("STMTRS", &mut statements);
+        ofx.find_all("CCSTMTRS", &mut statements);
+        let statements = statements.into_iter().map(|element| Statement {
+            element,
+            account_id: element
+                .child("BANKACCTFROM")
+                .or(element.child("CCACCTFROM"))
+                .and_then(|a| a.get("ACCTID")),
+            currency: element.get("CURDEF").unwrap_or_default(),
+        });
+        let mut statements = statements.filter(|s| match &self.account_id {
+            Some(account_id) => s.account_id == Some(account_id.as_str()),
+            None => true,
+        });
+        statements
+            .next()
+            .ok_or(anyhow!("No statement found in {} for account {:?}", self.path, self.account_id))
+    }
+
+    fn transactions_from(&self, ofx: &Element) -> Result<Vec<SourceTransaction>, anyhow::Error> {
+        let statement = self.statement(ofx)?;
+        let mut entries = vec![];
+        statement.element.find_all("STMTTRN", &mut entries);
+
+        entries
+            .into_iter()
+            .map(|entry| {
+                let (booking_date, booking_datetime) =
+                    parse_datetime(entry.get("DTPOSTED").ok_or(anyhow!("Transaction without DTPOSTED"))?)?;
+                let value_date = entry.get("DTAVAIL").map(parse_datetime).transpose()?.map(|(date, _)| date);
+                let amount = parse_amount(entry.get("TRNAMT").ok_or(anyhow!("Transaction without TRNAMT"))?)?;
+                let name = entry
+                    .get("NAME")
+                    .or(entry.child("PAYEE").and_then(|p| p.get("NAME")))
+                    .map(str::to_string);
+                let is_outgoing = amount.is_negative();
+                // With CURRENCY the amount is in that currency, with ORIGCURRENCY it was converted
+                // into the statement's from that one.
+                let amount_currency = entry
+                    .child("CURRENCY")
+                    .and_then(|c| c.get("CURSYM"))
+                    .unwrap_or(statement.currency);
+                let currency = entry
+                    .child("CURRENCY")
+                    .or(entry.child("ORIGCURRENCY"));
+                Ok(SourceTransaction {
+                    id: entry
+                        .get("FITID")
+                        .ok_or(anyhow!("Transaction without FITID"))?
+                        .to_string(),
+                    pending: false,
+                    // NAME is the other party: who was paid, or who paid us.
+                    creditor_name: name.clone().filter(|_| is_outgoing),
+                    debtor_name: name.filter(|_| !is_outgoing),
+                    creditor_account: None,
+                    debtor_account: None,
+                    remittance_information: entry.get("MEMO").map(str::to_string),
+                    booking_date,
+                    booking_datetime,
+                    value_date,
+                    transaction_amount: Money::new(amount, amount_currency.parse()?),
+                    currency_exchange_rate: currency.and_then(|c| c.get("CURRATE")).map(str::to_string),
+                    proprietary_bank_transaction_code: entry.get("TRNTYPE").map(str::to_string),
+                    currency_exchange_source_currency: currency.and_then(|c| c.get("CURSYM")).map(str::to_string),
+                    currency_exchange_target_currency: currency.map(|_| statement.currency.to_string()),
+                })
+            })
+            .collect()
+    }
+}
+
+impl crate::accounts::SourceAccount for Account {
+    async fn details(&self) -> Result<SourceAccountDetails, anyhow::Error> {
+        let ofx = self.read()?;
+        let statement = self.statement(&ofx)?;
+        let number = statement.account_id.unwrap_or_default().to_string();
+        Ok(SourceAccountDetails {
+            id: number.clone(),
+            number,
+            currency: statement.currency.to_string(),
+            details: format!("OFX statement {}", self.path),
+            owner_name: self.owner_name.clone(),
+            icon: None,
+            institution_name: self
+                .institution_name
+                .clone()
+                .or(ofx.find("FI").and_then(|fi| fi.get("ORG")).map(str::to_string))
+                .unwrap_or("OFX".into()),
+        })
+    }
+
+    async fn balances(&self) -> Result<Vec<SourceBalance>, anyhow::Error> {
+        let ofx = self.read()?;
+        let statement = self.statement(&ofx)?;
+        if statement.element.child("LEDGERBAL").is_none() {
+            return Err(anyhow!("No LEDGERBAL in {}", self.path));
+        }
+
+        let mut balances = vec![];
+        for (name, balance_type) in [
+            ("LEDGERBAL", SourceBalance::CLOSING_BOOKED),
+            ("AVAILBAL", SourceBalance::CLOSING_AVAILABLE),
+        ] {
+            let Some(element) = statement.element.child(name) else {
+                continue;
+            };
+            balances.push(SourceBalance {
+                balance_type: balance_type.to_string(),
+                amount: Money::new(
+                    parse_amount(element.get("BALAMT").ok_or(anyhow!("{} without BALAMT", name))?)?,
+                    statement.currency.parse()?,
+                ),
+                reference_date: element.get("DTASOF").map(parse_datetime).transpose()?.map(|(date, _)| date),
+            });
+        }
+        Ok(balances)
+    }
+
+    async fn transactions(
+        &self,
+        date_from: &Option<NaiveDate>,
+        date_to: &Option<NaiveDate>,
+    ) -> Result<Vec<SourceTransaction>, anyhow::Error> {
+        Ok(self
+            .transactions_from(&self.read()?)?
+            .into_iter()
+            .filter(|t| date_from.is_none_or(|from| t.booking_date >= from))
+            .filter(|t| date_to.is_none_or(|to| t.booking_date <= to))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account(account_id: Option<&str>) -> Account {
+        Account {
+            path: "statement.ofx".into(),
+            account_id: account_id.map(str::to_string),
+            institution_name: None,
+            owner_name: None,
+        }
+    }
+
+    const SGML: &str = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+
+<OFX>
+<SIGNONMSGSRSV1><SONRS><STATUS><CODE>0<SEVERITY>INFO</STATUS><FI><ORG>Big Bank<FID>123</FI></SONRS></SIGNONMSGSRSV1>
+<BANKMSGSRSV1><STMTTRNRS><TRNUID>1<STMTRS>
+<CURDEF>USD
+<BANKACCTFROM><BANKID>121000248<ACCTID>000123<ACCTTYPE>CHECKING</BANKACCTFROM>
+<BANKTRANLIST><DTSTART>20240101<DTEND>20240131
+<STMTTRN><TRNTYPE>POS<DTPOSTED>20240105120000.000[-5:EST]<TRNAMT>-12.34<FITID>A1<NAME>COFFEE &amp; CO<MEMO>Card 1234</STMTTRN>
+<STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20240110<TRNAMT>+1500,00<FITID>A2<PAYEE><NAME>ACME</PAYEE></STMTTRN>
+<STMTTRN><TRNTYPE>POS<DTPOSTED>20240112<TRNAMT>-20.00<FITID>A3<MEMO>
+<CURRENCY><CURRATE>1.09<CURSYM>EUR</CURRENCY><NAME>CAFE DE PARIS</STMTTRN>
+</BANKTRANLIST>
+<LEDGERBAL><BALAMT>2487.66<DTASOF>20240131</LEDGERBAL>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>";
+
+    const XML: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<?OFX OFXHEADER="200" VERSION="220"?>
+<OFX>
+  <CREDITCARDMSGSRSV1><CCSTMTTRNRS><CCSTMTRS>
+    <CURDEF>EUR</CURDEF>
+    <CCACCTFROM><ACCTID>4111</ACCTID></CCACCTFROM>
+    <BANKTRANLIST>
+      <STMTTRN><TRNTYPE>DEBIT</TRNTYPE><DTPOSTED>20240201</DTPOSTED><TRNAMT>-50.00</TRNAMT><FITID>X1</FITID><NAME>Shop</NAME><MEMO></MEMO></STMTTRN>
+    </BANKTRANLIST>
+    <LEDGERBAL><BALAMT>-50.00</BALAMT><DTASOF>20240201</DTASOF></LEDGERBAL>
+  </CCSTMTRS></CCSTMTTRNRS></CREDITCARDMSGSRSV1>
+</OFX>"#;
+
+    #[test]
+    fn test_parse_sgml() {
+        let ofx = parse(SGML).unwrap();
+        assert_eq!(ofx.find("FI").unwrap().get("ORG"), Some("Big Bank"));
+
+        let transactions = account(None).transactions_from(&ofx).unwrap();
+        assert_eq!(transactions.len(), 3);
+        assert_eq!(transactions[0].id, "A1");
+        assert_eq!(transactions[0].transaction_amount.amount.to_string(), "-12.34");
+        assert_eq!(transactions[0].transaction_amount.currency.to_string(), "USD");
+        assert_eq!(transactions[0].creditor_name.as_deref(), Some("COFFEE & CO"));
+        assert_eq!(transactions[0].remittance_information.as_deref(), Some("Card 1234"));
+        assert_eq!(
+            transactions[0].booking_datetime,
+            NaiveDate::from_ymd_opt(2024, 1, 5).unwrap().and_hms_opt(12, 0, 0)
+        );
+        assert_eq!(transactions[1].transaction_amount.amount.to_string(), "1500.00");
+        assert_eq!(transactions[1].debtor_name.as_deref(), Some("ACME"));
+        assert_eq!(transactions[1].booking_datetime, None);
+        // An empty MEMO doesn't swallow what comes after it, and the amount is in CURSYM.
+        assert_eq!(transactions[2].remittance_information, None);
+        assert_eq!(transactions[2].creditor_name.as_deref(), Some("CAFE DE PARIS"));
+        assert_eq!(transactions[2].transaction_amount.currency.to_string(), "EUR");
+        assert_eq!(transactions[2].currency_exchange_rate.as_deref(), Some("1.09"));
+        assert_eq!(transactions[2].currency_exchange_target_currency.as_deref(), Some("USD"));
+
+        let statement = account(Some("000123")).statement(&ofx).unwrap();
+        assert_eq!(statement.element.child("LEDGERBAL").unwrap().get("BALAMT"), Some("2487.66"));
+        assert!(account(Some("999")).statement(&ofx).is_err());
+    }
+
+    #[test]
+    fn test_parse_xml() {
+        let ofx = parse(XML).unwrap();
+        let transactions = account(Some("4111")).transactions_from(&ofx).unwrap();
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].id, "X1");
+        assert_eq!(transactions[0].transaction_amount.currency.to_string(), "EUR");
+        assert_eq!(transactions[0].creditor_name.as_deref(), Some("Shop"));
+        assert_eq!(transactions[0].remittance_information, None);
+        assert_eq!(transactions[0].proprietary_bank_transaction_code.as_deref(), Some("DEBIT"));
+    }
+}
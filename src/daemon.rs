@@ -0,0 +1,179 @@
+use chrono::Duration;
+use log::{error, info};
+
+use crate::{accounts::nordigen::RateLimited, merchant_enricher::EnricherChain, models::*, ultrafinance};
+
+/// How often the daemon wakes up to look for due jobs.
+const TICK: std::time::Duration = std::time::Duration::from_secs(30);
+/// Transactions enriched per run, as enrichment can time out on large batches.
+const ENRICH_BATCH_SIZE: u32 = 10;
+/// Transactions no provider found a merchant for are tried again after this many days.
+const ENRICH_RETRY_DAYS: i64 = 1;
+
+/// Default intervals, used for jobs that don't have their own override.
+#[derive(Debug, Clone)]
+pub struct Intervals {
+    pub import: Duration,
+    pub balances: Duration,
+    pub enrich: Duration,
+    pub trigger_queue: Duration,
+    pub requisitions: Duration,
+}
+
+impl Intervals {
+    fn get(&self, job: &str) -> Duration {
+        match job {
+            DaemonJob::IMPORT => self.import,
+            DaemonJob::BALANCES => self.balances,
+            DaemonJob::ENRICH => self.enrich,
+            DaemonJob::REQUISITIONS => self.requisitions,
+            _ => self.trigger_queue,
+        }
+    }
+}
+
+/// Shortest interval a provider allows between syncs. GoCardless (Nordigen) only allows
+/// 4 requests per day for each account and endpoint.
+fn min_interval(account_type: &str) -> Duration {
+    match account_type {
+        "nordigen" => Duration::hours(6),
+        _ => Duration::zero(),
+    }
+}
+
+/// The interval to schedule the job's next run with.
+fn interval(job: &DaemonJob, account: Option<&Account>, defaults: &Intervals) -> Duration {
+    let interval = job
+        .interval_seconds
+        .map(|s| Duration::seconds(s.into()))
+        .unwrap_or(defaults.get(&job.job));
+    match account {
+        Some(account) => interval.max(min_interval(&account.account_type)),
+        None => interval,
+    }
+}
+
+/// Parse an interval like `90`, `90s`, `15m`, `6h` or `1d`.
+pub fn parse_interval(s: &str) -> Result<Duration, anyhow::Error> {
+    let s = s.trim();
+    let (number, unit) = s.split_at(s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+    let number: i64 = number
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid interval \"{}\"", s))?;
+    match unit {
+        "" | "s" => Ok(Duration::seconds(number)),
+        "m" => Ok(Duration::minutes(number)),
+        "h" => Ok(Duration::hours(number)),
+        "d" => Ok(Duration::days(number)),
+        _ => Err(anyhow::anyhow!("Invalid interval unit \"{}\", expected s, m, h or d", unit)),
+    }
+}
+
+/// `requisition_expiring_days` is how long before consent lapses `requisition_expiring` triggers run.
+pub async fn run(
+    intervals: Intervals,
+    requisition_expiring_days: u32,
+    db: &sqlx::MySqlPool,
+) -> Result<(), anyhow::Error> {
+    info!("Starting daemon with intervals {:?}", intervals);
+    loop {
+        if let Err(e) = tick(&intervals, requisition_expiring_days, db).await {
+            error!("Daemon tick failed: {:?}", e);
+        }
+        tokio::time::sleep(TICK).await;
+    }
+}
+
+/// Run every job that is due.
+async fn tick(
+    intervals: &Intervals,
+    requisition_expiring_days: u32,
+    db: &sqlx::MySqlPool,
+) -> Result<(), anyhow::Error> {
+    let now = chrono::Local::now().naive_local();
+    let accounts = Account::sqlx_all(db).await?;
+
+    let mut due = vec![];
+    for job in DaemonJob::JOBS {
+        if DaemonJob::is_per_account(job) {
+            for account in &accounts {
+                due.push((DaemonJob::sqlx_find_or_create(job, Some(account.id), db).await?, Some(account)));
+            }
+        } else {
+            due.push((DaemonJob::sqlx_find_or_create(job, None, db).await?, None));
+        }
+    }
+    due.retain(|(job, _)| job.next_run_at <= now);
+
+    // Imports for all due accounts run together, as `transactions import` does.
+    let mut import_accounts = due
+        .iter()
+        .filter(|(job, _)| job.job == DaemonJob::IMPORT)
+        .filter_map(|(_, account)| account.cloned())
+        .collect::<Vec<_>>();
+    let mut imported = ultrafinance::sqlx_sync_accounts(&mut import_accounts, db).await;
+
+    for (mut job, account) in due {
+        let result = match (job.job.as_str(), account) {
+            (DaemonJob::IMPORT, Some(account)) => imported
+                .remove(&account.id)
+                .unwrap_or(Ok(vec![]))
+                .map(|transactions| info!("Imported {} transactions for account {}", transactions.len(), account.id)),
+            (DaemonJob::BALANCES, Some(account)) => update_balance(account.clone(), db).await,
+            (DaemonJob::ENRICH, None) => enrich(db).await,
+            (DaemonJob::TRIGGER_QUEUE, None) => ultrafinance::process_trigger_queue(100, db)
+                .await
+                .map(|results| info!("Processed {} trigger queue entries", results.len())),
+            (DaemonJob::REQUISITIONS, None) => ultrafinance::sqlx_check_requisitions(requisition_expiring_days, db)
+                .await
+                .map(|expiring| info!("Queued triggers for {} expiring requisitions", expiring.len())),
+            (job, _) => Err(anyhow::anyhow!("Unknown daemon job {}", job)),
+        };
+
+        if let Err(e) = &result {
+            error!("Daemon job {} ({:?}) failed: {:#}", job.job, job.account_id, e);
+        }
+        let mut interval = interval(&job, account, intervals);
+        // Defer rate limited jobs until the provider allows calls again.
+        if let Some(limited) = result.as_ref().err().and_then(|e| e.downcast_ref::<RateLimited>()) {
+            interval = interval.max(limited.until.naive_utc() - chrono::Utc::now().naive_utc());
+        }
+        job.sqlx_record_run(result.as_ref().err(), interval, db).await?;
+    }
+    Ok(())
+}
+
+async fn update_balance(mut account: Account, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+    ultrafinance::sqlx_update_balances(&mut account, db).await?;
+    info!("Updated balance for account {} to {}", account.id, account.current_balance());
+    Ok(())
+}
+
+async fn enrich(db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+    let tried_before = chrono::Local::now().naive_local() - Duration::days(ENRICH_RETRY_DAYS);
+    let transactions = Transaction::sqlx_to_enrich(ENRICH_BATCH_SIZE, tried_before, db).await?;
+    if transactions.is_empty() {
+        return Ok(());
+    }
+    let enricher = EnricherChain::from_env(db)?;
+    let ids = transactions.iter().map(|t| t.id).collect::<Vec<_>>();
+    Transaction::sqlx_mark_enrichment_attempted(&ids, db).await?;
+    let enriched = ultrafinance::sqlx_enrich_transactions(transactions, &enricher, db).await?;
+    info!("Enriched {} transactions", enriched.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("90").unwrap(), Duration::seconds(90));
+        assert_eq!(parse_interval("15m").unwrap(), Duration::minutes(15));
+        assert_eq!(parse_interval("6h").unwrap(), Duration::hours(6));
+        assert_eq!(parse_interval("1d").unwrap(), Duration::days(1));
+        assert!(parse_interval("1w").is_err());
+        assert!(parse_interval("h").is_err());
+    }
+}
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use chrono::{Utc};
+use chrono::{NaiveDate, Utc};
 use serde::Deserialize;
 
 use crate::{exchange_rate::ExchangeRate, ultrafinance::Currency};
@@ -24,10 +24,12 @@ impl From<Response> for ExchangeRate {
 				conversation_rates.insert(currency, value);
 			}
 		}
+		let last_update = Utc::now().naive_utc();
 		ExchangeRate {
 			base_code: response.base_code,
+			date: last_update.date(),
 			conversion_rates: conversation_rates,
-			last_update: Utc::now().naive_utc(),
+			last_update,
 		}
 	}
 }
@@ -47,4 +49,22 @@ impl Client {
 
 		Ok(json.into())
 	}
+
+	/// The rates at the end of `date`. Historical data needs a paid plan.
+	pub async fn get_historical_exchange_rate(
+		&self,
+		currency: &Currency,
+		date: NaiveDate,
+	) -> Result<ExchangeRate, anyhow::Error> {
+		let url = format!(
+			"https://v6.exchangerate-api.com/v6/{}/history/{}/{}",
+			&self.api_key,
+			&currency,
+			date.format("%Y/%m/%d")
+		);
+		let response = self.reqwest.get(&url).send().await?.error_for_status()?;
+		let mut exchange_rate: ExchangeRate = response.json::<Response>().await?.into();
+		exchange_rate.date = date;
+		Ok(exchange_rate)
+	}
 }
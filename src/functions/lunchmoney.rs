@@ -1,8 +1,12 @@
 use async_trait::async_trait;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
 
-use crate::{ultrafinance::TransactionDestination, FunctionParam, FunctionParams, Transaction};
+use crate::{
+    ultrafinance::{Amount, TransactionDestination},
+    FunctionParam, FunctionParams, Transaction,
+};
 
 #[derive(Serialize, Deserialize)]
 struct Config {
@@ -36,7 +40,7 @@ struct InsertTransactionResponse {
 struct DraftTransaction {
     asset_id: u32,
     date: String,
-    amount: String,
+    amount: Amount,
     currency: String,
     payee: String,
     notes: String,
@@ -73,14 +77,20 @@ pub async fn get_params() -> Result<FunctionParams, anyhow::Error> {
 
 pub struct Lunchmoney {
     config: Config,
+    output: Mutex<Vec<String>>,
 }
 
 impl Lunchmoney {
     pub fn new(config: &str) -> Result<Self, anyhow::Error> {
         Ok(Self {
             config: serde_json::from_str(config)?,
+            output: Mutex::new(vec![]),
         })
     }
+
+    fn log(&self, line: String) {
+        self.output.lock().unwrap().push(line);
+    }
 }
 
 #[async_trait]
@@ -130,6 +140,7 @@ impl TransactionDestination for Lunchmoney {
             if let Some(choice) = response.choices.first() {
                 let category_name = choice.message.content.as_ref().unwrap();
                 if let Some(category) = lm_categories.categories.iter().find(|category| &category.name == category_name) {
+                    self.log(format!("Categorized as \"{}\"", category.name));
                     category_id = Some(category.id);
                 }
             }
@@ -139,7 +150,7 @@ impl TransactionDestination for Lunchmoney {
             category_id,
             asset_id: self.config.account_id.parse::<u32>()?,
             date: transaction.booking_date.to_string(),
-            amount: transaction.transaction_amount.clone(),
+            amount: transaction.transaction_amount,
             currency: transaction
                 .transaction_amount_currency
                 .to_string()
@@ -182,6 +193,11 @@ impl TransactionDestination for Lunchmoney {
                 inserted.error.unwrap().join(", ")
             ));
         }
+        self.log(format!("Inserted transaction {} into Lunchmoney", transaction.external_id));
         Ok(())
     }
+
+    fn get_output(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
 }
@@ -1 +1,3 @@
 pub mod lunchmoney;
+pub mod script;
+pub mod webhook;
@@ -0,0 +1,306 @@
+use async_trait::async_trait;
+use rquickjs::{
+    function::Rest, promise::MaybePromise, CatchResultExt, Context, Ctx, Function, Module,
+    Object, Runtime, Value,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use crate::{ultrafinance::TransactionDestination, FunctionParams, Transaction, TransactionWithMerchant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+const MEMORY_LIMIT: usize = 64 * 1024 * 1024;
+const MAX_STACK_SIZE: usize = 512 * 1024;
+/// Comfortably above `MAX_STACK_SIZE`, so QuickJS hits its own limit before the thread overflows.
+const THREAD_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct StdioMsg {
+    pub msg: String,
+    pub is_err: bool,
+}
+
+impl std::fmt::Display for StdioMsg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.is_err {
+            true => write!(f, "[stderr] {}", self.msg),
+            false => write!(f, "{}", self.msg),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Limits {
+    pub timeout: Duration,
+    pub memory: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            timeout: TIMEOUT,
+            memory: MEMORY_LIMIT,
+        }
+    }
+}
+
+/// Script functions declare their own params, so there is nothing to ask for up front.
+pub async fn get_params() -> Result<FunctionParams, anyhow::Error> {
+    Ok(FunctionParams::new())
+}
+
+pub struct Script {
+    source: String,
+    params: String,
+    db: sqlx::MySqlPool,
+    output: Mutex<Vec<StdioMsg>>,
+}
+
+impl Script {
+    pub fn new(source: &str, params: &str, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        serde_json::from_str::<serde_json::Value>(params)?;
+        Ok(Self {
+            source: source.to_string(),
+            params: params.to_string(),
+            db: db.clone(),
+            output: Mutex::new(vec![]),
+        })
+    }
+
+    async fn run_payload(&self, payload: String) -> Result<(), anyhow::Error> {
+        let (result, console) = run(self.source.clone(), payload, self.params.clone(), Limits::default()).await;
+        self.output.lock().unwrap().extend(console);
+        result.map(|_| ())
+    }
+}
+
+#[async_trait]
+impl TransactionDestination for Script {
+    async fn transaction_created(&self, transaction: &Transaction) -> Result<(), anyhow::Error> {
+        let payload = TransactionWithMerchant::sqlx_from_transaction(transaction.clone(), &self.db).await?;
+        self.run_payload(serde_json::to_string(&payload)?).await
+    }
+
+    async fn event(&self, _event: &str, payload: &serde_json::Value) -> Result<(), anyhow::Error> {
+        self.run_payload(serde_json::to_string(payload)?).await
+    }
+
+    fn get_output(&self) -> Vec<String> {
+        self.output.lock().unwrap().iter().map(|m| m.to_string()).collect()
+    }
+}
+
+/// Run the default export of `source` with the JSON `payload` and `params` on a blocking thread,
+/// returning its JSON encoded result along with everything it wrote to the console.
+pub async fn run(
+    source: String,
+    payload: String,
+    params: String,
+    limits: Limits,
+) -> (Result<String, anyhow::Error>, Vec<StdioMsg>) {
+    tokio::task::spawn_blocking(move || run_blocking(&source, &payload, &params, limits))
+        .await
+        .unwrap_or_else(|e| (Err(anyhow::anyhow!(e)), vec![]))
+}
+
+/// Run the script on a dedicated thread. QuickJS measures its stack limit from the thread that
+/// creates the runtime, so the runtime has to be created and used on a thread whose stack we size.
+fn run_blocking(
+    source: &str,
+    payload: &str,
+    params: &str,
+    limits: Limits,
+) -> (Result<String, anyhow::Error>, Vec<StdioMsg>) {
+    let (source, payload, params) = (source.to_string(), payload.to_string(), params.to_string());
+    std::thread::Builder::new()
+        .name("script".to_string())
+        .stack_size(THREAD_STACK_SIZE)
+        .spawn(move || run_on_thread(&source, &payload, &params, limits))
+        .map_err(|e| anyhow::anyhow!(e))
+        .and_then(|thread| {
+            thread
+                .join()
+                .map_err(|_| anyhow::anyhow!("Script thread panicked"))
+        })
+        .unwrap_or_else(|e| (Err(e), vec![]))
+}
+
+fn run_on_thread(
+    source: &str,
+    payload: &str,
+    params: &str,
+    limits: Limits,
+) -> (Result<String, anyhow::Error>, Vec<StdioMsg>) {
+    let console = Arc::new(Mutex::new(vec![]));
+    let deadline = Instant::now() + limits.timeout;
+    let result = eval(source, payload, params, limits, deadline, console.clone());
+    let result = match result {
+        Err(_) if Instant::now() >= deadline => Err(anyhow::anyhow!(
+            "Script exceeded the time limit of {}ms",
+            limits.timeout.as_millis()
+        )),
+        result => result,
+    };
+    let console = console.lock().unwrap().clone();
+    (result, console)
+}
+
+fn eval(
+    source: &str,
+    payload: &str,
+    params: &str,
+    limits: Limits,
+    deadline: Instant,
+    console: Arc<Mutex<Vec<StdioMsg>>>,
+) -> Result<String, anyhow::Error> {
+    // A fresh runtime per run, so scripts can't leak state into each other.
+    let runtime = Runtime::new()?;
+    runtime.set_memory_limit(limits.memory);
+    runtime.set_max_stack_size(MAX_STACK_SIZE);
+    runtime.set_interrupt_handler(Some(Box::new(move || Instant::now() >= deadline)));
+    let context = Context::full(&runtime)?;
+
+    context.with(|ctx| {
+        let caught = |e| anyhow::anyhow!("{}", rquickjs::CaughtError::from_error(&ctx, e));
+
+        ctx.globals()
+            .set("console", console_object(&ctx, console).map_err(caught)?)
+            .map_err(caught)?;
+
+        let (module, promise) = Module::declare(ctx.clone(), "function.js", source)
+            .and_then(|m| m.eval())
+            .catch(&ctx)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        promise.finish::<()>().map_err(caught)?;
+
+        let function: Function = module
+            .get("default")
+            .map_err(|_| anyhow::anyhow!("Script must have a default export function"))?;
+        let payload = ctx.json_parse(payload).map_err(caught)?;
+        let params = ctx.json_parse(params).map_err(caught)?;
+
+        let value = function
+            .call::<_, MaybePromise>((payload, params))
+            .and_then(|r| r.finish::<Value>())
+            .map_err(|e| match e {
+                rquickjs::Error::WouldBlock => {
+                    anyhow::anyhow!("Script returned a promise that never resolved")
+                }
+                e => caught(e),
+            })?;
+
+        Ok(ctx
+            .json_stringify(value)
+            .map_err(caught)?
+            .map(|s| s.to_string())
+            .transpose()?
+            .unwrap_or("undefined".to_string()))
+    })
+}
+
+fn console_object<'js>(
+    ctx: &Ctx<'js>,
+    console: Arc<Mutex<Vec<StdioMsg>>>,
+) -> rquickjs::Result<Object<'js>> {
+    let object = Object::new(ctx.clone())?;
+    for (name, is_err) in [
+        ("log", false),
+        ("info", false),
+        ("debug", false),
+        ("warn", true),
+        ("error", true),
+    ] {
+        let console = console.clone();
+        let function = Function::new(ctx.clone(), move |ctx: Ctx<'js>, args: Rest<Value<'js>>| {
+            let msg = args
+                .0
+                .into_iter()
+                .map(|arg| format_arg(&ctx, arg))
+                .collect::<rquickjs::Result<Vec<_>>>()?
+                .join(" ");
+            console.lock().unwrap().push(StdioMsg { msg, is_err });
+            Ok::<_, rquickjs::Error>(())
+        })?;
+        object.set(name, function)?;
+    }
+    Ok(object)
+}
+
+fn format_arg<'js>(ctx: &Ctx<'js>, arg: Value<'js>) -> rquickjs::Result<String> {
+    if let Some(s) = arg.as_string() {
+        return s.to_string();
+    }
+    if arg.is_function() || arg.is_undefined() || arg.is_symbol() {
+        return Ok(format!("{:?}", arg.type_of()).to_lowercase());
+    }
+    Ok(ctx
+        .json_stringify(arg)?
+        .map(|s| s.to_string())
+        .transpose()?
+        .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits() -> Limits {
+        Limits {
+            timeout: Duration::from_millis(500),
+            memory: 16 * 1024 * 1024,
+        }
+    }
+
+    #[test]
+    fn test_run_with_console() {
+        let (result, console) = run_blocking(
+            "export default async function (transaction, params) {
+                console.log('amount', transaction.amount, { id: 1 });
+                console.error('oops');
+                return { total: transaction.amount * params.multiplier };
+            }",
+            r#"{"amount": 2}"#,
+            r#"{"multiplier": 3}"#,
+            limits(),
+        );
+        assert_eq!(result.unwrap(), r#"{"total":6}"#);
+        assert_eq!(
+            console,
+            vec![
+                StdioMsg { msg: r#"amount 2 {"id":1}"#.to_string(), is_err: false },
+                StdioMsg { msg: "oops".to_string(), is_err: true },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_errors() {
+        let (result, _) = run_blocking(
+            "export default function () { throw new Error('Bad transaction') }",
+            "{}",
+            "{}",
+            limits(),
+        );
+        assert!(result.unwrap_err().to_string().contains("Bad transaction"));
+
+        let (result, _) = run_blocking("export const a = 1;", "{}", "{}", limits());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_run_limits() {
+        let (result, _) = run_blocking("export default function () { while (true) {} }", "{}", "{}", limits());
+        assert!(result.unwrap_err().to_string().contains("time limit"));
+
+        let (result, _) = run_blocking(
+            "export default function () { const a = []; while (true) { a.push('x'.repeat(1024)) } }",
+            "{}",
+            "{}",
+            limits(),
+        );
+        assert!(result.is_err());
+    }
+}
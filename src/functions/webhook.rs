@@ -0,0 +1,183 @@
+use async_trait::async_trait;
+use reqwest::{
+    header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE},
+    Client,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::sync::Mutex;
+
+use crate::{
+    ultrafinance::TransactionDestination, FunctionParam, FunctionParams, Transaction,
+    TransactionWithMerchant,
+};
+
+const SIGNATURE_HEADER: &str = "X-Ultrafinance-Signature";
+const EVENT_HEADER: &str = "X-Ultrafinance-Event";
+
+#[derive(Serialize, Deserialize)]
+struct Config {
+    url: String,
+    secret: Option<String>,
+    /// Extra headers to send, one `Name: value` pair per line.
+    headers: Option<String>,
+}
+
+pub async fn get_params() -> Result<FunctionParams, anyhow::Error> {
+    Ok(FunctionParams::from([
+        (
+            "url".to_string(),
+            FunctionParam {
+                name: "Webhook URL".to_string(),
+                r#type: "string".to_string(),
+            },
+        ),
+        (
+            "secret".to_string(),
+            FunctionParam {
+                name: "Signing Secret".to_string(),
+                r#type: "string".to_string(),
+            },
+        ),
+        (
+            "headers".to_string(),
+            FunctionParam {
+                name: "Headers (one \"Name: value\" per line)".to_string(),
+                r#type: "string".to_string(),
+            },
+        ),
+    ]))
+}
+
+pub struct Webhook {
+    config: Config,
+    headers: HeaderMap,
+    db: sqlx::MySqlPool,
+    output: Mutex<Vec<String>>,
+}
+
+impl Webhook {
+    pub fn new(config: &str, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        let config: Config = serde_json::from_str(config)?;
+        reqwest::Url::parse(&config.url)?;
+        let headers = parse_headers(config.headers.as_deref().unwrap_or_default())?;
+        Ok(Self {
+            config,
+            headers,
+            db: db.clone(),
+            output: Mutex::new(vec![]),
+        })
+    }
+
+    async fn send(&self, event: &str, body: String) -> Result<(), anyhow::Error> {
+        let mut request = Client::new()
+            .post(&self.config.url)
+            .headers(self.headers.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, event);
+
+        if let Some(secret) = &self.config.secret {
+            request = request.header(
+                SIGNATURE_HEADER,
+                format!("sha256={}", sign(secret.as_bytes(), body.as_bytes())),
+            );
+        }
+
+        self.log(format!("POST {} ({})", self.config.url, event));
+        let response = request.body(body).send().await?;
+        let status = response.status();
+        let text = response.text().await.unwrap_or_default();
+        self.log(format!("{}: {}", status, text));
+        if !status.is_success() {
+            return Err(anyhow::anyhow!(
+                "Webhook {} responded with {}: {}",
+                self.config.url,
+                status,
+                text
+            ));
+        }
+        Ok(())
+    }
+
+    fn log(&self, line: String) {
+        self.output.lock().unwrap().push(line);
+    }
+}
+
+#[async_trait]
+impl TransactionDestination for Webhook {
+    async fn transaction_created(&self, transaction: &Transaction) -> Result<(), anyhow::Error> {
+        let payload = TransactionWithMerchant::sqlx_from_transaction(transaction.clone(), &self.db).await?;
+        self.send("transaction_created", serde_json::to_string(&payload)?)
+            .await
+    }
+
+    async fn event(&self, event: &str, payload: &serde_json::Value) -> Result<(), anyhow::Error> {
+        self.send(event, serde_json::to_string(payload)?).await
+    }
+
+    fn get_output(&self) -> Vec<String> {
+        self.output.lock().unwrap().clone()
+    }
+}
+
+fn parse_headers(headers: &str) -> Result<HeaderMap, anyhow::Error> {
+    let mut map = HeaderMap::new();
+    for line in headers.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or(anyhow::anyhow!("Invalid header \"{}\", expected \"Name: value\"", line))?;
+        map.insert(
+            HeaderName::from_bytes(name.trim().as_bytes())?,
+            HeaderValue::from_str(value.trim())?,
+        );
+    }
+    Ok(map)
+}
+
+/// Hex encoded HMAC-SHA256 of the body, so receivers can verify the request came from us.
+fn sign(secret: &[u8], body: &[u8]) -> String {
+    const BLOCK_SIZE: usize = 64;
+
+    let mut key = [0u8; BLOCK_SIZE];
+    if secret.len() > BLOCK_SIZE {
+        key[..32].copy_from_slice(&Sha256::digest(secret));
+    } else {
+        key[..secret.len()].copy_from_slice(secret);
+    }
+
+    let mut inner = Sha256::new();
+    inner.update(key.map(|b| b ^ 0x36));
+    inner.update(body);
+    let inner = inner.finalize();
+
+    let mut outer = Sha256::new();
+    outer.update(key.map(|b| b ^ 0x5c));
+    outer.update(inner);
+    format!("{:x}", outer.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_matches_rfc_4231() {
+        assert_eq!(
+            sign(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            sign(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First"),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+    }
+
+    #[test]
+    fn test_parse_headers() {
+        let headers = parse_headers("Authorization: Bearer abc\n\nX-Team: payments ").unwrap();
+        assert_eq!(headers.get("authorization").unwrap(), "Bearer abc");
+        assert_eq!(headers.get("x-team").unwrap(), "payments");
+        assert!(parse_headers("not a header").is_err());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{NewMerchant, Transaction};
+use crate::{merchant_enricher::Enrichment, NewMerchant, Transaction};
 use async_openai::{
     config::OpenAIConfig,
     types::{
@@ -19,6 +19,7 @@ pub struct Client {
 struct EnrichTransactionArguments {
     transaction_id: u32,
     merchant: NewMerchant,
+    confidence: Option<f32>,
 }
 
 #[derive(Deserialize)]
@@ -43,8 +44,8 @@ impl Client {
 
     pub async fn get_merchants(
         &self,
-        transactions: &Vec<Transaction>,
-    ) -> Result<HashMap<u32, NewMerchant>, anyhow::Error> {
+        transactions: &[Transaction],
+    ) -> Result<HashMap<u32, Enrichment>, anyhow::Error> {
         let request = CreateChatCompletionRequestArgs::default()
         .model("gpt-4-turbo-preview")
         .messages([
@@ -75,6 +76,10 @@ impl Client {
                                 "transaction_id": {
                                     "type": "number"
                                 },
+                                "confidence": {
+                                    "type": "number",
+                                    "description": "How likely the merchant is right, from 0 to 1."
+                                },
                                 "merchant": {
                                     "type": "object",
                                     "properties": {
@@ -122,7 +127,7 @@ impl Client {
                                     "required": ["name", "location", "location_structured", "labels", "website"]
                                 }
                             },
-                            "required": ["transaction_id", "merchant"],
+                            "required": ["transaction_id", "merchant", "confidence"],
                         }
                     },
                 },
@@ -158,13 +163,17 @@ impl Client {
                         .add_brandfetch(transaction.merchant.clone())
                         .await
                         .unwrap_or(transaction.merchant);
-                    (transaction.transaction_id, transaction.merchant)
+                    let mut enrichment = Enrichment::from(transaction.merchant);
+                    // Labels are asked for space separated.
+                    enrichment.labels = enrichment.labels.iter().flat_map(|l| l.split_whitespace()).map(String::from).collect();
+                    enrichment.confidence = transaction.confidence;
+                    (transaction.transaction_id, enrichment)
                 });
             }
 
-            while let Some((transaction_id, merchant)) = futures.next().await {
+            while let Some((transaction_id, enrichment)) = futures.next().await {
                 // Perform the operation that does not need to be awaited
-                transactions_map.insert(transaction_id, merchant);
+                transactions_map.insert(transaction_id, enrichment);
                 // You can use `id` here as needed
             }
             Ok(transactions_map)
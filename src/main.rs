@@ -3,20 +3,27 @@ use clap::{Parser, Subcommand};
 use cli_table::{print_stdout, WithTitle};
 use dotenvy::dotenv;
 use accounts::nordigen::{self, Nordigen};
-use sqlx::{mysql::MySqlPoolOptions, query_as};
+use merchant_enricher::{Enricher, EnricherChain};
+use rate_providers::RateProvider;
+use sqlx::{mysql::MySqlPoolOptions, QueryBuilder};
 use std::{env, time::Duration};
-use ultrafinance::Currency;
+use models::exchange_rate::{Converter, ExchangeRate};
+use ultrafinance::{Currency, Money};
 
 use crate::accounts::{get_source_account, SourceAccount};
 
 pub use self::models::*;
 
 pub mod accounts;
+pub mod daemon;
 pub mod exchangerate_api;
 pub mod functions;
 pub mod gpt_enricher;
+pub mod merchant_enricher;
 pub mod models;
 pub mod ntropy;
+pub mod rate_providers;
+pub mod server;
 pub mod synth_api;
 pub mod ultrafinance;
 pub mod utils;
@@ -47,6 +54,50 @@ enum Commands {
     Merchants(MerchantsCommand),
     #[command(subcommand)]
     ExchangeRates(ExchangeRatesCommand),
+    #[command(subcommand)]
+    Server(ServerCommand),
+    #[command(subcommand)]
+    Daemon(DaemonCommand),
+}
+
+#[derive(Subcommand)]
+enum DaemonCommand {
+    /// Run imports, balance updates, enrichment, the trigger queue and requisition checks on a schedule.
+    Start {
+        #[arg(long, default_value = "6h", value_parser = daemon::parse_interval)]
+        import_interval: chrono::Duration,
+        #[arg(long, default_value = "12h", value_parser = daemon::parse_interval)]
+        balances_interval: chrono::Duration,
+        #[arg(long, default_value = "15m", value_parser = daemon::parse_interval)]
+        enrich_interval: chrono::Duration,
+        #[arg(long, default_value = "1m", value_parser = daemon::parse_interval)]
+        trigger_queue_interval: chrono::Duration,
+        #[arg(long, default_value = "12h", value_parser = daemon::parse_interval)]
+        requisitions_interval: chrono::Duration,
+        /// Days before a requisition's consent lapses to run `requisition_expiring` triggers.
+        #[arg(long, default_value_t = 7)]
+        requisition_expiring_days: u32,
+    },
+    /// Show when each job last ran and is next due.
+    Status,
+    /// Override the interval for a job, or reset it to the default when --interval is omitted.
+    /// The next run is rescheduled to match.
+    SetInterval {
+        #[arg(long, value_parser = DaemonJob::JOBS)]
+        job: String,
+        #[arg(long)]
+        account_id: Option<u32>,
+        #[arg(long, value_parser = daemon::parse_interval)]
+        interval: Option<chrono::Duration>,
+    },
+}
+
+#[derive(Subcommand)]
+enum ServerCommand {
+    Start {
+        #[arg(long, default_value_t = 3000)]
+        port: u16,
+    },
 }
 
 #[derive(Subcommand)]
@@ -57,7 +108,50 @@ enum UsersCommand {
         name: String,
         #[arg(long)]
         email: String,
-    }
+        /// Prompted for when not given.
+        #[arg(long)]
+        password: Option<String>,
+        /// Currency totals and converted amounts are shown in, defaults to EUR.
+        #[arg(long)]
+        currency: Option<String>,
+    },
+    /// Set a user's primary currency and recalculate their converted amounts.
+    SetCurrency {
+        #[arg(long)]
+        user_id: u32,
+        #[arg(long)]
+        currency: String,
+    },
+    /// Set a user's password, logging them out of all sessions.
+    SetPassword {
+        #[arg(long)]
+        user_id: u32,
+        /// Prompted for when not given.
+        #[arg(long)]
+        password: Option<String>,
+    },
+    #[command(subcommand)]
+    Sessions(UsersSessionsCommand),
+    /// Create an API key used to authenticate with the server.
+    CreateApiKey {
+        #[arg(long)]
+        user_id: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum UsersSessionsCommand {
+    List {
+        #[arg(long)]
+        user_id: Option<u32>,
+    },
+    /// Revoke a single session, or every session for a user.
+    Revoke {
+        #[arg(long, required_unless_present = "user_id")]
+        id: Option<u32>,
+        #[arg(long, conflicts_with = "id")]
+        user_id: Option<u32>,
+    },
 }
 
 #[derive(Clone, clap::ValueEnum)]
@@ -68,7 +162,11 @@ enum ListFormat {
 
 #[derive(Subcommand)]
 enum AccountsCommand {
-    List,
+    List {
+        /// Only the user's accounts, with their total balance in the user's primary currency.
+        #[arg(long)]
+        user_id: Option<u32>,
+    },
     ListSourceTransactions {
         #[arg(long)]
         account_id: u32,
@@ -90,9 +188,24 @@ enum AccountsCommand {
         account_id: u32,
         #[arg(long)]
         requisition_id: Option<String>,
+        /// Port to listen on for the bank's redirect, any free port when 0.
+        #[arg(long, default_value_t = 0)]
+        callback_port: u16,
     },
     PopulateAccountsDetails,
     UpdateBalances,
+    /// Show the balances fetched for an account, newest first.
+    BalanceHistory {
+        #[arg(long)]
+        account_id: u32,
+        /// Only show one balance type, like `closingBooked` or `interimAvailable`.
+        #[arg(long)]
+        balance_type: Option<String>,
+        #[arg(long, default_value_t = 50)]
+        limit: u32,
+        #[arg(long, value_enum, default_value = "table")]
+        format: ListFormat,
+    },
     Add {
         #[arg(long)]
         user_id: u32,
@@ -106,6 +219,9 @@ enum AccountsCommand {
         account_id: Option<u32>,
         #[arg(long)]
         requisition_id: Option<String>,
+        /// Port to listen on for the bank's redirect, any free port when 0.
+        #[arg(long, default_value_t = 0)]
+        callback_port: u16,
     }
 }
 
@@ -122,6 +238,15 @@ enum FunctionsCommand {
         #[arg(long)]
         user_id: u32,
     },
+    /// Run a script function against a JSON payload and print its result and console output.
+    Test {
+        #[arg(long)]
+        id: u32,
+        #[arg(long)]
+        payload: String,
+        #[arg(long, default_value = "{}")]
+        params: String,
+    },
 }
 
 #[derive(Subcommand)]
@@ -135,6 +260,34 @@ enum RequisitionsCommand {
         institution_id: String,
         #[arg(long)]
         user_id: u32,
+        /// Days of transaction history to fetch, up to the institution's limit.
+        #[arg(long, default_value_t = nordigen::DEFAULT_MAX_HISTORICAL_DAYS)]
+        max_historical_days: u32,
+        /// Days access lasts before the requisition has to be renewed.
+        #[arg(long, default_value_t = nordigen::DEFAULT_ACCESS_VALID_FOR_DAYS)]
+        access_valid_for_days: u32,
+        #[arg(long, value_delimiter = ',', default_values_t = nordigen::ACCESS_SCOPES.map(String::from))]
+        access_scope: Vec<String>,
+        /// Port to listen on for the bank's redirect, any free port when 0.
+        #[arg(long, default_value_t = 0)]
+        callback_port: u16,
+    },
+    List {
+        #[arg(long)]
+        user_id: Option<u32>,
+    },
+    /// Refresh the status of a requisition, or all of them, from Nordigen.
+    Status {
+        #[arg(long)]
+        id: Option<u32>,
+    },
+    /// Create a new requisition for the same institution and move its accounts over to it.
+    Renew {
+        #[arg(long)]
+        id: u32,
+        /// Port to listen on for the bank's redirect, any free port when 0.
+        #[arg(long, default_value_t = 0)]
+        callback_port: u16,
     },
 }
 
@@ -148,6 +301,9 @@ enum TriggersCommand {
         name: String,
         #[arg(long)]
         params: String,
+        /// JSON array of filter predicates, e.g. '[{"Account": [1]}, "Outgoing"]'
+        #[arg(long, default_value = "[]")]
+        filter: String,
         #[arg(long)]
         user_id: u32,
         #[arg(long)]
@@ -155,6 +311,8 @@ enum TriggersCommand {
     },
     #[command(subcommand)]
     Log(TriggersLogCommand),
+    #[command(subcommand)]
+    Queue(TriggersQueueCommand),
     Run {
         #[arg(long)]
         trigger_id: u32,
@@ -166,11 +324,87 @@ enum TriggersCommand {
 #[derive(Subcommand)]
 enum MerchantsCommand {
     List,
+    #[command(subcommand)]
+    Rules(MerchantRulesCommand),
+    #[command(subcommand)]
+    Cache(MerchantCacheCommand),
+}
+
+#[derive(Subcommand)]
+enum MerchantCacheCommand {
+    /// Lookups paid for and saved by the enrichment cache, per provider.
+    Stats,
+    Clear {
+        #[arg(long, value_parser = Enricher::PROVIDERS)]
+        provider: Option<String>,
+        /// Only remove entries past their TTL.
+        #[arg(long)]
+        expired: bool,
+    },
+}
+
+#[derive(Subcommand)]
+enum MerchantRulesCommand {
+    List {
+        #[arg(long)]
+        user_id: Option<u32>,
+    },
+    Add {
+        /// Text to look for, ignoring case and words with digits in them.
+        #[arg(long)]
+        pattern: String,
+        #[arg(long)]
+        merchant_id: u32,
+        #[arg(long, default_value = MerchantRule::ANY, value_parser = MerchantRule::FIELDS)]
+        field: String,
+        /// Only apply the rule to this user's transactions.
+        #[arg(long)]
+        user_id: Option<u32>,
+    },
+    /// Show which rule a transaction matches, without changing it.
+    Test {
+        #[arg(long)]
+        transaction_id: u32,
+    },
 }
 
 #[derive(Subcommand)]
 enum TriggersLogCommand {
-    List,
+    List {
+        #[arg(long)]
+        trigger_id: Option<u32>,
+        #[arg(long)]
+        status: Option<String>,
+        #[arg(long)]
+        user_id: Option<u32>,
+        #[arg(long)]
+        from: Option<chrono::NaiveDate>,
+        #[arg(long)]
+        to: Option<chrono::NaiveDate>,
+        #[arg(long, default_value = "100")]
+        limit: u32,
+    },
+}
+
+#[derive(Subcommand)]
+enum TriggersQueueCommand {
+    List {
+        #[arg(long)]
+        status: Option<String>,
+    },
+    Process {
+        #[arg(long, default_value = "100")]
+        limit: u32,
+    },
+    Retry {
+        #[arg(long)]
+        id: Option<u32>,
+    },
+    Purge {
+        /// Also delete pending entries, not just failed ones.
+        #[arg(long)]
+        all: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -178,11 +412,19 @@ enum TransactionsCommand {
     List {
         #[arg(long)]
         account_id: Option<u32>,
+        /// Only the user's transactions, with their total in the user's primary currency.
+        #[arg(long)]
+        user_id: Option<u32>,
         #[arg(long)]
         search: Option<String>,
         #[arg(long, default_value = "100")]
         limit: u32,
     },
+    /// Recalculate a user's amounts in their primary currency, such as after updating exchange rates.
+    Convert {
+        #[arg(long)]
+        user_id: u32,
+    },
     Import {
         #[arg(long)]
         account_id: Option<u32>,
@@ -191,20 +433,63 @@ enum TransactionsCommand {
         #[arg(long)]
         id: u32,
     },
+    /// Look up the merchant for a transaction.
     Enrich {
         #[arg(long)]
         id: u32,
+        /// Use only this provider instead of those in MERCHANT_ENRICHERS.
+        #[arg(long, value_parser = Enricher::PROVIDERS)]
+        provider: Option<String>,
+    },
+    AssignMerchants {
+        #[arg(long, value_parser = Enricher::PROVIDERS)]
+        provider: Option<String>,
+    },
+    /// Set a transaction's merchant, and match the user's transactions like it to it from now on.
+    SetMerchant {
+        #[arg(long)]
+        id: u32,
+        #[arg(long)]
+        merchant_id: u32,
     },
-    AssignMerchants {},
 }
 
 #[derive(Subcommand)]
 enum ExchangeRatesCommand {
     Update {
+        #[arg(long, default_value = "exchangerate-api", value_parser = ["exchangerate-api", "ecb", "file"])]
+        provider: String,
+        /// Base currency to fetch, for exchangerate-api.
         #[arg(long)]
-        code: String,
+        code: Option<String>,
+        /// A downloaded ECB eurofxref file, or the CSV or JSON file to import.
+        #[arg(long)]
+        path: Option<String>,
+    },
+    List {
+        #[arg(long)]
+        code: Option<String>,
+        /// Show the rate to this currency instead of how many rates each day has.
+        #[arg(long)]
+        currency: Option<String>,
+        #[arg(long)]
+        from: Option<chrono::NaiveDate>,
+        #[arg(long)]
+        to: Option<chrono::NaiveDate>,
+    },
+    /// Fetch the rates for each day in a range that doesn't have them yet.
+    Backfill {
+        #[arg(long, default_value = "exchangerate-api", value_parser = ["exchangerate-api", "ecb", "file"])]
+        provider: String,
+        #[arg(long)]
+        code: Option<String>,
+        #[arg(long)]
+        path: Option<String>,
+        #[arg(long)]
+        from: chrono::NaiveDate,
+        #[arg(long)]
+        to: Option<chrono::NaiveDate>,
     },
-    List,
 }
 
 #[tokio::main]
@@ -230,16 +515,85 @@ async fn main() -> anyhow::Result<()> {
             UsersCommand::Add {
                 name,
                 email,
+                password,
+                currency,
             } => {
                 let user = NewUser {
                     name: name.clone(),
                     email: email.clone(),
+                    password: match password {
+                        Some(password) => password.clone(),
+                        None => prompt_password()?,
+                    },
+                    primary_currency: currency.as_deref().map(str::parse).transpose()?,
                 }
                 .sqlx_create(&sqlx_pool)
                 .await?;
                 dbg!(user);
                 Ok(())
             }
+            UsersCommand::SetPassword { user_id, password } => {
+                let user = User::sqlx_by_id(*user_id, &sqlx_pool).await?;
+                UpdateUser {
+                    id: Some(user.id),
+                    name: None,
+                    email: None,
+                    password: Some(match password {
+                        Some(password) => password.clone(),
+                        None => prompt_password()?,
+                    }),
+                    primary_currency: None,
+                }
+                .sqlx_update(&sqlx_pool)
+                .await?;
+                let revoked = Session::sqlx_delete_by_user(user.id, &sqlx_pool).await?;
+                println!("Password updated, revoked {} sessions.", revoked);
+                Ok(())
+            }
+            UsersCommand::SetCurrency { user_id, currency } => {
+                let user = UpdateUser {
+                    id: Some(*user_id),
+                    name: None,
+                    email: None,
+                    password: None,
+                    primary_currency: Some(currency.parse()?),
+                }
+                .sqlx_update(&sqlx_pool)
+                .await?;
+                let unconverted = ultrafinance::sqlx_convert_amounts(user.id, &sqlx_pool).await?;
+                println!("Primary currency set to {}.", user.primary_currency);
+                if unconverted > 0 {
+                    println!("{} amounts have no exchange rate to {}, run exchange-rates update.", unconverted, user.primary_currency);
+                }
+                Ok(())
+            }
+            UsersCommand::Sessions(command) => match command {
+                UsersSessionsCommand::List { user_id } => {
+                    let sessions = match user_id {
+                        Some(user_id) => Session::sqlx_by_user(*user_id, &sqlx_pool).await?,
+                        None => Session::sqlx_all(&sqlx_pool).await?,
+                    };
+                    print_stdout(sessions.with_title()).unwrap_or(());
+                    Ok(())
+                }
+                UsersSessionsCommand::Revoke { id, user_id } => {
+                    let revoked = match (id, user_id) {
+                        (Some(id), _) => {
+                            Session::sqlx_by_id(*id, &sqlx_pool).await?.sqlx_delete(&sqlx_pool).await?;
+                            1
+                        }
+                        (None, Some(user_id)) => Session::sqlx_delete_by_user(*user_id, &sqlx_pool).await?,
+                        (None, None) => bail!("Either --id or --user-id is required"),
+                    };
+                    println!("Revoked {} sessions.", revoked);
+                    Ok(())
+                }
+            },
+            UsersCommand::CreateApiKey { user_id } => {
+                let user = User::sqlx_by_id(*user_id, &sqlx_pool).await?;
+                println!("{}", user.sqlx_create_api_key(&sqlx_pool).await?);
+                Ok(())
+            }
         },
         Commands::Accounts(command) => match command {
             AccountsCommand::Add {
@@ -247,8 +601,7 @@ async fn main() -> anyhow::Result<()> {
                 type_,
                 config,
             } => {
-                let source_account =
-                    get_source_account(type_, config).ok_or(anyhow::anyhow!("No source found"))?;
+                let source_account = get_source_account(type_, config)?;
 
                 let mut new_account = NewAccount::from(source_account.details().await?);
                 new_account.user_id = *user_id;
@@ -258,9 +611,17 @@ async fn main() -> anyhow::Result<()> {
                 dbg!(account);
                 Ok(())
             }
-            AccountsCommand::List => {
-                let my_accounts = Account::sqlx_all(&sqlx_pool).await?;
+            AccountsCommand::List { user_id } => {
+                let my_accounts = match user_id {
+                    Some(user_id) => Account::sqlx_by_user(*user_id, &sqlx_pool).await?,
+                    None => Account::sqlx_all(&sqlx_pool).await?,
+                };
+                let balances = my_accounts.iter().map(Account::current_balance).collect::<Vec<_>>();
                 print_stdout(my_accounts.with_title()).unwrap_or(());
+                if let Some(user_id) = user_id {
+                    let (total, unconverted) = Converter::sqlx_for_user(*user_id, None, &sqlx_pool).await?.total(&balances);
+                    print_total(total, unconverted);
+                }
                 Ok(())
             }
 
@@ -279,8 +640,7 @@ async fn main() -> anyhow::Result<()> {
                 Ok(())
             }
             AccountsCommand::ListNordigenTransactions { account_id, format } => {
-                let mut client = Nordigen::new();
-                let _token = client.populate_token().await?;
+                let client = Nordigen::new();
                 let account = Account::sqlx_by_id_only(*account_id, &sqlx_pool).await?;
                 let transactions = client
                     .get_account_transactions(&account.nordigen_id, &None, &None)
@@ -298,8 +658,7 @@ async fn main() -> anyhow::Result<()> {
                 Ok(())
             }
             AccountsCommand::GetNordigenAccount { account_id } => {
-                let mut client = Nordigen::new();
-                client.populate_token().await?;
+                let client = Nordigen::new();
                 let account = Account::sqlx_by_id_only(*account_id, &sqlx_pool).await?;
                 let _nordigen_account = client.get_account(&account.nordigen_id).await?;
                 let _account_details = client.get_account_details(&account.nordigen_id).await?;
@@ -308,63 +667,25 @@ async fn main() -> anyhow::Result<()> {
             AccountsCommand::RenewNordigenRequisition {
                 account_id,
                 requisition_id,
+                callback_port,
             } => {
-                let mut client = Nordigen::new();
-                client.populate_token().await?;
+                let client = Nordigen::new();
                 let account = Account::sqlx_by_id_only(*account_id, &sqlx_pool).await?;
 
-                let nordigen_account = client.get_account(&account.nordigen_id).await?;
-                let requisition = match requisition_id {
-                    Some(requisition_id) => client.get_requisition(&requisition_id).await?,
+                let (requisition, nordigen_requisition) = match requisition_id {
+                    Some(requisition_id) => track_requisition(&client, requisition_id, account.user_id, &sqlx_pool).await?,
                     None => {
-                        let requisition = client
-                            .create_requisition(
-                                &"oob://".to_owned(),
-                                &nordigen_account.institution_id,
-                            )
-                            .await?;
-                        println!("Visit {} to complete setup", requisition.link);
-                        let _input: String = dialoguer::Input::new()
-                            .with_prompt("Press return when completed.")
-                            .interact_text()?;
-                        client.get_requisition(&requisition.id).await?
+                        let nordigen_account = client.get_account(&account.nordigen_id).await?;
+                        let terms = Requisition::sqlx_latest_by_account(account.id, &sqlx_pool)
+                            .await?
+                            .map(|r| r.terms())
+                            .unwrap_or_default();
+                        authorise_requisition(&client, &nordigen_account.institution_id, &terms, account.user_id, *callback_port, &sqlx_pool).await?
                     }
                 };
-
-                if &requisition.status != "LN" {
-                    bail!("Requisition not yet completed.");
-                }
-
-                let account = Account::sqlx_by_id_only(*account_id, &sqlx_pool).await?;
-
-                for account_id in requisition.accounts {
-                    let nordigen_account = client.get_account(&account_id).await?;
-                    let details = nordigen_account.details().await?;
-                    dbg!(&details);
-                    let select_account = Account::sqlx_by_source_account_details(
-                        details,
-                        account.user_id,
-                        &sqlx_pool,
-                    )
-                    .await;
-
-                    let mut account = match select_account {
-                        Ok(a) => a,
-                        Err(e) => {
-                            println!("Error getting account {}: {}, skipping.", account_id, e);
-                            continue;
-                        }
-                    };
-                    account.config = serde_json::to_string(&nordigen_account).ok();
-                    account.sqlx_update(&sqlx_pool).await?;
-                    println!("Account {} updated.", &account_id);
-                }
-
-                Ok(())
+                link_requisition_accounts(&client, &requisition, &nordigen_requisition, false, &sqlx_pool).await
             }
             AccountsCommand::PopulateAccountsDetails => {
-                let mut client = Nordigen::new();
-                client.populate_token().await?;
                 let accounts = Account::sqlx_all(&sqlx_pool).await?;
                 for account in accounts {
                     let Ok(account_source) = account.source() else {
@@ -382,26 +703,39 @@ async fn main() -> anyhow::Result<()> {
                 Ok(())
             }
             AccountsCommand::UpdateBalances {} => {
-                let mut client = Nordigen::new();
-                client.populate_token().await?;
                 let accounts = Account::sqlx_all(&sqlx_pool).await?;
                 for mut account in accounts {
-                    match account.update_balance().await {
+                    match ultrafinance::sqlx_update_balances(&mut account, &sqlx_pool).await {
                         Ok(_) => {
                             println!(
                                 "Updated balance for account {} to {}",
-                                account.id, account.balance
+                                account.id, account.current_balance()
                             );
-                            let _ = account.sqlx_update(&sqlx_pool).await;
-                        }
-                        Err(err) => {
-                            println!("Error updating balance for account {}: {}", account.id, err)
                         }
+                        Err(err) => match err.downcast_ref::<nordigen::RateLimited>() {
+                            Some(limited) => println!("Skipping account {}: {}", account.id, limited),
+                            None => println!("Error updating balance for account {}: {}", account.id, err),
+                        },
                     }
                 }
                 Ok(())
             },
-            AccountsCommand::RelinkNordigenAccount { account_id, requisition_id } => {
+            AccountsCommand::BalanceHistory {
+                account_id,
+                balance_type,
+                limit,
+                format,
+            } => {
+                let account = Account::sqlx_by_id_only(*account_id, &sqlx_pool).await?;
+                let balances =
+                    AccountBalance::sqlx_by_account(account.id, balance_type.as_deref(), *limit, &sqlx_pool).await?;
+                match format {
+                    ListFormat::Json => println!("{}", serde_json::to_string_pretty(&balances)?),
+                    ListFormat::Table => print_stdout(balances.with_title()).unwrap_or(()),
+                }
+                Ok(())
+            }
+            AccountsCommand::RelinkNordigenAccount { account_id, requisition_id, callback_port } => {
                 let account_id: u32 = account_id.unwrap();
                 let account = Account::sqlx_by_id_only(account_id, &sqlx_pool).await?;
                 let nordigen = serde_json::from_str::<nordigen::Account>(&account.config.unwrap())?;
@@ -410,57 +744,20 @@ async fn main() -> anyhow::Result<()> {
                     println!("Account {} is already linked.", account_id);
                     return Ok(());
                 }
-                let mut client = nordigen::Nordigen::new();
-                client.populate_token().await?;
-
-                let nordigen_account = client.get_account(&account.nordigen_id).await?;
+                let client = nordigen::Nordigen::new();
 
-                let requisition = match requisition_id {
-                    Some(requisition_id) => {
-                        client.get_requisition(&requisition_id).await?
-                    }
+                let (requisition, nordigen_requisition) = match requisition_id {
+                    Some(requisition_id) => track_requisition(&client, requisition_id, account.user_id, &sqlx_pool).await?,
                     None => {
-                        let requisition = client
-                        .create_requisition(
-                            &"oob://".to_owned(),
-                            &nordigen_account.institution_id,
-                        )
-                        .await?;
-
-                      println!("Visit {} to complete setup", requisition.link);
-                         let _input: String = dialoguer::Input::new()
-                            .with_prompt("Press return when completed.")
-                            .interact_text()?;
-                        client.get_requisition(&requisition.id).await?
+                        let nordigen_account = client.get_account(&account.nordigen_id).await?;
+                        let terms = Requisition::sqlx_latest_by_account(account.id, &sqlx_pool)
+                            .await?
+                            .map(|r| r.terms())
+                            .unwrap_or_default();
+                        authorise_requisition(&client, &nordigen_account.institution_id, &terms, account.user_id, *callback_port, &sqlx_pool).await?
                     }
                 };
-
-                if &requisition.status != "LN" {
-                    bail!("Requisition not yet completed.");
-                }
-
-                for account_id in requisition.accounts {
-                    let nordigen_account = client.get_account(&account_id).await?;
-                    let details = nordigen_account.details().await?;
-                    let select_account = Account::sqlx_by_source_account_details(
-                        details,
-                        account.user_id,
-                        &sqlx_pool,
-                    )
-                    .await;
-
-                    let mut account = match select_account {
-                        Ok(a) => a,
-                        Err(e) => {
-                            println!("Error getting account {}: {}, skipping.", account_id, e);
-                            continue;
-                        }
-                    };
-                    account.config = serde_json::to_string(&nordigen_account).ok();
-                    account.sqlx_update(&sqlx_pool).await?;
-                    println!("Account {} updated.", &account_id);
-                }
-                Ok(())
+                link_requisition_accounts(&client, &requisition, &nordigen_requisition, false, &sqlx_pool).await
             },
         },
         Commands::Functions(command) => match command {
@@ -485,11 +782,21 @@ async fn main() -> anyhow::Result<()> {
                 new_function.sqlx_create(&sqlx_pool).await?;
                 Ok(())
             }
+            FunctionsCommand::Test { id, payload, params } => {
+                let function = Function::sqlx_by_id(*id, &sqlx_pool).await?;
+                let result = function
+                    .test(TestFunction {
+                        payload: payload.clone(),
+                        params: params.clone(),
+                    })
+                    .await?;
+                println!("{}", serde_json::to_string_pretty(&result)?);
+                Ok(())
+            }
         },
         Commands::Requisitions(command) => match command {
             RequisitionsCommand::ListInstitutions { country } => {
-                let mut client = Nordigen::new();
-                client.populate_token().await?;
+                let client = Nordigen::new();
                 let institutions = client.get_institutions(country).await?;
                 print_stdout(institutions.with_title()).unwrap_or(());
                 Ok(())
@@ -497,31 +804,59 @@ async fn main() -> anyhow::Result<()> {
             RequisitionsCommand::Add {
                 institution_id,
                 user_id,
+                max_historical_days,
+                access_valid_for_days,
+                access_scope,
+                callback_port,
             } => {
-                let mut client = Nordigen::new();
-                client.populate_token().await?;
-                let requisition = client
-                    .create_requisition(&"oob://".to_owned(), institution_id)
-                    .await?;
+                let client = Nordigen::new();
                 let user = User::sqlx_by_id(*user_id, &sqlx_pool).await?;
-                let requisition = client.get_requisition(&requisition.id).await?;
-
-                if &requisition.status != "LN" {
-                    bail!("Requisition not yet completed.");
-                }
-
-                for account_id in requisition.accounts {
-                    let account_details =
-                        accounts::SourceAccount::details(&client.get_account(&account_id).await?)
-                            .await?;
-                    let mut account = NewAccount::from(account_details);
-                    account.user_id = user.id;
-                    let _account = account.sqlx_create(&sqlx_pool).await?;
-                    println!("Account {} added.", &account_id);
+                let terms = nordigen::AgreementTerms {
+                    max_historical_days: *max_historical_days,
+                    access_valid_for_days: *access_valid_for_days,
+                    access_scope: access_scope.clone(),
+                };
+                let (requisition, nordigen_requisition) =
+                    authorise_requisition(&client, institution_id, &terms, user.id, *callback_port, &sqlx_pool).await?;
+                link_requisition_accounts(&client, &requisition, &nordigen_requisition, true, &sqlx_pool).await
+            }
+            RequisitionsCommand::List { user_id } => {
+                let requisitions = match user_id {
+                    Some(user_id) => Requisition::sqlx_by_user(*user_id, &sqlx_pool).await?,
+                    None => Requisition::sqlx_all(&sqlx_pool).await?,
+                };
+                print_stdout(requisitions.with_title()).unwrap_or(());
+                Ok(())
+            }
+            RequisitionsCommand::Status { id } => {
+                let client = Nordigen::new();
+                let requisitions = match id {
+                    Some(id) => vec![Requisition::sqlx_by_id(*id, &sqlx_pool).await?],
+                    None => Requisition::sqlx_all(&sqlx_pool).await?,
+                };
+                let mut updated = vec![];
+                for requisition in requisitions {
+                    let (requisition, _) =
+                        track_requisition(&client, &requisition.nordigen_id, requisition.user_id, &sqlx_pool).await?;
+                    updated.push(requisition);
                 }
-
+                print_stdout(updated.with_title()).unwrap_or(());
                 Ok(())
             }
+            RequisitionsCommand::Renew { id, callback_port } => {
+                let client = Nordigen::new();
+                let requisition = Requisition::sqlx_by_id(*id, &sqlx_pool).await?;
+                let (renewed, nordigen_requisition) = authorise_requisition(
+                    &client,
+                    &requisition.institution_id,
+                    &requisition.terms(),
+                    requisition.user_id,
+                    *callback_port,
+                    &sqlx_pool,
+                )
+                .await?;
+                link_requisition_accounts(&client, &renewed, &nordigen_requisition, false, &sqlx_pool).await
+            }
         },
         Commands::Triggers(command) => match command {
             TriggersCommand::List => {
@@ -534,16 +869,19 @@ async fn main() -> anyhow::Result<()> {
                 event,
                 name,
                 params,
+                filter,
                 user_id,
                 function_id,
             } => {
                 let user = User::sqlx_by_id(*user_id, &sqlx_pool).await?;
                 let function = Function::sqlx_by_id(*function_id, &sqlx_pool).await?;
+                let filter = serde_json::from_str::<TriggerFilter>(filter)?;
+                filter.validate()?;
 
                 let trigger = NewTrigger {
                     event: event.clone(),
                     name: name.clone(),
-                    filter: TriggerFilter(vec![]),
+                    filter,
                     params: params.clone(),
                     user_id: user.id,
                     function_id: function.id,
@@ -554,12 +892,68 @@ async fn main() -> anyhow::Result<()> {
                 Ok(())
             }
             TriggersCommand::Log(command) => match command {
-                TriggersLogCommand::List => {
-                    let log = TriggerLog::sqlx_all(&sqlx_pool).await?;
+                TriggersLogCommand::List {
+                    trigger_id,
+                    status,
+                    user_id,
+                    from,
+                    to,
+                    limit,
+                } => {
+                    let filter = TriggerLogFilter {
+                        trigger_id: *trigger_id,
+                        status: status.clone(),
+                        user_id: *user_id,
+                        from: *from,
+                        to: *to,
+                        limit: Some(*limit),
+                    };
+                    let log = TriggerLog::sqlx_by_filter(&filter, &sqlx_pool).await?;
                     print_stdout(log.with_title()).unwrap_or(());
                     Ok(())
                 }
             },
+            TriggersCommand::Queue(command) => match command {
+                TriggersQueueCommand::List { status } => {
+                    let queue = match status {
+                        Some(status) => TriggerQueue::sqlx_by_status(status, &sqlx_pool).await?,
+                        None => TriggerQueue::sqlx_all(&sqlx_pool).await?,
+                    };
+                    print_stdout(queue.with_title()).unwrap_or(());
+                    Ok(())
+                }
+                TriggersQueueCommand::Process { limit } => {
+                    let results = ultrafinance::process_trigger_queue(*limit, &sqlx_pool).await?;
+                    for (id, result) in &results {
+                        if let Err(e) = result {
+                            println!("Queue entry {} failed: {:#}", id, e);
+                        }
+                    }
+                    println!(
+                        "Processed {} queue entries, {} failed.",
+                        results.len(),
+                        results.values().filter(|r| r.is_err()).count()
+                    );
+                    Ok(())
+                }
+                TriggersQueueCommand::Retry { id } => {
+                    let entries = match id {
+                        Some(id) => vec![TriggerQueue::sqlx_by_id(*id, &sqlx_pool).await?],
+                        None => TriggerQueue::sqlx_by_status(TriggerQueue::FAILED, &sqlx_pool).await?,
+                    };
+                    for mut entry in entries {
+                        entry.sqlx_retry(&sqlx_pool).await?;
+                        println!("Queue entry {} will be retried.", entry.id);
+                    }
+                    Ok(())
+                }
+                TriggersQueueCommand::Purge { all } => {
+                    let status = if *all { None } else { Some(TriggerQueue::FAILED) };
+                    let purged = TriggerQueue::sqlx_purge(status, &sqlx_pool).await?;
+                    println!("Purged {} queue entries.", purged);
+                    Ok(())
+                }
+            },
             TriggersCommand::Run {
                 trigger_id,
                 transaction_id,
@@ -571,15 +965,12 @@ async fn main() -> anyhow::Result<()> {
                     }
                     None => {
                         let mut transactions = vec![];
-                        for filter in trigger.filter.0.iter() {
-                            match filter {
-                                TriggerFilterPredicate::Account(account_ids) => {
-                                    let account =
-                                        Account::sqlx_by_id_only(account_ids[0], &sqlx_pool)
-                                            .await?;
-                                    transactions = sqlx::query_as!(Transaction, "SELECT * FROM transactions WHERE account_id = ? ORDER BY booking_date DESC LIMIT 50", account.id).fetch_all(&sqlx_pool)
-                                    .await?;
-                                }
+                        for transaction in
+                            Transaction::sqlx_by_user(trigger.user_id, 50, &sqlx_pool).await?
+                        {
+                            let merchant = transaction.sqlx_merchant(&sqlx_pool).await?;
+                            if trigger.filter.matches(&transaction, merchant.as_ref()) {
+                                transactions.push(transaction);
                             }
                         }
                         transactions
@@ -600,11 +991,34 @@ async fn main() -> anyhow::Result<()> {
             }
         },
         Commands::Transactions(command) => match command {
-            TransactionsCommand::List { account_id, search, limit } => {
-                let my_transactions = query_as!(Transaction, "SELECT * FROM transactions ORDER BY booking_date DESC LIMIT ?", limit)
-                    .fetch_all(&sqlx_pool)
-                    .await?;
+            TransactionsCommand::List { account_id, user_id, search, limit } => {
+                let mut qb = QueryBuilder::new("SELECT * FROM transactions WHERE 1 = 1");
+                push_transaction_filters(&mut qb, *account_id, *user_id, search.as_deref());
+                qb.push(" ORDER BY booking_date DESC LIMIT ").push_bind(limit);
+                let my_transactions = qb.build_query_as::<Transaction>().fetch_all(&sqlx_pool).await?;
                 print_stdout(my_transactions.with_title()).unwrap_or(());
+                if let Some(user_id) = user_id {
+                    let user = User::sqlx_by_id(*user_id, &sqlx_pool).await?;
+                    // The total covers every matching transaction, not just the ones shown.
+                    let mut qb = QueryBuilder::new("SELECT converted_amount, converted_currency FROM transactions WHERE 1 = 1");
+                    push_transaction_filters(&mut qb, *account_id, Some(*user_id), search.as_deref());
+                    let amounts = qb
+                        .build_query_as::<(Option<ultrafinance::Amount>, Option<Currency>)>()
+                        .fetch_all(&sqlx_pool)
+                        .await?;
+                    let converted = amounts
+                        .iter()
+                        .filter_map(|(amount, currency)| Some(Money::new((*amount)?, (*currency)?)))
+                        .collect::<Vec<_>>();
+                    // Stored conversions are already in the primary currency.
+                    let (total, unconverted) = Converter::new(user.primary_currency, vec![]).total(&converted);
+                    print_total(total, unconverted + amounts.len() - converted.len());
+                }
+                Ok(())
+            }
+            TransactionsCommand::Convert { user_id } => {
+                let unconverted = ultrafinance::sqlx_convert_amounts(*user_id, &sqlx_pool).await?;
+                println!("Converted amounts for user {}, {} without an exchange rate.", user_id, unconverted);
                 Ok(())
             }
             TransactionsCommand::Import { account_id } => {
@@ -622,27 +1036,45 @@ async fn main() -> anyhow::Result<()> {
                         Ok(imported_transactions) => {
                             println!("imported {} transactions for account {}.", imported_transactions.len(), account.id);
                         }
-                        Err(e) => {
-                            println!("Error importing transactions for accont {}: {}", account.id, e);
-                        }
+                        Err(e) => match e.downcast_ref::<nordigen::RateLimited>() {
+                            Some(limited) => println!("Skipping account {}: {}", account.id, limited),
+                            None => println!("Error importing transactions for accont {}: {}", account.id, e),
+                        },
                     }
                 }
 
+                ultrafinance::process_trigger_queue(100, &sqlx_pool).await?;
                 Ok(())
             }
             TransactionsCommand::CreateTrigger { id } => {
                 let transaction = Transaction::sqlx_by_id(*id, &sqlx_pool).await?;
-                ultrafinance::run_triggers_for_transaction(&transaction, &sqlx_pool).await
+                ultrafinance::queue_triggers_for_transaction(&transaction, Trigger::TRANSACTION_CREATED, &sqlx_pool).await?;
+                ultrafinance::process_trigger_queue(100, &sqlx_pool).await?;
+                Ok(())
             }
-            TransactionsCommand::Enrich { id } => {
+            TransactionsCommand::Enrich { id, provider } => {
                 let transaction = Transaction::sqlx_by_id(*id, &sqlx_pool).await?;
-                let merchant = synth_api::Client::new(env::var("SYNTH_API_KEY").unwrap())
-                    .get_merchants(&vec![transaction])
-                    .await?;
-                dbg!(&merchant);
+                let enricher = match provider {
+                    Some(provider) => EnricherChain::from_names(provider, &sqlx_pool)?,
+                    None => EnricherChain::from_env(&sqlx_pool)?,
+                };
+                let transaction = ultrafinance::sqlx_enrich_transactions(vec![transaction], &enricher, &sqlx_pool)
+                    .await?
+                    .remove(0);
+                match transaction.merchant_id {
+                    Some(merchant_id) => {
+                        let merchant = Merchant::sqlx_by_id(merchant_id, &sqlx_pool).await?;
+                        print_stdout(vec![merchant].with_title()).unwrap_or(());
+                    }
+                    None => println!("No merchant found for transaction {}.", transaction.id),
+                }
                 Ok(())
             }
-            TransactionsCommand::AssignMerchants {} => {
+            TransactionsCommand::AssignMerchants { provider } => {
+                let enricher = match provider {
+                    Some(provider) => EnricherChain::from_names(provider, &sqlx_pool)?,
+                    None => EnricherChain::from_env(&sqlx_pool)?,
+                };
                 loop {
                     let transactions_to_do =
                         Transaction::sqlx_without_merchant_limit_100(&sqlx_pool).await?;
@@ -653,13 +1085,22 @@ async fn main() -> anyhow::Result<()> {
                     // Only take the first 10 transactions, as enrichment can timeout
                     let transactions_to_do = transactions_to_do.into_iter().take(10).collect();
                     let enriched =
-                        ultrafinance::sqlx_enrich_transactions(transactions_to_do, &sqlx_pool)
+                        ultrafinance::sqlx_enrich_transactions(transactions_to_do, &enricher, &sqlx_pool)
                             .await?;
                     dbg!(enriched.len());
                 }
 
                 Ok(())
             }
+            TransactionsCommand::SetMerchant { id, merchant_id } => {
+                let transaction = Transaction::sqlx_by_id(*id, &sqlx_pool).await?;
+                let (_, rule) = ultrafinance::sqlx_confirm_merchant(transaction, *merchant_id, &sqlx_pool).await?;
+                match rule {
+                    Some(rule) => println!("Set merchant, and matching \"{}\" to it from now on.", rule.pattern),
+                    None => println!("Set merchant."),
+                }
+                Ok(())
+            }
         },
         Commands::Merchants(command) => match command {
             MerchantsCommand::List => {
@@ -667,17 +1108,302 @@ async fn main() -> anyhow::Result<()> {
                 print_stdout(merchants.with_title()).unwrap_or(());
                 Ok(())
             }
+            MerchantsCommand::Cache(command) => match command {
+                MerchantCacheCommand::Stats => {
+                    let stats = EnrichmentCacheEntry::sqlx_stats(&sqlx_pool).await?;
+                    print_stdout(stats.with_title()).unwrap_or(());
+                    Ok(())
+                }
+                MerchantCacheCommand::Clear { provider, expired } => {
+                    let cleared = EnrichmentCacheEntry::sqlx_clear(provider.as_deref(), *expired, &sqlx_pool).await?;
+                    println!("Cleared {} cached enrichments.", cleared);
+                    Ok(())
+                }
+            },
+            MerchantsCommand::Rules(command) => match command {
+                MerchantRulesCommand::List { user_id } => {
+                    let rules = match user_id {
+                        Some(user_id) => MerchantRule::sqlx_for_user(*user_id, &sqlx_pool).await?,
+                        None => MerchantRule::sqlx_all(&sqlx_pool).await?,
+                    };
+                    print_stdout(rules.with_title()).unwrap_or(());
+                    Ok(())
+                }
+                MerchantRulesCommand::Add { pattern, merchant_id, field, user_id } => {
+                    let merchant = Merchant::sqlx_by_id(*merchant_id, &sqlx_pool).await?;
+                    let rule = NewMerchantRule {
+                        pattern: pattern.clone(),
+                        field: field.clone(),
+                        merchant_id: merchant.id,
+                        user_id: *user_id,
+                        learned: false,
+                    }
+                    .sqlx_create_or_update(&sqlx_pool)
+                    .await?;
+                    print_stdout(vec![rule].with_title()).unwrap_or(());
+                    Ok(())
+                }
+                MerchantRulesCommand::Test { transaction_id } => {
+                    let transaction = Transaction::sqlx_by_id(*transaction_id, &sqlx_pool).await?;
+                    let rules = MerchantRule::sqlx_for_user(transaction.user_id, &sqlx_pool).await?;
+                    match MerchantRule::find_match(&rules, &transaction) {
+                        Some(rule) => {
+                            let merchant = Merchant::sqlx_by_id(rule.merchant_id, &sqlx_pool).await?;
+                            println!("Matches rule {} ({} in {}): {}", rule.id, rule.pattern, rule.field, merchant.name);
+                        }
+                        None => println!("No rule matches transaction {}.", transaction.id),
+                    }
+                    Ok(())
+                }
+            },
         },
         Commands::ExchangeRates(command) => match command {
-            ExchangeRatesCommand::Update { code } => {
-                let exchange_rate =
-                    exchangerate_api::Client::new(env::var("EXCHANGERATE_API_KEY").unwrap())
-                        .get_exchange_rate(&Currency::try_from(code.clone())?)
-                        .await?;
-                exchange_rate.create_or_update(&sqlx_pool).await?;
+            ExchangeRatesCommand::Update { provider, code, path } => {
+                let code = code.as_deref().map(str::parse::<Currency>).transpose()?;
+                let rates = rate_providers::get_provider(provider, code, path.clone())?.latest().await?;
+                for rate in &rates {
+                    rate.create_or_update(&sqlx_pool).await?;
+                }
+                println!("Stored {} days of rates.", rates.len());
+                Ok(())
+            }
+            ExchangeRatesCommand::List { code, currency, from, to } => {
+                let code = code.as_deref().map(str::parse::<Currency>).transpose()?;
+                let rates = ExchangeRate::get_filtered(code, *from, *to, &sqlx_pool).await?;
+                match currency {
+                    Some(currency) => {
+                        let currency = currency.parse::<Currency>()?;
+                        for rate in rates {
+                            match rate.rate(rate.base_code, currency) {
+                                Some(value) => println!("{} 1 {} = {} {}", rate.date, rate.base_code, value, currency),
+                                None => println!("{} no rate from {} to {}", rate.date, rate.base_code, currency),
+                            }
+                        }
+                    }
+                    None => print_stdout(rates.with_title()).unwrap_or(()),
+                }
+                Ok(())
+            }
+            ExchangeRatesCommand::Backfill { provider, code, path, from, to } => {
+                let code = code.as_deref().map(str::parse::<Currency>).transpose()?;
+                let provider = rate_providers::get_provider(provider, code, path.clone())?;
+                let to = to.unwrap_or_else(|| chrono::Local::now().date_naive());
+                let existing = match provider.base_code() {
+                    Some(code) => ExchangeRate::get_dates(&code, *from, to, &sqlx_pool).await?,
+                    None => vec![],
+                };
+                let missing = from
+                    .iter_days()
+                    .take_while(|date| *date <= to)
+                    .filter(|date| !existing.contains(date))
+                    .collect::<Vec<_>>();
+                let rates = provider.history(&missing).await?;
+                for rate in &rates {
+                    rate.create_or_update(&sqlx_pool).await?;
+                }
+                println!("Stored {} days of rates, run transactions convert to use them.", rates.len());
+                Ok(())
+            }
+        },
+        Commands::Server(command) => match command {
+            ServerCommand::Start { port } => server::start(*port, sqlx_pool).await,
+        },
+        Commands::Daemon(command) => match command {
+            DaemonCommand::Start {
+                import_interval,
+                balances_interval,
+                enrich_interval,
+                trigger_queue_interval,
+                requisitions_interval,
+                requisition_expiring_days,
+            } => {
+                let intervals = daemon::Intervals {
+                    import: *import_interval,
+                    balances: *balances_interval,
+                    enrich: *enrich_interval,
+                    trigger_queue: *trigger_queue_interval,
+                    requisitions: *requisitions_interval,
+                };
+                daemon::run(intervals, *requisition_expiring_days, &sqlx_pool).await
+            }
+            DaemonCommand::Status => {
+                let jobs = DaemonJob::sqlx_all(&sqlx_pool).await?;
+                print_stdout(jobs.with_title()).unwrap_or(());
+                Ok(())
+            }
+            DaemonCommand::SetInterval {
+                job,
+                account_id,
+                interval,
+            } => {
+                if DaemonJob::is_per_account(job) != account_id.is_some() {
+                    bail!("--account-id is required for import and balances jobs, and not allowed for others");
+                }
+                if let Some(account_id) = account_id {
+                    Account::sqlx_by_id_only(*account_id, &sqlx_pool).await?;
+                }
+                let mut daemon_job = DaemonJob::sqlx_find_or_create(job, *account_id, &sqlx_pool).await?;
+                daemon_job
+                    .sqlx_set_interval(interval.map(|i| i.num_seconds() as u32), &sqlx_pool)
+                    .await?;
+                print_stdout(vec![daemon_job].with_title()).unwrap_or(());
                 Ok(())
             }
-            ExchangeRatesCommand::List => Ok(()),
         },
     }
 }
+
+/// How long to wait for the user to authorise a requisition with their bank.
+const REQUISITION_CALLBACK_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Create a requisition for the institution and wait for the bank to redirect back once
+/// the user has authorised it in their browser.
+async fn authorise_requisition(
+    client: &Nordigen,
+    institution_id: &String,
+    terms: &nordigen::AgreementTerms,
+    user_id: u32,
+    callback_port: u16,
+    db: &sqlx::MySqlPool,
+) -> anyhow::Result<(Requisition, nordigen::Requisition)> {
+    terms.validate(&client.get_institution(institution_id).await?)?;
+    let agreement = client.create_agreement(institution_id, terms).await?;
+
+    let listener = server::CallbackListener::bind(callback_port)?;
+    let requisition = client
+        .create_requisition(&listener.redirect_url(), institution_id, Some(&agreement.id))
+        .await?;
+    NewRequisition::from_nordigen(&requisition, terms, user_id)
+        .sqlx_create(db)
+        .await?;
+
+    println!("Visit {} to complete setup", requisition.link);
+    listener.wait(REQUISITION_CALLBACK_TIMEOUT).await?;
+
+    let (requisition, nordigen_requisition) = track_requisition(client, &requisition.id, user_id, db).await?;
+    if requisition.status != Requisition::LINKED {
+        bail!("Requisition not yet completed.");
+    }
+    Ok((requisition, nordigen_requisition))
+}
+
+/// Fetch a requisition from Nordigen and store it, or its latest status if it's already stored.
+async fn track_requisition(
+    client: &Nordigen,
+    nordigen_id: &String,
+    user_id: u32,
+    db: &sqlx::MySqlPool,
+) -> anyhow::Result<(Requisition, nordigen::Requisition)> {
+    let nordigen_requisition = client.get_requisition(nordigen_id).await?;
+    let mut requisition = match Requisition::sqlx_by_nordigen_id(nordigen_id, db).await? {
+        Some(requisition) => requisition,
+        None => {
+            let terms = match &nordigen_requisition.agreement {
+                Some(agreement) => nordigen::AgreementTerms::from(&client.get_agreement(agreement).await?),
+                None => nordigen::AgreementTerms::default(),
+            };
+            NewRequisition::from_nordigen(&nordigen_requisition, &terms, user_id)
+                .sqlx_create(db)
+                .await?
+        }
+    };
+    let granted_at = match requisition.is_linking(&nordigen_requisition.status) {
+        true => client.access_granted_at(&nordigen_requisition).await,
+        false => None,
+    };
+    requisition
+        .sqlx_update_status(&nordigen_requisition.status, granted_at, db)
+        .await?;
+    Ok((requisition, nordigen_requisition))
+}
+
+/// Point the user's accounts at the requisition's Nordigen accounts, adding any that
+/// don't exist yet when `create` is set. New accounts get all the history the agreement allows.
+async fn link_requisition_accounts(
+    client: &Nordigen,
+    requisition: &Requisition,
+    nordigen_requisition: &nordigen::Requisition,
+    create: bool,
+    db: &sqlx::MySqlPool,
+) -> anyhow::Result<()> {
+    if requisition.status != Requisition::LINKED {
+        bail!("Requisition not yet completed.");
+    }
+
+    for account_id in &nordigen_requisition.accounts {
+        let nordigen_account = client.get_account(account_id).await?;
+        let details = nordigen_account.details().await?;
+        let existing = Account::sqlx_by_source_account_details(details.clone(), requisition.user_id, db).await;
+
+        let account = match existing {
+            Ok(mut account) => {
+                account.nordigen_id = account_id.clone();
+                account.config = serde_json::to_string(&nordigen_account).ok();
+                let account = account.sqlx_update(db).await?;
+                println!("Account {} updated.", account_id);
+                account
+            }
+            Err(_) if create => {
+                let mut account = NewAccount::from(details);
+                account.account_type = "nordigen".into();
+                account.nordigen_id = account_id.clone();
+                account.config = serde_json::to_string(&nordigen_account).ok();
+                account.user_id = requisition.user_id;
+                let account = account.sqlx_create(db).await?;
+                println!("Account {} added.", account_id);
+
+                if requisition.terms().access_scope.iter().any(|s| s == "transactions") {
+                    let from_date = chrono::Local::now().date_naive()
+                        - chrono::Duration::days(requisition.max_historical_days.into());
+                    match ultrafinance::sqlx_backfill_transactions(&account, from_date, db).await {
+                        Ok(transactions) => println!("Imported {} transactions for account {}.", transactions.len(), account.id),
+                        Err(e) => println!("Error importing history for account {}: {}", account.id, e),
+                    }
+                }
+                account
+            }
+            Err(e) => {
+                println!("Error getting account {}: {}, skipping.", account_id, e);
+                continue;
+            }
+        };
+        requisition.sqlx_link_account(account.id, db).await?;
+    }
+    Ok(())
+}
+
+/// The `WHERE` conditions `transactions list` filters by, after a `WHERE 1 = 1`.
+fn push_transaction_filters(
+    qb: &mut QueryBuilder<'_, sqlx::MySql>,
+    account_id: Option<u32>,
+    user_id: Option<u32>,
+    search: Option<&str>,
+) {
+    if let Some(account_id) = account_id {
+        qb.push(" AND account_id = ").push_bind(account_id);
+    }
+    if let Some(user_id) = user_id {
+        qb.push(" AND user_id = ").push_bind(user_id);
+    }
+    if let Some(search) = search {
+        let search = format!("%{}%", search);
+        qb.push(" AND (creditor_name LIKE ").push_bind(search.clone());
+        qb.push(" OR debtor_name LIKE ").push_bind(search.clone());
+        qb.push(" OR remittance_information LIKE ").push_bind(search).push(")");
+    }
+}
+
+fn print_total(total: Money, unconverted: usize) {
+    match unconverted {
+        0 => println!("Total: {}", total),
+        _ => println!("Total: {} ({} without an exchange rate left out)", total, unconverted),
+    }
+}
+
+fn prompt_password() -> anyhow::Result<String> {
+    dialoguer::Password::new()
+        .with_prompt("Password")
+        .with_confirmation("Confirm password", "Passwords don't match")
+        .interact()
+        .map_err(|e| anyhow::anyhow!(e))
+}
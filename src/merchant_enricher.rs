@@ -0,0 +1,325 @@
+use std::collections::HashMap;
+
+use chrono::Duration;
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::models::merchant_rule::normalize;
+use crate::{
+    daemon, gpt_enricher, ntropy, synth_api, EnrichmentCacheEntry, NewEnrichmentCacheEntry, NewMerchant, Transaction,
+};
+
+/// What a provider found out about a transaction.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Enrichment {
+    pub merchant: NewMerchant,
+    pub labels: Vec<String>,
+    /// `one-off`, `recurring` or `subscription`.
+    pub recurrence: Option<String>,
+    /// Merchant category codes.
+    pub mcc: Vec<i32>,
+    /// From 0 to 1, when the provider reports how sure it is.
+    pub confidence: Option<f32>,
+}
+
+impl From<NewMerchant> for Enrichment {
+    fn from(merchant: NewMerchant) -> Self {
+        Enrichment {
+            labels: merchant
+                .labels
+                .iter()
+                .flat_map(|labels| labels.split(','))
+                .map(|label| label.trim().to_string())
+                .filter(|label| !label.is_empty())
+                .collect(),
+            merchant,
+            recurrence: None,
+            mcc: vec![],
+            confidence: None,
+        }
+    }
+}
+
+pub trait MerchantEnricher {
+    /// Enrichments by transaction id. Transactions the provider couldn't match are left out.
+    fn enrich(
+        &self,
+        transactions: &[Transaction],
+    ) -> impl std::future::Future<Output = Result<HashMap<u32, Enrichment>, anyhow::Error>> + Send;
+}
+
+/// A merchant data provider, by the `--provider` name.
+pub enum Enricher {
+    Synth(synth_api::Client),
+    Ntropy(ntropy::ApiClient),
+    Gpt(gpt_enricher::Client),
+}
+
+impl Enricher {
+    pub const PROVIDERS: [&'static str; 3] = ["synth", "ntropy", "gpt"];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Enricher::Synth(_) => "synth",
+            Enricher::Ntropy(_) => "ntropy",
+            Enricher::Gpt(_) => "gpt",
+        }
+    }
+
+    /// Whether the provider is told whose transactions they are, so its answers (like
+    /// recurrence) depend on the user's other transactions.
+    pub fn uses_account_holder(&self) -> bool {
+        matches!(self, Enricher::Ntropy(_))
+    }
+}
+
+impl MerchantEnricher for Enricher {
+    async fn enrich(&self, transactions: &[Transaction]) -> Result<HashMap<u32, Enrichment>, anyhow::Error> {
+        match self {
+            Enricher::Synth(client) => Ok(client
+                .get_merchants(transactions)
+                .await?
+                .into_iter()
+                .map(|(id, merchant)| (id, merchant.into()))
+                .collect()),
+            Enricher::Ntropy(client) => client.get_merchants(transactions).await,
+            Enricher::Gpt(client) => client.get_merchants(transactions).await,
+        }
+    }
+}
+
+fn api_key(name: &str) -> Result<String, anyhow::Error> {
+    std::env::var(name).map_err(|_| anyhow::anyhow!("{} is needed for merchant enrichment", name))
+}
+
+pub fn get_enricher(name: &str) -> Result<Enricher, anyhow::Error> {
+    let enricher = match name {
+        "synth" => Enricher::Synth(synth_api::Client::new(api_key("SYNTH_API_KEY")?)),
+        "ntropy" => Enricher::Ntropy(ntropy::ApiClient::new(api_key("NTROPY_API_KEY")?)),
+        "gpt" => Enricher::Gpt(gpt_enricher::Client::new(api_key("OPENAI_API_KEY")?)),
+        _ => return Err(anyhow::anyhow!("No merchant enricher called {}", name)),
+    };
+    Ok(enricher)
+}
+
+/// The normalized description, and a key for it along with the direction and country of the
+/// transaction, and its user when `per_user`. `None` when there's no description to go on.
+pub fn cache_key(transaction: &Transaction, per_user: bool) -> Option<(String, String)> {
+    let description = normalize(
+        &[&transaction.creditor_name, &transaction.debtor_name, &transaction.remittance_information]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(" "),
+    );
+    if description.is_empty() {
+        return None;
+    }
+    let direction = match transaction.transaction_amount.is_negative() {
+        true => "outgoing",
+        false => "incoming",
+    };
+    // Enrichers are given the country from the currency too.
+    let country = transaction.transaction_amount_currency.used_by().first().copied().unwrap_or_default();
+    let mut input = format!("{}\n{}\n{}", description, direction, country);
+    if per_user {
+        input.push_str(&format!("\n{}", transaction.user_id));
+    }
+    let key = format!("{:x}", Sha256::digest(input));
+    Some((key, description))
+}
+
+/// Answers from the enricher are kept in `enrichment_cache` for `ttl`, so a description is only
+/// looked up once, however many transactions share it. Descriptions the enricher didn't answer
+/// for are kept for the shorter `not_found_ttl`, as providers also leave out ones that failed.
+pub struct CachedEnricher {
+    pub enricher: Enricher,
+    ttl: Duration,
+    not_found_ttl: Duration,
+    db: sqlx::MySqlPool,
+}
+
+impl CachedEnricher {
+    pub fn new(enricher: Enricher, ttl: Duration, not_found_ttl: Duration, db: &sqlx::MySqlPool) -> Self {
+        Self {
+            enricher,
+            ttl,
+            not_found_ttl,
+            db: db.clone(),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.enricher.name()
+    }
+}
+
+impl MerchantEnricher for CachedEnricher {
+    async fn enrich(&self, transactions: &[Transaction]) -> Result<HashMap<u32, Enrichment>, anyhow::Error> {
+        let per_user = self.enricher.uses_account_holder();
+        let keys = transactions.iter().map(|t| (t.id, cache_key(t, per_user))).collect::<HashMap<_, _>>();
+        let cached = EnrichmentCacheEntry::sqlx_by_keys(
+            self.name(),
+            &keys.values().flatten().map(|(key, _)| key.clone()).collect::<Vec<_>>(),
+            &self.db,
+        )
+        .await?
+        .into_iter()
+        .map(|entry| (entry.cache_key.clone(), entry))
+        .collect::<HashMap<_, _>>();
+
+        let mut enriched = HashMap::new();
+        let mut hits: HashMap<u32, u32> = HashMap::new();
+        // Transactions to look up, and those sharing each key with them.
+        let mut to_enrich: Vec<Transaction> = vec![];
+        let mut lookups: HashMap<String, (String, Vec<u32>)> = HashMap::new();
+        for transaction in transactions {
+            let Some((key, description)) = &keys[&transaction.id] else {
+                to_enrich.push(transaction.clone());
+                continue;
+            };
+            if let Some(entry) = cached.get(key) {
+                *hits.entry(entry.id).or_default() += 1;
+                if let Some(enrichment) = entry.enrichment.as_deref().and_then(|e| serde_json::from_str(e).ok()) {
+                    enriched.insert(transaction.id, enrichment);
+                }
+                continue;
+            }
+            match lookups.get_mut(key) {
+                Some((_, ids)) => ids.push(transaction.id),
+                None => {
+                    lookups.insert(key.clone(), (description.clone(), vec![transaction.id]));
+                    to_enrich.push(transaction.clone());
+                }
+            }
+        }
+        info!("{} enrichment cache: {} hits, {} lookups", self.name(), hits.values().sum::<u32>(), to_enrich.len());
+        EnrichmentCacheEntry::sqlx_add_hits(&hits, &self.db).await?;
+        if to_enrich.is_empty() {
+            return Ok(enriched);
+        }
+
+        let mut results = self.enricher.enrich(&to_enrich).await?;
+        let mut entries = vec![];
+        for (cache_key, (description, ids)) in lookups {
+            let result = results.remove(&ids[0]);
+            let json = result.as_ref().map(serde_json::to_string).transpose()?;
+            let ttl = match result {
+                Some(_) => self.ttl,
+                None => self.not_found_ttl,
+            };
+            entries.push(NewEnrichmentCacheEntry {
+                cache_key,
+                description,
+                enrichment: json,
+                ttl,
+            });
+            if let Some(result) = result {
+                for id in ids {
+                    enriched.insert(id, result.clone());
+                }
+            }
+        }
+        EnrichmentCacheEntry::sqlx_put_all(self.name(), entries, &self.db).await?;
+        // What's left is for transactions without a description to cache by.
+        enriched.extend(results);
+        Ok(enriched)
+    }
+}
+
+/// Providers tried in order, each getting the transactions the ones before it didn't enrich.
+/// A provider that fails is logged and skipped.
+pub struct EnricherChain(pub Vec<CachedEnricher>);
+
+impl EnricherChain {
+    /// The comma separated providers in `MERCHANT_ENRICHERS`, Synth when it isn't set.
+    pub fn from_env(db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        let names = std::env::var("MERCHANT_ENRICHERS").unwrap_or("synth".to_string());
+        Self::from_names(&names, db)
+    }
+
+    /// Answers are cached for `ENRICHMENT_CACHE_TTL`, 30 days by default, and descriptions
+    /// without one for `ENRICHMENT_CACHE_NOT_FOUND_TTL`, a day by default.
+    pub fn from_names(names: &str, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        let ttl = daemon::parse_interval(&std::env::var("ENRICHMENT_CACHE_TTL").unwrap_or("30d".to_string()))?;
+        let not_found_ttl =
+            daemon::parse_interval(&std::env::var("ENRICHMENT_CACHE_NOT_FOUND_TTL").unwrap_or("1d".to_string()))?;
+        names
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(|name| Ok(CachedEnricher::new(get_enricher(name)?, ttl, not_found_ttl, db)))
+            .collect::<Result<Vec<_>, anyhow::Error>>()
+            .map(EnricherChain)
+    }
+}
+
+impl MerchantEnricher for EnricherChain {
+    async fn enrich(&self, transactions: &[Transaction]) -> Result<HashMap<u32, Enrichment>, anyhow::Error> {
+        let mut enriched: HashMap<u32, Enrichment> = HashMap::new();
+        for enricher in &self.0 {
+            let remaining = transactions
+                .iter()
+                .filter(|t| !enriched.contains_key(&t.id))
+                .cloned()
+                .collect::<Vec<_>>();
+            if remaining.is_empty() {
+                break;
+            }
+            match enricher.enrich(&remaining).await {
+                Ok(enrichments) => {
+                    info!("{} enriched {} of {} transactions", enricher.name(), enrichments.len(), remaining.len());
+                    enriched.extend(
+                        enrichments
+                            .into_iter()
+                            .filter(|(id, _)| remaining.iter().any(|t| t.id == *id)),
+                    );
+                }
+                Err(e) => error!("Error enriching transactions with {}: {}", enricher.name(), e),
+            }
+        }
+        Ok(enriched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::test_transaction;
+
+    #[tokio::test]
+    async fn test_enrichment_from_merchant() {
+        let merchant = NewMerchant {
+            name: "Tesco".to_string(),
+            labels: Some("groceries, supermarket,".to_string()),
+            ..Default::default()
+        };
+        let enrichment = Enrichment::from(merchant);
+        assert_eq!(enrichment.labels, vec!["groceries", "supermarket"]);
+        assert_eq!(enrichment.confidence, None);
+
+        let db = sqlx::mysql::MySqlPoolOptions::new()
+            .connect_lazy("mysql://localhost/ultrafinance")
+            .unwrap();
+        assert!(EnricherChain::from_names("nope", &db).is_err());
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let transaction = |creditor_name: &str, amount: &str| test_transaction(Some(creditor_name), None, amount);
+        let tesco = transaction("Tesco Stores 2341", "-12.50");
+        let (key, description) = cache_key(&tesco, false).unwrap();
+        assert_eq!(description, "TESCO STORES");
+        assert_eq!(cache_key(&transaction("TESCO STORES 118", "-3.00"), false).unwrap().0, key);
+        assert_ne!(cache_key(&transaction("TESCO STORES 118", "3.00"), false).unwrap().0, key);
+        assert!(cache_key(&transaction("1234", "-3.00"), false).is_none());
+
+        // Answers about one user's transactions aren't shared with another's.
+        let mut other_user = tesco.clone();
+        other_user.user_id = 2;
+        assert_eq!(cache_key(&other_user, false).unwrap().0, key);
+        assert_ne!(cache_key(&other_user, true).unwrap().0, cache_key(&tesco, true).unwrap().0);
+    }
+}
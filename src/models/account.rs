@@ -1,5 +1,5 @@
-use crate::accounts::{get_source_account, SourceAccount, SourceAccountDetails};
-use crate::ultrafinance::Currency;
+use crate::accounts::{get_source_account, SourceAccount, SourceAccountDetails, SourceBalance};
+use crate::ultrafinance::{Amount, Currency, Money};
 use crate::utils::display_option;
 use anyhow::Result;
 use cli_table::Table;
@@ -22,7 +22,7 @@ pub struct Account {
     pub cash_account_type: Option<String>,
     pub status: String,
     pub details: String,
-    pub balance: f32,
+    pub balance: Amount,
     #[table(title = "Owner Name", display_fn = "display_option")]
     pub owner_name: Option<String>,
     #[table(skip)]
@@ -45,11 +45,19 @@ pub struct Account {
 }
 
 impl Account {
-    pub async fn update_balance(&mut self) -> Result<()> {
-        let balance = self.source()?.balance().await?;
+    /// Fetch every balance from the source, setting `balance` to the main one.
+    pub async fn update_balance(&mut self) -> Result<Vec<SourceBalance>> {
+        let balances = self.source()?.balances().await?;
+        let balance = balances
+            .first()
+            .ok_or(anyhow::anyhow!("No balances found for account {}", self.id))?;
 
-        self.balance = balance.amount.parse::<f32>()?;
-        Ok(())
+        self.balance = balance.amount.amount;
+        Ok(balances)
+    }
+
+    pub fn current_balance(&self) -> Money {
+        Money::new(self.balance, self.currency)
     }
 
     pub fn source(&self) -> Result<Box<impl SourceAccount>> {
@@ -57,7 +65,7 @@ impl Account {
             Some(config) => config,
             None => return Err(anyhow::anyhow!("No config found")),
         };
-        get_source_account(&self.account_type, config).ok_or(anyhow::anyhow!("No source found"))
+        get_source_account(&self.account_type, config)
     }
 
     pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
@@ -123,8 +131,8 @@ impl Account {
             .bind(&self.number)
             .bind(&self.account_type)
             .bind(&self.nordigen_id)
-            .bind(&self.balance)
-            .bind(&self.currency)
+            .bind(self.balance)
+            .bind(self.currency)
             .bind(&self.product)
             .bind(&self.cash_account_type)
             .bind(&self.details)
@@ -232,22 +240,20 @@ pub struct UpdateAccount {
 
 impl UpdateAccount {
     pub async fn sqlx_update(self, db: &sqlx::MySqlPool) -> Result<Account, anyhow::Error> {
-        let result = sqlx::query("UPDATE accounts SET name = ?, number = ?, account_type = ?, nordigen_id = ?, currency = ?, product = ?, cash_account_type = ?, details = ?, owner_name = ?, status = ?, icon = ?, institution_name = ?, config = ?, user_id = ?, updated_at = ? WHERE id = ?")
-            .bind(&self.name)
-            .bind(&self.number)
-            .bind(&self.account_type)
-            .bind(&self.currency)
-            .bind(&self.product)
-            .bind(&self.cash_account_type)
-            .bind(&self.details)
-            .bind(&self.owner_name)
-            .bind(&self.status)
-            .bind(&self.icon)
-            .bind(&self.institution_name)
-            .bind(&self.id)
-            .execute(db)
-            .await?;
-        Account::sqlx_by_id_only(result.last_insert_id() as u32, db).await
+        let id = self.id.ok_or(anyhow::anyhow!("No id found"))?;
+        let mut account = Account::sqlx_by_id_only(id, db).await?;
+        account.name = self.name.unwrap_or(account.name);
+        account.number = self.number.or(account.number);
+        account.account_type = self.account_type.unwrap_or(account.account_type);
+        account.currency = self.currency.map(Currency::from).unwrap_or(account.currency);
+        account.product = self.product.or(account.product);
+        account.cash_account_type = self.cash_account_type.or(account.cash_account_type);
+        account.details = self.details.unwrap_or(account.details);
+        account.owner_name = self.owner_name.or(account.owner_name);
+        account.status = self.status.unwrap_or(account.status);
+        account.icon = self.icon.or(account.icon);
+        account.institution_name = self.institution_name.unwrap_or(account.institution_name);
+        account.sqlx_update(db).await
     }
 }
 
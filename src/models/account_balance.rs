@@ -0,0 +1,209 @@
+use crate::accounts::SourceBalance;
+use crate::ultrafinance::{Amount, Currency, Money};
+
+use super::exchange_rate::Converter;
+use crate::utils::display_option;
+use chrono::{NaiveDate, NaiveDateTime};
+use cli_table::Table;
+use serde::{Deserialize, Serialize};
+
+#[derive(Table, Debug, Serialize, Clone, sqlx::FromRow)]
+pub struct AccountBalance {
+    #[table(title = "Balance ID")]
+    pub id: u32,
+    #[table(title = "Account ID")]
+    pub account_id: u32,
+    #[table(title = "Type")]
+    pub balance_type: String,
+    #[table(title = "Amount")]
+    pub amount: Amount,
+    #[table(title = "Currency")]
+    pub currency: Currency,
+    /// In the user's primary currency.
+    #[table(title = "Converted", display_fn = "display_option")]
+    pub converted_amount: Option<Amount>,
+    #[table(skip)]
+    pub converted_currency: Option<Currency>,
+    #[table(title = "Reference Date", display_fn = "display_option")]
+    pub reference_date: Option<NaiveDate>,
+    #[table(title = "Fetched At")]
+    pub created_at: NaiveDateTime,
+}
+
+/// How a balance type changed between two fetches, sent with `account_balance_updated` events.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BalanceChange {
+    pub balance_type: String,
+    pub currency: Currency,
+    pub old_amount: Option<Amount>,
+    pub new_amount: Amount,
+    pub reference_date: Option<NaiveDate>,
+}
+
+impl BalanceChange {
+    /// Compare freshly fetched balances to the previous balance of each type.
+    pub fn between(previous: &[AccountBalance], current: &[AccountBalance]) -> Vec<Self> {
+        current
+            .iter()
+            .map(|balance| Self {
+                balance_type: balance.balance_type.clone(),
+                currency: balance.currency,
+                old_amount: previous
+                    .iter()
+                    .find(|p| p.balance_type == balance.balance_type)
+                    .map(|p| p.amount),
+                new_amount: balance.amount,
+                reference_date: balance.reference_date,
+            })
+            .collect()
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.old_amount != Some(self.new_amount)
+    }
+}
+
+impl AccountBalance {
+    pub fn converted(&self) -> Option<Money> {
+        Some(Money::new(self.converted_amount?, self.converted_currency?))
+    }
+
+    pub async fn sqlx_by_user(user_id: u32, db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT account_balances.* FROM account_balances \
+            JOIN accounts ON accounts.id = account_balances.account_id WHERE accounts.user_id = ?",
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_set_converted(&mut self, converted: Option<Money>, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        self.converted_amount = converted.as_ref().map(|c| c.amount);
+        self.converted_currency = converted.map(|c| c.currency);
+        sqlx::query("UPDATE account_balances SET converted_amount = ?, converted_currency = ? WHERE id = ?")
+            .bind(self.converted_amount)
+            .bind(self.converted_currency)
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    pub async fn sqlx_by_id(id: u32, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM account_balances WHERE id = ?")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// The account's balance history, newest first.
+    pub async fn sqlx_by_account(
+        account_id: u32,
+        balance_type: Option<&str>,
+        limit: u32,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let mut qb = sqlx::QueryBuilder::new("SELECT * FROM account_balances WHERE account_id = ");
+        qb.push_bind(account_id);
+        if let Some(balance_type) = balance_type {
+            qb.push(" AND balance_type = ").push_bind(balance_type);
+        }
+        qb.push(" ORDER BY id DESC LIMIT ").push_bind(limit);
+        qb.build_query_as::<Self>()
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// The most recent balance of each type.
+    pub async fn sqlx_latest_by_account(account_id: u32, db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT * FROM account_balances WHERE id IN \
+            (SELECT MAX(id) FROM account_balances WHERE account_id = ? GROUP BY balance_type)",
+        )
+        .bind(account_id)
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+}
+
+pub struct NewAccountBalance {
+    pub account_id: u32,
+    pub balance_type: String,
+    pub amount: Amount,
+    pub currency: Currency,
+    pub converted_amount: Option<Money>,
+    pub reference_date: Option<NaiveDate>,
+}
+
+impl NewAccountBalance {
+    pub fn from_source(account_id: u32, balance: SourceBalance, converter: &Converter) -> Self {
+        Self {
+            account_id,
+            balance_type: balance.balance_type,
+            converted_amount: match balance.reference_date {
+                Some(date) => converter.convert_on(&balance.amount, date),
+                None => converter.convert(&balance.amount),
+            },
+            amount: balance.amount.amount,
+            currency: balance.amount.currency,
+            reference_date: balance.reference_date,
+        }
+    }
+
+    pub async fn sqlx_create(self, db: &sqlx::MySqlPool) -> Result<AccountBalance, anyhow::Error> {
+        let result = sqlx::query(
+            "INSERT INTO account_balances (account_id, balance_type, amount, currency, converted_amount, converted_currency, reference_date) VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(self.account_id)
+        .bind(&self.balance_type)
+        .bind(self.amount)
+        .bind(self.currency)
+        .bind(self.converted_amount.as_ref().map(|c| c.amount))
+        .bind(self.converted_amount.map(|c| c.currency))
+        .bind(self.reference_date)
+        .execute(db)
+        .await?;
+        AccountBalance::sqlx_by_id(result.last_insert_id() as u32, db).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_balance_changes() {
+        let balance = |id, balance_type: &str, amount: &str| AccountBalance {
+            id,
+            account_id: 1,
+            balance_type: balance_type.into(),
+            amount: amount.parse().unwrap(),
+            currency: "EUR".parse().unwrap(),
+            converted_amount: None,
+            converted_currency: None,
+            reference_date: None,
+            created_at: NaiveDateTime::default(),
+        };
+        let previous = vec![balance(1, "closingBooked", "10.00"), balance(2, "expected", "5.00")];
+        let current = vec![
+            balance(3, "closingBooked", "12.50"),
+            // The same amount, reported with fewer decimals.
+            balance(4, "expected", "5.0"),
+            balance(5, "interimAvailable", "7.00"),
+        ];
+
+        let changes = BalanceChange::between(&previous, &current);
+        assert_eq!(changes[0].old_amount, Some("10.00".parse().unwrap()));
+        assert_eq!(changes[0].new_amount.to_string(), "12.50");
+        assert_eq!(
+            changes.iter().map(BalanceChange::is_changed).collect::<Vec<_>>(),
+            vec![true, false, true]
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use crate::utils::display_option;
+use chrono::{Duration, NaiveDateTime};
+use cli_table::Table;
+use serde::Serialize;
+
+#[derive(Table, Debug, Serialize, sqlx::FromRow)]
+pub struct DaemonJob {
+    #[table(title = "Job ID")]
+    pub id: u32,
+    #[table(title = "Job")]
+    pub job: String,
+    #[table(title = "Account ID", display_fn = "display_option")]
+    pub account_id: Option<u32>,
+    #[table(title = "Interval (s)", display_fn = "display_option")]
+    pub interval_seconds: Option<u32>,
+    #[table(title = "Last Run", display_fn = "display_option")]
+    pub last_run_at: Option<NaiveDateTime>,
+    #[table(title = "Next Run")]
+    pub next_run_at: NaiveDateTime,
+    #[table(title = "Last Error", display_fn = "display_option")]
+    pub last_error: Option<String>,
+    #[table(title = "Date Created")]
+    pub created_at: NaiveDateTime,
+    #[table(title = "Updated At")]
+    pub updated_at: NaiveDateTime,
+}
+
+impl DaemonJob {
+    pub const IMPORT: &'static str = "import";
+    pub const BALANCES: &'static str = "balances";
+    pub const ENRICH: &'static str = "enrich";
+    pub const TRIGGER_QUEUE: &'static str = "trigger_queue";
+    pub const REQUISITIONS: &'static str = "requisitions";
+    pub const JOBS: [&'static str; 5] = [
+        Self::IMPORT,
+        Self::BALANCES,
+        Self::ENRICH,
+        Self::TRIGGER_QUEUE,
+        Self::REQUISITIONS,
+    ];
+
+    /// Whether the job runs once per account rather than once for the instance.
+    pub fn is_per_account(job: &str) -> bool {
+        job == Self::IMPORT || job == Self::BALANCES
+    }
+
+    pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM daemon_jobs ORDER BY job, account_id")
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_id(id: u32, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM daemon_jobs WHERE id = ?")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Fetch the state for a job, creating it (due straight away) the first time it's seen.
+    pub async fn sqlx_find_or_create(
+        job: &str,
+        account_id: Option<u32>,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Self, anyhow::Error> {
+        let existing = sqlx::query_as::<_, Self>("SELECT * FROM daemon_jobs WHERE job = ? AND account_id <=> ?")
+            .bind(job)
+            .bind(account_id)
+            .fetch_optional(db)
+            .await?;
+        if let Some(existing) = existing {
+            return Ok(existing);
+        }
+
+        let result = sqlx::query("INSERT INTO daemon_jobs (job, account_id, next_run_at) VALUES (?, ?, ?)")
+            .bind(job)
+            .bind(account_id)
+            .bind(chrono::Local::now().naive_local())
+            .execute(db)
+            .await?;
+        Self::sqlx_by_id(result.last_insert_id() as u32, db).await
+    }
+
+    /// Record the outcome of a run and schedule the next one `interval` from now.
+    pub async fn sqlx_record_run(
+        &mut self,
+        error: Option<&anyhow::Error>,
+        interval: Duration,
+        db: &sqlx::MySqlPool,
+    ) -> Result<(), anyhow::Error> {
+        let now = chrono::Local::now().naive_local();
+        self.last_run_at = Some(now);
+        self.next_run_at = now + interval;
+        self.last_error = error.map(|e| format!("{:#}", e));
+        sqlx::query("UPDATE daemon_jobs SET last_run_at = ?, next_run_at = ?, last_error = ? WHERE id = ?")
+            .bind(self.last_run_at)
+            .bind(self.next_run_at)
+            .bind(&self.last_error)
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    /// Override the interval for this job, or go back to the daemon default with `None`. The
+    /// next run moves to the new interval after the last one, or to now when the job has never
+    /// run or goes back to the default, which the daemon then schedules with.
+    pub async fn sqlx_set_interval(
+        &mut self,
+        interval_seconds: Option<u32>,
+        db: &sqlx::MySqlPool,
+    ) -> Result<(), anyhow::Error> {
+        sqlx::query("UPDATE daemon_jobs SET interval_seconds = ?, next_run_at = COALESCE(last_run_at + INTERVAL ? SECOND, ?) WHERE id = ?")
+            .bind(interval_seconds)
+            .bind(interval_seconds)
+            .bind(chrono::Local::now().naive_local())
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        *self = Self::sqlx_by_id(self.id, db).await?;
+        Ok(())
+    }
+}
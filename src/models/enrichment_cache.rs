@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use chrono::{Duration, NaiveDateTime};
+use cli_table::Table;
+use serde::Serialize;
+
+/// A merchant enricher's answer for a normalized description, kept until `expires_at`.
+#[derive(Debug, Clone, sqlx::FromRow)]
+pub struct EnrichmentCacheEntry {
+    pub id: u32,
+    pub provider: String,
+    pub cache_key: String,
+    pub description: String,
+    /// The enrichment as JSON, `None` when the provider found nothing.
+    pub enrichment: Option<String>,
+    /// Calls made to the provider for the key, once each time the entry was fetched.
+    pub lookups: u32,
+    pub hits: u32,
+    pub created_at: NaiveDateTime,
+    pub expires_at: NaiveDateTime,
+}
+
+/// An answer to cache for `ttl`.
+pub struct NewEnrichmentCacheEntry {
+    pub cache_key: String,
+    pub description: String,
+    pub enrichment: Option<String>,
+    pub ttl: Duration,
+}
+
+/// How well the cache is doing for a provider: lookups are paid for, hits were saved.
+#[derive(Table, Debug, Serialize, sqlx::FromRow)]
+pub struct EnrichmentCacheStats {
+    #[table(title = "Provider")]
+    pub provider: String,
+    #[table(title = "Entries")]
+    pub entries: i64,
+    #[table(title = "Not Found")]
+    pub not_found: i64,
+    #[table(title = "Expired")]
+    pub expired: i64,
+    #[table(title = "Lookups")]
+    pub lookups: i64,
+    #[table(title = "Hits")]
+    pub hits: i64,
+}
+
+impl EnrichmentCacheEntry {
+    /// Unexpired entries for any of the keys.
+    pub async fn sqlx_by_keys(
+        provider: &str,
+        keys: &[String],
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        if keys.is_empty() {
+            return Ok(vec![]);
+        }
+        let mut query = sqlx::QueryBuilder::new("SELECT * FROM enrichment_cache WHERE provider = ");
+        query.push_bind(provider);
+        query.push(" AND expires_at > ");
+        query.push_bind(chrono::Local::now().naive_local());
+        query.push(" AND cache_key IN (");
+        let mut separated = query.separated(", ");
+        for key in keys {
+            separated.push_bind(key);
+        }
+        separated.push_unseparated(")");
+        query
+            .build_query_as::<Self>()
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Count hits for entries by id, `hits` times for each.
+    pub async fn sqlx_add_hits(hits: &HashMap<u32, u32>, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        if hits.is_empty() {
+            return Ok(());
+        }
+        let mut query = sqlx::QueryBuilder::new("UPDATE enrichment_cache SET hits = hits + CASE id");
+        for (id, count) in hits {
+            query.push(" WHEN ").push_bind(*id).push(" THEN ").push_bind(*count);
+        }
+        query.push(" ELSE 0 END WHERE id IN (");
+        let mut separated = query.separated(", ");
+        for id in hits.keys() {
+            separated.push_bind(*id);
+        }
+        separated.push_unseparated(")");
+        query.build().execute(db).await.map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    /// Store fresh answers, replacing expired ones for the same keys.
+    pub async fn sqlx_put_all(
+        provider: &str,
+        entries: Vec<NewEnrichmentCacheEntry>,
+        db: &sqlx::MySqlPool,
+    ) -> Result<(), anyhow::Error> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let now = chrono::Local::now().naive_local();
+        let mut query = sqlx::QueryBuilder::new(
+            "INSERT INTO enrichment_cache (provider, cache_key, description, enrichment, created_at, expires_at) ",
+        );
+        query.push_values(entries, |mut row, entry| {
+            row.push_bind(provider)
+                .push_bind(entry.cache_key)
+                .push_bind(entry.description.chars().take(255).collect::<String>())
+                .push_bind(entry.enrichment)
+                .push_bind(now)
+                .push_bind(now + entry.ttl);
+        });
+        query.push(
+            " ON DUPLICATE KEY UPDATE description = VALUES(description), enrichment = VALUES(enrichment), lookups = lookups + 1, created_at = VALUES(created_at), expires_at = VALUES(expires_at)",
+        );
+        query.build().execute(db).await.map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    pub async fn sqlx_stats(db: &sqlx::MySqlPool) -> Result<Vec<EnrichmentCacheStats>, anyhow::Error> {
+        sqlx::query_as::<_, EnrichmentCacheStats>(
+            "SELECT provider, COUNT(*) AS entries, \
+            CAST(SUM(enrichment IS NULL) AS SIGNED) AS not_found, \
+            CAST(SUM(expires_at <= ?) AS SIGNED) AS expired, \
+            CAST(SUM(lookups) AS SIGNED) AS lookups, \
+            CAST(SUM(hits) AS SIGNED) AS hits \
+            FROM enrichment_cache GROUP BY provider ORDER BY provider",
+        )
+        .bind(chrono::Local::now().naive_local())
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Delete cached answers, only the expired ones when `expired_only`. Returns how many.
+    pub async fn sqlx_clear(
+        provider: Option<&str>,
+        expired_only: bool,
+        db: &sqlx::MySqlPool,
+    ) -> Result<u64, anyhow::Error> {
+        let mut query = sqlx::QueryBuilder::new("DELETE FROM enrichment_cache WHERE 1 = 1");
+        if let Some(provider) = provider {
+            query.push(" AND provider = ");
+            query.push_bind(provider);
+        }
+        if expired_only {
+            query.push(" AND expires_at <= ");
+            query.push_bind(chrono::Local::now().naive_local());
+        }
+        Ok(query.build().execute(db).await?.rows_affected())
+    }
+}
@@ -1,23 +1,36 @@
 use std::collections::HashMap;
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDate, NaiveDateTime};
+use cli_table::Table;
+use rust_decimal::{prelude::FromPrimitive, Decimal};
 use serde::{Deserialize, Serialize};
 use sqlx::{mysql::MySqlRow, FromRow, MySql, Row};
 
-use crate::ultrafinance::Currency;
-
-#[derive(Serialize, Deserialize, Clone)]
+use super::User;
+use crate::ultrafinance::{Currency, Money};
 
+#[derive(Serialize, Deserialize, Clone, Table)]
 pub struct ExchangeRate {
+    #[table(title = "Base")]
     pub base_code: Currency,
+    /// The day the rates are for.
+    #[table(title = "Date")]
+    pub date: NaiveDate,
+    #[table(title = "Rates", display_fn = "display_rate_count")]
     pub conversion_rates: HashMap<Currency, f64>,
+    #[table(title = "Last Update")]
     pub last_update: NaiveDateTime,
 }
 
+fn display_rate_count(rates: &HashMap<Currency, f64>) -> impl std::fmt::Display {
+    rates.len()
+}
+
 impl<'a> FromRow<'_, MySqlRow> for ExchangeRate {
     fn from_row(row: &sqlx::mysql::MySqlRow) -> Result<ExchangeRate, sqlx::Error> {
         Ok(ExchangeRate {
             base_code: row.get("base_code"),
+            date: row.get("date"),
             conversion_rates: serde_json::from_str(row.get("conversion_rates")).map_err(|_| {
                 sqlx::Error::TypeNotFound {
                     type_name: "conversion_rates".to_string(),
@@ -29,22 +42,111 @@ impl<'a> FromRow<'_, MySqlRow> for ExchangeRate {
 }
 
 impl ExchangeRate {
+    /// How many `to` one `from` is worth, going through the base currency when it's neither.
+    pub fn rate(&self, from: Currency, to: Currency) -> Option<Decimal> {
+        let rate = |currency: Currency| match currency == self.base_code {
+            true => Some(Decimal::ONE),
+            false => self
+                .conversion_rates
+                .get(&currency)
+                .and_then(|rate| Decimal::from_f64(*rate))
+                .filter(|rate| !rate.is_zero()),
+        };
+        rate(to)?.checked_div(rate(from)?)
+    }
+
+    pub fn convert(&self, money: &Money, to: Currency) -> Option<Money> {
+        money.convert(to, self.rate(money.currency, to)?)
+    }
+
+    pub async fn get_all(db: &sqlx::MySqlPool) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        sqlx::query_as::<MySql, ExchangeRate>("SELECT * FROM exchange_rates ORDER BY base_code, date")
+            .fetch_all(db)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// Rates for the base currency (or all of them) between the days, either end optional.
+    pub async fn get_filtered(
+        code: Option<Currency>,
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        let mut query = sqlx::QueryBuilder::new("SELECT * FROM exchange_rates WHERE 1 = 1");
+        if let Some(code) = code {
+            query.push(" AND base_code = ").push_bind(code.to_string());
+        }
+        if let Some(from) = from {
+            query.push(" AND date >= ").push_bind(from);
+        }
+        if let Some(to) = to {
+            query.push(" AND date <= ").push_bind(to);
+        }
+        query.push(" ORDER BY base_code, date");
+        query
+            .build_query_as::<ExchangeRate>()
+            .fetch_all(db)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// The rates in effect from `since` on: each base currency's latest snapshot from on or
+    /// before the day, and all later ones. Only the latest snapshots without a day.
+    pub async fn get_since(since: Option<NaiveDate>, db: &sqlx::MySqlPool) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        let mut query = sqlx::QueryBuilder::new(
+            "SELECT * FROM exchange_rates r WHERE date >= COALESCE((SELECT MAX(e.date) FROM exchange_rates e WHERE e.base_code = r.base_code",
+        );
+        match since {
+            Some(since) => query.push(" AND e.date <= ").push_bind(since).push("), ").push_bind(since).push(")"),
+            None => query.push("), r.date)"),
+        };
+        query.push(" ORDER BY base_code, date");
+        query
+            .build_query_as::<ExchangeRate>()
+            .fetch_all(db)
+            .await
+            .map_err(|e| e.into())
+    }
+
+    /// The most recent rates for the base currency.
     pub async fn get_by_currency(
         currency: &Currency,
         db: &sqlx::MySqlPool,
     ) -> Result<ExchangeRate, anyhow::Error> {
-        sqlx::query_as::<MySql, ExchangeRate>("SELECT * FROM exchange_rates WHERE base_code = ?")
+        sqlx::query_as::<MySql, ExchangeRate>(
+            "SELECT * FROM exchange_rates WHERE base_code = ? ORDER BY date DESC LIMIT 1",
+        )
+        .bind(currency.to_string())
+        .fetch_one(db)
+        .await
+        .map_err(|e| e.into())
+    }
+
+    /// Days between `from` and `to` that already have rates for the base currency.
+    pub async fn get_dates(
+        currency: &Currency,
+        from: NaiveDate,
+        to: NaiveDate,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<NaiveDate>, anyhow::Error> {
+        sqlx::query_scalar("SELECT date FROM exchange_rates WHERE base_code = ? AND date BETWEEN ? AND ?")
             .bind(currency.to_string())
-            .fetch_one(db)
+            .bind(from)
+            .bind(to)
+            .fetch_all(db)
             .await
             .map_err(|e| e.into())
     }
 
-	pub async fn create(&self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+	/// Store the rates for their day, replacing any fetched earlier that day.
+	pub async fn create_or_update(&self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
 		sqlx::query(
-			"INSERT INTO exchange_rates (base_code, conversion_rates, last_update) VALUES (?, ?, ?)",
+			"INSERT INTO exchange_rates (base_code, date, conversion_rates, last_update) VALUES (?, ?, ?, ?) \
+			ON DUPLICATE KEY UPDATE conversion_rates = VALUES(conversion_rates), last_update = VALUES(last_update)",
 		)
 		.bind(self.base_code.to_string())
+		.bind(self.date)
 		.bind(serde_json::to_string(&self.conversion_rates).unwrap())
 		.bind(self.last_update)
 		.execute(db)
@@ -52,24 +154,119 @@ impl ExchangeRate {
 		.map_err(|e| e.into())
 		.map(|_| ())
 	}
+}
 
-	pub async fn update(&self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
-		sqlx::query(
-			"UPDATE exchange_rates SET conversion_rates = ?, last_update = ? WHERE base_code = ?",
-		)
-		.bind(serde_json::to_string(&self.conversion_rates).unwrap())
-		.bind(self.last_update)
-		.bind(self.base_code.to_string())
-		.execute(db)
-		.await
-		.map_err(|e| e.into())
-		.map(|_| ())
-	}
+/// Converts amounts into a user's primary currency with the stored exchange rates.
+pub struct Converter {
+    pub currency: Currency,
+    /// Snapshots for each base currency, oldest first.
+    rates: HashMap<Currency, Vec<ExchangeRate>>,
+}
 
-	pub async fn create_or_update(&self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
-		match ExchangeRate::get_by_currency(&self.base_code, db).await {
-			Ok(_) => self.update(db).await,
-			Err(_) => self.create(db).await,
-		}
-	}
+impl Converter {
+    pub fn new(currency: Currency, rates: Vec<ExchangeRate>) -> Self {
+        let mut by_base: HashMap<Currency, Vec<ExchangeRate>> = HashMap::new();
+        for rate in rates {
+            by_base.entry(rate.base_code).or_default().push(rate);
+        }
+        for snapshots in by_base.values_mut() {
+            snapshots.sort_by_key(|rate| rate.date);
+        }
+        Self { currency, rates: by_base }
+    }
+
+    /// A converter for amounts from `since` on, or only at the latest rates without a day.
+    pub async fn sqlx_for_user(
+        user_id: u32,
+        since: Option<NaiveDate>,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Self, anyhow::Error> {
+        let user = User::sqlx_by_id(user_id, db).await?;
+        Ok(Self::new(user.primary_currency, ExchangeRate::get_since(since, db).await?))
+    }
+
+    /// Convert at the latest rates. `None` when there's no rate between the currencies.
+    pub fn convert(&self, money: &Money) -> Option<Money> {
+        self.convert_on(money, NaiveDate::MAX)
+    }
+
+    /// Convert at the rates in effect on `date`: the latest snapshot from on or before the day.
+    /// `None` when there's no rate between the currencies yet on that day.
+    pub fn convert_on(&self, money: &Money, date: NaiveDate) -> Option<Money> {
+        if money.currency == self.currency {
+            return Some(money.clone());
+        }
+        // Rates based on either currency don't need to go through a third one.
+        let direct = [money.currency, self.currency].into_iter().filter_map(|base| self.rates.get(&base));
+        direct.chain(self.rates.values()).find_map(|snapshots| {
+            let effective = snapshots.partition_point(|rate| rate.date <= date);
+            snapshots[..effective].last()?.convert(money, self.currency)
+        })
+    }
+
+    /// Sum amounts in any currency, along with how many of them couldn't be converted.
+    pub fn total<'a>(&self, amounts: impl IntoIterator<Item = &'a Money>) -> (Money, usize) {
+        let mut total = Money::zero(self.currency);
+        let mut unconverted = 0;
+        for money in amounts {
+            match self.convert(money).and_then(|converted| (total.clone() + converted).ok()) {
+                Some(sum) => total = sum,
+                None => unconverted += 1,
+            }
+        }
+        (total, unconverted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_convert_through_base_currency() {
+        let currency = |code: &str| code.parse::<Currency>().unwrap();
+        let rates = ExchangeRate {
+            base_code: currency("USD"),
+            date: NaiveDate::default(),
+            conversion_rates: HashMap::from([(currency("EUR"), 0.5), (currency("GBP"), 0.25)]),
+            last_update: NaiveDateTime::default(),
+        };
+        let converter = Converter::new(currency("EUR"), vec![rates]);
+
+        let dollars = Money::parse("10.00", "USD").unwrap();
+        assert_eq!(converter.convert(&dollars).unwrap().to_string(), "5.00 EUR");
+        let pounds = Money::parse("1.00", "GBP").unwrap();
+        assert_eq!(converter.convert(&pounds).unwrap().to_string(), "2.00 EUR");
+        assert!(converter.convert(&Money::parse("1", "JPY").unwrap()).is_none());
+
+        let (total, unconverted) = converter.total(&[
+            dollars,
+            pounds,
+            Money::parse("-0.50", "EUR").unwrap(),
+            Money::parse("100", "JPY").unwrap(),
+        ]);
+        assert_eq!(total.to_string(), "6.50 EUR");
+        assert_eq!(unconverted, 1);
+    }
+
+    #[test]
+    fn test_convert_on_date() {
+        let currency = |code: &str| code.parse::<Currency>().unwrap();
+        let day = |d: u32| NaiveDate::from_ymd_opt(2023, 1, d).unwrap();
+        let snapshot = |date: NaiveDate, eur: f64| ExchangeRate {
+            base_code: currency("USD"),
+            date,
+            conversion_rates: HashMap::from([(currency("EUR"), eur)]),
+            last_update: NaiveDateTime::default(),
+        };
+        let converter = Converter::new(currency("EUR"), vec![snapshot(day(10), 0.8), snapshot(day(5), 0.5)]);
+
+        let dollars = Money::parse("10.00", "USD").unwrap();
+        // No rates yet on the day.
+        assert!(converter.convert_on(&dollars, day(1)).is_none());
+        assert_eq!(converter.convert_on(&dollars, day(5)).unwrap().to_string(), "5.00 EUR");
+        assert_eq!(converter.convert_on(&dollars, day(9)).unwrap().to_string(), "5.00 EUR");
+        assert_eq!(converter.convert_on(&dollars, day(10)).unwrap().to_string(), "8.00 EUR");
+        assert_eq!(converter.convert(&dollars).unwrap().to_string(), "8.00 EUR");
+    }
 }
@@ -2,6 +2,7 @@ use std::collections::HashMap;
 use cli_table::Table;
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
+use crate::functions::script::StdioMsg;
 use crate::ultrafinance::TransactionDestination;
 
 #[derive(Table, Serialize, sqlx::FromRow)]
@@ -22,6 +23,9 @@ pub struct Function {
     pub created_at: chrono::NaiveDateTime,
     #[table(title = "Updated At")]
     pub updated_at: chrono::NaiveDateTime,
+    #[table(skip)]
+    #[sqlx(skip)]
+    pub params: Option<FunctionParams>,
 }
 
 #[derive(Deserialize, Debug, Serialize)]
@@ -34,9 +38,11 @@ pub struct FunctionParam {
 pub type FunctionParams = HashMap<String, FunctionParam>;
 
 impl Function {
-    pub fn get_destination(&self, config: &str) -> Result<Box<dyn TransactionDestination + Send>, anyhow::Error> {
+    pub fn get_destination(&self, config: &str, db: &sqlx::MySqlPool) -> Result<Box<dyn TransactionDestination + Send + Sync>, anyhow::Error> {
         match self.function_type.as_str() {
-            "lunchmoney" => Ok(Box::new(crate::functions::lunchmoney::Lunchmoney::new(config)?) as Box<dyn TransactionDestination + Send>),
+            "lunchmoney" => Ok(Box::new(crate::functions::lunchmoney::Lunchmoney::new(config)?) as Box<dyn TransactionDestination + Send + Sync>),
+            "webhook" => Ok(Box::new(crate::functions::webhook::Webhook::new(config, db)?) as Box<dyn TransactionDestination + Send + Sync>),
+            "script" => Ok(Box::new(crate::functions::script::Script::new(&self.source, config, db)?) as Box<dyn TransactionDestination + Send + Sync>),
             _ => Err(anyhow::anyhow!("No destination found for function type {}", self.function_type)),
         }
     }
@@ -44,10 +50,34 @@ impl Function {
     pub async fn get_params(&self) -> anyhow::Result<FunctionParams> {
         match self.function_type.as_str() {
             "lunchmoney" => crate::functions::lunchmoney::get_params().await,
+            "webhook" => crate::functions::webhook::get_params().await,
+            "script" => crate::functions::script::get_params().await,
             _ => Err(anyhow::anyhow!("No destination found for function type {}", self.function_type)),
         }
     }
 
+    /// Run a script function against an arbitrary payload, without a trigger or transaction.
+    pub async fn test(&self, test: TestFunction) -> Result<TestFunctionResult, anyhow::Error> {
+        if self.function_type != "script" {
+            return Err(anyhow::anyhow!("Only script functions can be tested, {} is a {} function", self.id, self.function_type));
+        }
+        let (result, mut console) = crate::functions::script::run(
+            self.source.clone(),
+            test.payload,
+            test.params,
+            crate::functions::script::Limits::default(),
+        )
+        .await;
+        let result = match result {
+            Ok(result) => result,
+            Err(e) => {
+                console.push(StdioMsg { msg: format!("{:#}", e), is_err: true });
+                "undefined".to_string()
+            }
+        };
+        Ok(TestFunctionResult { result, console })
+    }
+
     pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
         sqlx::QueryBuilder::new("SELECT * FROM functions")
             .build_query_as::<Self>()
@@ -69,13 +99,10 @@ impl Function {
         user_id: u32,
         db: &sqlx::MySqlPool,
     ) -> Result<Self, anyhow::Error> {
-        sqlx::query_as!(
-            Self,
-            "SELECT * FROM functions WHERE id = ? AND user_id = ?",
-            id,
-            user_id
-        )
-        .fetch_one(db)
+        sqlx::query_as::<_, Self>("SELECT * FROM functions WHERE id = ? AND user_id = ?")
+            .bind(id)
+            .bind(user_id)
+            .fetch_one(db)
         .await
         .map_err(|e| anyhow::anyhow!(e))
     }
@@ -84,14 +111,16 @@ impl Function {
         user_id: u32,
         db: &sqlx::MySqlPool,
     ) -> Result<Vec<Self>, anyhow::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM functions WHERE user_id = ?", user_id)
+        sqlx::query_as::<_, Self>("SELECT * FROM functions WHERE user_id = ?")
+            .bind(user_id)
             .fetch_all(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))
     }
 
     pub async fn sqlx_delete(&self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
-        sqlx::query!("DELETE FROM accounts WHERE id = ?", &self.id)
+        sqlx::query("DELETE FROM functions WHERE id = ?")
+            .bind(self.id)
             .execute(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))?;
@@ -123,6 +152,12 @@ impl NewFunction {
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct CreateFunction {
+    pub name: String,
+    pub source: String,
+}
+
 #[derive(Deserialize)]
 
 pub struct UpdateFunction {
@@ -134,14 +169,26 @@ pub struct UpdateFunction {
 impl UpdateFunction {
     pub async fn sqlx_update(self, db: &sqlx::MySqlPool) -> Result<Function, anyhow::Error> {
         let id = self.id.ok_or(anyhow::anyhow!("No id found"))?;
-        sqlx::query!(
-            "UPDATE functions SET name = ?, source = ? WHERE id = ?",
-            self.name,
-            self.source,
-            id
-        )
-        .execute(db)
-        .await?;
+        let function = Function::sqlx_by_id(id, db).await?;
+        sqlx::query("UPDATE functions SET name = ?, source = ?, updated_at = ? WHERE id = ?")
+            .bind(self.name.unwrap_or(function.name))
+            .bind(self.source.unwrap_or(function.source))
+            .bind(chrono::Local::now().naive_local())
+            .bind(id)
+            .execute(db)
+            .await?;
         Function::sqlx_by_id(id, db).await
     }
 }
+
+#[derive(Deserialize, Debug)]
+pub struct TestFunction {
+    pub params: String,
+    pub payload: String,
+}
+
+#[derive(Serialize, Debug)]
+pub struct TestFunctionResult {
+    pub result: String,
+    pub console: Vec<StdioMsg>,
+}
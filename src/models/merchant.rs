@@ -162,7 +162,7 @@ impl Merchant {
     }
 }
 
-#[derive(Default, Debug, Deserialize, Clone)]
+#[derive(Default, Debug, Serialize, Deserialize, Clone)]
 pub struct NewMerchant {
     pub name: String,
     pub logo_url: Option<String>,
@@ -0,0 +1,285 @@
+use crate::utils::display_option;
+use chrono::NaiveDateTime;
+use cli_table::Table;
+use serde::Serialize;
+
+use super::Transaction;
+
+/// Matches transactions to a merchant by their text, so known merchants don't need an enricher.
+#[derive(Table, Debug, Serialize, Clone, sqlx::FromRow)]
+pub struct MerchantRule {
+    #[table(title = "Rule ID")]
+    pub id: u32,
+    /// Stored normalized, and matched anywhere in the field once that's normalized too, so
+    /// case and words with digits in them like store numbers and references don't matter.
+    #[table(title = "Pattern")]
+    pub pattern: String,
+    #[table(title = "Field")]
+    pub field: String,
+    #[table(title = "Merchant ID")]
+    pub merchant_id: u32,
+    #[table(title = "User ID", display_fn = "display_option")]
+    pub user_id: Option<u32>,
+    /// Created from a merchant the user picked, rather than added by hand.
+    #[table(title = "Learned")]
+    pub learned: bool,
+    #[table(title = "Date Created")]
+    pub created_at: NaiveDateTime,
+}
+
+/// Upper case words without digits, so "Tesco Stores 2341" and "TESCO STORES 118" match alike.
+pub fn normalize(text: &str) -> String {
+    text.split_whitespace()
+        .filter(|word| !word.chars().any(|c| c.is_ascii_digit()))
+        .map(str::to_uppercase)
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// A transaction's text as rules match it, normalized and split into words once for all of them.
+pub struct MatchText {
+    pub user_id: u32,
+    pub creditor_name: Vec<String>,
+    pub debtor_name: Vec<String>,
+    pub remittance_information: Vec<String>,
+}
+
+impl From<&Transaction> for MatchText {
+    fn from(transaction: &Transaction) -> Self {
+        let words = |text: &Option<String>| {
+            text.as_deref()
+                .map(normalize)
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(String::from)
+                .collect()
+        };
+        Self {
+            user_id: transaction.user_id,
+            creditor_name: words(&transaction.creditor_name),
+            debtor_name: words(&transaction.debtor_name),
+            remittance_information: words(&transaction.remittance_information),
+        }
+    }
+}
+
+impl MerchantRule {
+    pub const ANY: &'static str = "any";
+    pub const CREDITOR_NAME: &'static str = "creditor_name";
+    pub const DEBTOR_NAME: &'static str = "debtor_name";
+    pub const REMITTANCE_INFORMATION: &'static str = "remittance_information";
+    pub const FIELDS: [&'static str; 4] = [
+        Self::ANY,
+        Self::CREDITOR_NAME,
+        Self::DEBTOR_NAME,
+        Self::REMITTANCE_INFORMATION,
+    ];
+    /// Learned patterns shorter than this are too vague to trust.
+    const MIN_LEARNED_LENGTH: usize = 3;
+    /// Remittance information is free text, so it takes more of it to be sure of a merchant.
+    const MIN_LEARNED_REMITTANCE_LENGTH: usize = 8;
+    /// Words banks put in remittance information for every kind of payment.
+    const REMITTANCE_BOILERPLATE: [&'static str; 24] = [
+        "AT", "BGC", "CARD", "CONTACTLESS", "DD", "DEBIT", "DIRECT", "FASTER", "FPI", "FPO", "FROM",
+        "GUTSCHRIFT", "KARTENZAHLUNG", "LASTSCHRIFT", "ON", "ORDER", "PAYMENT", "POS", "PURCHASE",
+        "SEPA", "SO", "STANDING", "TO", "TRANSFER",
+    ];
+
+    /// Whether the rule's words appear, in order and as whole words, in a transaction's
+    /// normalized text, so `SHELL` doesn't match `SHELLEYS CAFE`. Patterns are stored normalized.
+    pub fn matches(&self, text: &MatchText) -> bool {
+        let pattern = self.pattern.split_whitespace().collect::<Vec<_>>();
+        if pattern.is_empty() || self.user_id.is_some_and(|user_id| user_id != text.user_id) {
+            return false;
+        }
+        let fields = match self.field.as_str() {
+            Self::CREDITOR_NAME => vec![&text.creditor_name],
+            Self::DEBTOR_NAME => vec![&text.debtor_name],
+            Self::REMITTANCE_INFORMATION => vec![&text.remittance_information],
+            _ => vec![&text.creditor_name, &text.debtor_name, &text.remittance_information],
+        };
+        fields
+            .into_iter()
+            .any(|words| words.windows(pattern.len()).any(|window| window == pattern.as_slice()))
+    }
+
+    /// The rule that applies to the transaction. The user's own rules win over everyone's, then
+    /// longer, more specific patterns over shorter ones.
+    pub fn find_match<'a>(rules: &'a [Self], transaction: &Transaction) -> Option<&'a Self> {
+        let text = MatchText::from(transaction);
+        rules
+            .iter()
+            .filter(|rule| rule.matches(&text))
+            .max_by_key(|rule| (rule.user_id.is_some(), rule.pattern.len()))
+    }
+
+    /// A rule for the transaction's counterparty, or its remittance information without the
+    /// bank's boilerplate when there isn't one. `None` when there's nothing distinctive enough
+    /// to go on.
+    pub fn learn_from(transaction: &Transaction, merchant_id: u32) -> Option<NewMerchantRule> {
+        let (field, text) = match transaction.transaction_amount.is_negative() {
+            true => (Self::CREDITOR_NAME, &transaction.creditor_name),
+            false => (Self::DEBTOR_NAME, &transaction.debtor_name),
+        };
+        let (field, pattern) = match text.as_deref().map(normalize).filter(|p| !p.is_empty()) {
+            Some(pattern) if pattern.len() >= Self::MIN_LEARNED_LENGTH => (field, pattern),
+            Some(_) => return None,
+            None => {
+                let pattern = normalize(transaction.remittance_information.as_deref()?)
+                    .split(' ')
+                    .filter(|word| !Self::REMITTANCE_BOILERPLATE.contains(word))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                if pattern.len() < Self::MIN_LEARNED_REMITTANCE_LENGTH {
+                    return None;
+                }
+                (Self::REMITTANCE_INFORMATION, pattern)
+            }
+        };
+        Some(NewMerchantRule {
+            pattern,
+            field: field.to_string(),
+            merchant_id,
+            user_id: Some(transaction.user_id),
+            learned: true,
+        })
+    }
+
+    pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM merchant_rules ORDER BY id")
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// The user's rules and the ones for everyone.
+    pub async fn sqlx_for_user(user_id: u32, db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM merchant_rules WHERE user_id = ? OR user_id IS NULL ORDER BY id")
+            .bind(user_id)
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_id(id: u32, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM merchant_rules WHERE id = ?")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+}
+
+pub struct NewMerchantRule {
+    pub pattern: String,
+    pub field: String,
+    pub merchant_id: u32,
+    pub user_id: Option<u32>,
+    pub learned: bool,
+}
+
+impl NewMerchantRule {
+    /// Adds the rule, or points an existing one with the same pattern at the new merchant. The
+    /// pattern is stored normalized, as it's matched.
+    pub async fn sqlx_create_or_update(mut self, db: &sqlx::MySqlPool) -> Result<MerchantRule, anyhow::Error> {
+        if !MerchantRule::FIELDS.contains(&self.field.as_str()) {
+            anyhow::bail!("Rule field must be one of {}", MerchantRule::FIELDS.join(", "));
+        }
+        self.pattern = normalize(&self.pattern);
+        if self.pattern.is_empty() {
+            anyhow::bail!("Rule pattern needs a word without digits in it");
+        }
+        let existing = sqlx::query_as::<_, MerchantRule>(
+            "SELECT * FROM merchant_rules WHERE user_id <=> ? AND field = ? AND pattern = ?",
+        )
+        .bind(self.user_id)
+        .bind(&self.field)
+        .bind(&self.pattern)
+        .fetch_optional(db)
+        .await?;
+        let id = match existing {
+            Some(existing) => {
+                sqlx::query("UPDATE merchant_rules SET merchant_id = ?, learned = ? WHERE id = ?")
+                    .bind(self.merchant_id)
+                    .bind(self.learned)
+                    .bind(existing.id)
+                    .execute(db)
+                    .await?;
+                existing.id
+            }
+            None => {
+                sqlx::query(
+                    "INSERT INTO merchant_rules (pattern, field, merchant_id, user_id, learned) VALUES (?, ?, ?, ?, ?)",
+                )
+                .bind(&self.pattern)
+                .bind(&self.field)
+                .bind(self.merchant_id)
+                .bind(self.user_id)
+                .bind(self.learned)
+                .execute(db)
+                .await?
+                .last_insert_id() as u32
+            }
+        };
+        MerchantRule::sqlx_by_id(id, db).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::test_transaction;
+
+    fn transaction(creditor_name: Option<&str>, remittance_information: &str) -> Transaction {
+        test_transaction(creditor_name, Some(remittance_information), "-12.50")
+    }
+
+    fn rule(id: u32, pattern: &str, field: &str, user_id: Option<u32>) -> MerchantRule {
+        MerchantRule {
+            id,
+            pattern: pattern.to_string(),
+            field: field.to_string(),
+            merchant_id: id,
+            user_id,
+            learned: false,
+            created_at: NaiveDateTime::default(),
+        }
+    }
+
+    #[test]
+    fn test_match_and_learn() {
+        let tesco = transaction(Some("TESCO STORES 2341"), "CARD 1234 LONDON");
+        let learned = MerchantRule::learn_from(&tesco, 7).unwrap();
+        assert_eq!(learned.pattern, "TESCO STORES");
+        assert_eq!(learned.field, MerchantRule::CREDITOR_NAME);
+
+        let rules = vec![
+            rule(1, "TESCO", MerchantRule::ANY, None),
+            rule(2, "TESCO STORES", MerchantRule::CREDITOR_NAME, Some(1)),
+            rule(3, "LONDON", MerchantRule::DEBTOR_NAME, None),
+            rule(4, "TESCO STORES", MerchantRule::ANY, Some(2)),
+        ];
+        let other_store = transaction(Some("Tesco Stores 118"), "");
+        assert_eq!(MerchantRule::find_match(&rules, &other_store).unwrap().id, 2);
+        let by_remittance = transaction(None, "TESCO PFS 4411");
+        assert_eq!(MerchantRule::find_match(&rules, &by_remittance).unwrap().id, 1);
+        assert!(MerchantRule::find_match(&rules, &transaction(Some("Sainsbury's"), "London")).is_none());
+        // Only whole words match.
+        let shell = vec![rule(5, "SHELL", MerchantRule::ANY, None)];
+        assert!(MerchantRule::find_match(&shell, &transaction(Some("SHELLEYS CAFE"), "")).is_none());
+        assert!(MerchantRule::find_match(&shell, &transaction(None, "CARD SHELL 0042 LEEDS")).is_some());
+
+        assert_eq!(MerchantRule::learn_from(&transaction(None, "DD 12345"), 7).map(|r| r.pattern), None);
+    }
+
+    #[test]
+    fn test_learn_from_remittance() {
+        let learned = MerchantRule::learn_from(&transaction(None, "CARD PAYMENT TO NETFLIX.COM 4411"), 7).unwrap();
+        assert_eq!(learned.pattern, "NETFLIX.COM");
+        assert_eq!(learned.field, MerchantRule::REMITTANCE_INFORMATION);
+
+        // Nothing but the bank's wording, or too little left once it's gone.
+        assert!(MerchantRule::learn_from(&transaction(None, "SEPA Direct Debit 2024-01"), 7).is_none());
+        assert!(MerchantRule::learn_from(&transaction(None, "Card payment to BP 0012"), 7).is_none());
+    }
+}
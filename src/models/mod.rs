@@ -1,16 +1,30 @@
 pub mod account;
+pub mod account_balance;
+pub mod daemon_job;
+pub mod enrichment_cache;
 pub mod function;
 pub mod merchant;
+pub mod merchant_rule;
+pub mod requisition;
+pub mod session;
 pub mod transaction;
 pub mod trigger;
 pub mod trigger_log;
+pub mod trigger_queue;
 pub mod user;
 pub mod exchange_rate;
 
 pub use account::*;
+pub use account_balance::*;
+pub use daemon_job::*;
+pub use enrichment_cache::*;
 pub use function::*;
 pub use merchant::*;
+pub use merchant_rule::*;
+pub use requisition::*;
+pub use session::*;
 pub use transaction::*;
 pub use trigger::*;
 pub use trigger_log::*;
+pub use trigger_queue::*;
 pub use user::*;
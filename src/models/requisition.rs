@@ -0,0 +1,289 @@
+use crate::accounts::nordigen;
+use crate::utils::display_option;
+use chrono::{Duration, NaiveDateTime};
+use cli_table::Table;
+use serde::Serialize;
+
+use super::Account;
+
+#[derive(Table, Debug, Serialize, Clone, sqlx::FromRow)]
+pub struct Requisition {
+    #[table(title = "Requisition ID")]
+    pub id: u32,
+    #[table(title = "Nordigen ID")]
+    pub nordigen_id: String,
+    #[table(title = "Institution ID")]
+    pub institution_id: String,
+    #[table(skip)]
+    pub agreement_id: Option<String>,
+    #[table(title = "Status")]
+    pub status: String,
+    #[table(skip)]
+    pub link: String,
+    #[table(title = "History Days")]
+    pub max_historical_days: u32,
+    #[table(title = "Access Days")]
+    pub access_valid_for_days: u32,
+    #[table(title = "Access Scope")]
+    pub access_scope: String,
+    #[table(title = "Linked At", display_fn = "display_option")]
+    pub linked_at: Option<NaiveDateTime>,
+    #[table(title = "Expires At", display_fn = "display_option")]
+    pub expires_at: Option<NaiveDateTime>,
+    #[table(skip)]
+    pub expiring_notified_at: Option<NaiveDateTime>,
+    #[table(title = "User ID")]
+    #[serde(skip_serializing)]
+    pub user_id: u32,
+    #[table(title = "Date Created")]
+    pub created_at: NaiveDateTime,
+    #[table(title = "Updated At")]
+    pub updated_at: NaiveDateTime,
+}
+
+/// A requisition with the accounts it grants access to, sent with requisition events.
+#[derive(Debug, Serialize)]
+pub struct RequisitionWithAccounts {
+    #[serde(flatten)]
+    pub requisition: Requisition,
+    pub accounts: Vec<Account>,
+}
+
+impl Requisition {
+    /// The end user has authorised access and the accounts can be used.
+    pub const LINKED: &'static str = "LN";
+
+    pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM requisitions ORDER BY created_at")
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_id(id: u32, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM requisitions WHERE id = ?")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_user(user_id: u32, db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM requisitions WHERE user_id = ? ORDER BY created_at")
+            .bind(user_id)
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_nordigen_id(
+        nordigen_id: &str,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM requisitions WHERE nordigen_id = ?")
+            .bind(nordigen_id)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// The most recently created requisition giving access to the account.
+    pub async fn sqlx_latest_by_account(
+        account_id: u32,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT requisitions.* FROM requisitions \
+            JOIN requisition_accounts ON requisition_accounts.requisition_id = requisitions.id \
+            WHERE requisition_accounts.account_id = ? ORDER BY requisitions.created_at DESC LIMIT 1",
+        )
+        .bind(account_id)
+        .fetch_optional(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Linked requisitions that expire before `before` and haven't been notified about yet.
+    /// Requisitions that have been renewed by a newer linked one for their accounts are left out.
+    pub async fn sqlx_expiring(
+        before: NaiveDateTime,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT * FROM requisitions WHERE status = ? AND expires_at <= ? AND expiring_notified_at IS NULL \
+            AND NOT EXISTS (SELECT 1 FROM requisition_accounts \
+                JOIN requisition_accounts renewed_accounts ON renewed_accounts.account_id = requisition_accounts.account_id \
+                JOIN requisitions renewed ON renewed.id = renewed_accounts.requisition_id \
+                WHERE requisition_accounts.requisition_id = requisitions.id \
+                AND renewed.status = ? AND renewed.created_at > requisitions.created_at)",
+        )
+        .bind(Self::LINKED)
+        .bind(before)
+        .bind(Self::LINKED)
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_accounts(&self, db: &sqlx::MySqlPool) -> Result<Vec<Account>, anyhow::Error> {
+        sqlx::query_as::<_, Account>(
+            "SELECT accounts.* FROM accounts \
+            JOIN requisition_accounts ON requisition_accounts.account_id = accounts.id \
+            WHERE requisition_accounts.requisition_id = ?",
+        )
+        .bind(self.id)
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_link_account(&self, account_id: u32, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        sqlx::query("INSERT IGNORE INTO requisition_accounts (requisition_id, account_id) VALUES (?, ?)")
+            .bind(self.id)
+            .bind(account_id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    /// Whether moving to `status` links the requisition for the first time.
+    pub fn is_linking(&self, status: &str) -> bool {
+        status == Self::LINKED && self.linked_at.is_none()
+    }
+
+    /// Store the status reported by Nordigen. Consent starts counting down from when access
+    /// was granted, or now if that isn't known.
+    pub async fn sqlx_update_status(
+        &mut self,
+        status: &str,
+        granted_at: Option<NaiveDateTime>,
+        db: &sqlx::MySqlPool,
+    ) -> Result<(), anyhow::Error> {
+        if self.is_linking(status) {
+            let linked_at = granted_at.unwrap_or_else(|| chrono::Local::now().naive_local());
+            self.linked_at = Some(linked_at);
+            self.expires_at = Some(linked_at + Duration::days(self.access_valid_for_days.into()));
+        }
+        self.status = status.to_string();
+        sqlx::query("UPDATE requisitions SET status = ?, linked_at = ?, expires_at = ? WHERE id = ?")
+            .bind(&self.status)
+            .bind(self.linked_at)
+            .bind(self.expires_at)
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    pub async fn sqlx_mark_expiring_notified(&mut self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        self.expiring_notified_at = Some(chrono::Local::now().naive_local());
+        sqlx::query("UPDATE requisitions SET expiring_notified_at = ? WHERE id = ?")
+            .bind(self.expiring_notified_at)
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    pub fn terms(&self) -> nordigen::AgreementTerms {
+        nordigen::AgreementTerms {
+            max_historical_days: self.max_historical_days,
+            access_valid_for_days: self.access_valid_for_days,
+            access_scope: self.access_scope.split(',').map(String::from).collect(),
+        }
+    }
+
+    /// Days left until consent lapses, if the requisition has been linked.
+    pub fn days_until_expiry(&self, now: NaiveDateTime) -> Option<i64> {
+        self.expires_at.map(|expires_at| (expires_at - now).num_days())
+    }
+}
+
+pub struct NewRequisition {
+    pub nordigen_id: String,
+    pub institution_id: String,
+    pub agreement_id: Option<String>,
+    pub status: String,
+    pub link: String,
+    pub max_historical_days: u32,
+    pub access_valid_for_days: u32,
+    pub access_scope: String,
+    pub user_id: u32,
+}
+
+impl NewRequisition {
+    pub async fn sqlx_create(self, db: &sqlx::MySqlPool) -> Result<Requisition, anyhow::Error> {
+        let result = sqlx::query(
+            "INSERT INTO requisitions (nordigen_id, institution_id, agreement_id, status, link, max_historical_days, access_valid_for_days, access_scope, user_id) VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(&self.nordigen_id)
+        .bind(&self.institution_id)
+        .bind(&self.agreement_id)
+        .bind(&self.status)
+        .bind(&self.link)
+        .bind(self.max_historical_days)
+        .bind(self.access_valid_for_days)
+        .bind(&self.access_scope)
+        .bind(self.user_id)
+        .execute(db)
+        .await?;
+        Requisition::sqlx_by_id(result.last_insert_id() as u32, db).await
+    }
+
+    pub fn from_nordigen(
+        requisition: &nordigen::Requisition,
+        terms: &nordigen::AgreementTerms,
+        user_id: u32,
+    ) -> Self {
+        Self {
+            nordigen_id: requisition.id.clone(),
+            institution_id: requisition.institution_id.clone(),
+            agreement_id: requisition.agreement.clone(),
+            status: requisition.status.clone(),
+            link: requisition.link.clone(),
+            max_historical_days: terms.max_historical_days,
+            access_valid_for_days: terms.access_valid_for_days,
+            access_scope: terms.access_scope.join(","),
+            user_id,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiry_and_terms() {
+        let now = chrono::NaiveDate::from_ymd_opt(2023, 1, 1).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let mut requisition = Requisition {
+            id: 1,
+            nordigen_id: "abc".into(),
+            institution_id: "BANK_XYZ".into(),
+            agreement_id: None,
+            status: Requisition::LINKED.into(),
+            link: "".into(),
+            max_historical_days: 90,
+            access_valid_for_days: 90,
+            access_scope: "balances,details,transactions".into(),
+            linked_at: None,
+            expires_at: None,
+            expiring_notified_at: None,
+            user_id: 1,
+            created_at: now,
+            updated_at: now,
+        };
+        assert_eq!(requisition.days_until_expiry(now), None);
+        assert!(requisition.is_linking(Requisition::LINKED));
+        assert!(!requisition.is_linking("CR"));
+        assert_eq!(requisition.terms(), nordigen::AgreementTerms::default());
+
+        requisition.linked_at = Some(now);
+        assert!(!requisition.is_linking(Requisition::LINKED));
+        requisition.expires_at = Some(now + Duration::days(7));
+        assert_eq!(requisition.days_until_expiry(now), Some(7));
+    }
+}
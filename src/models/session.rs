@@ -0,0 +1,149 @@
+use chrono::{Duration, NaiveDateTime};
+use cli_table::Table;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::User;
+
+/// How long a session is valid for after logging in.
+pub const SESSION_TTL_DAYS: i64 = 30;
+const TOKEN_LENGTH: usize = 48;
+
+#[derive(Table, Debug, Serialize, sqlx::FromRow)]
+pub struct Session {
+    #[table(title = "Session ID")]
+    pub id: u32,
+    #[table(skip)]
+    #[serde(skip_serializing)]
+    pub token_hash: String,
+    #[table(title = "User ID")]
+    pub user_id: u32,
+    #[table(title = "Expires At")]
+    pub expires_at: NaiveDateTime,
+    #[table(title = "Date Created")]
+    pub created_at: NaiveDateTime,
+    #[table(title = "Updated At")]
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct CreateSession {
+    pub email: String,
+    pub password: String,
+}
+
+/// Returned once on login; the token can't be recovered afterwards.
+#[derive(Serialize, Debug)]
+pub struct SessionToken {
+    pub token: String,
+    pub expires_at: NaiveDateTime,
+    pub user: User,
+}
+
+fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+impl Session {
+    pub async fn sqlx_by_id(id: u32, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM sessions WHERE id = ?")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Look up an unexpired session by its bearer token.
+    pub async fn sqlx_by_token(token: &str, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM sessions WHERE token_hash = ? AND expires_at > ?")
+            .bind(hash_token(token))
+            .bind(chrono::Local::now().naive_local())
+            .fetch_one(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM sessions ORDER BY created_at DESC")
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_user(user_id: u32, db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM sessions WHERE user_id = ? ORDER BY created_at DESC")
+            .bind(user_id)
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_delete(&self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM sessions WHERE id = ?")
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    /// Revoke the user's sessions other than `except`, returning how many were removed.
+    pub async fn sqlx_delete_other_by_user(
+        user_id: u32,
+        except: u32,
+        db: &sqlx::MySqlPool,
+    ) -> Result<u64, anyhow::Error> {
+        let result = sqlx::query("DELETE FROM sessions WHERE user_id = ? AND id != ?")
+            .bind(user_id)
+            .bind(except)
+            .execute(db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Revoke every session for the user, returning how many were removed.
+    pub async fn sqlx_delete_by_user(user_id: u32, db: &sqlx::MySqlPool) -> Result<u64, anyhow::Error> {
+        let result = sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+            .bind(user_id)
+            .execute(db)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
+
+#[derive(Debug)]
+pub struct NewSession {
+    pub user_id: u32,
+}
+
+impl NewSession {
+    /// Create the session, returning it along with the plain text token to hand to the client.
+    pub async fn sqlx_create(self, db: &sqlx::MySqlPool) -> Result<(Session, String), anyhow::Error> {
+        let token: String = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(TOKEN_LENGTH)
+            .map(char::from)
+            .collect();
+        let result = sqlx::query("INSERT INTO sessions (token_hash, user_id, expires_at) VALUES (?, ?, ?)")
+            .bind(hash_token(&token))
+            .bind(self.user_id)
+            .bind(chrono::Local::now().naive_local() + Duration::days(SESSION_TTL_DAYS))
+            .execute(db)
+            .await?;
+        Ok((Session::sqlx_by_id(result.last_insert_id() as u32, db).await?, token))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_token() {
+        assert_eq!(
+            hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+}
@@ -1,11 +1,15 @@
-use crate::{accounts::SourceTransaction, ultrafinance::Currency};
+use crate::{
+    accounts::SourceTransaction,
+    ultrafinance::{Amount, Currency, Money},
+};
 use crate::utils::display_option;
+use crate::Merchant;
 use cli_table::Table;
 use serde::{Deserialize, Serialize};
 
 use anyhow::Result;
 
-#[derive(Table, Debug, Serialize, Deserialize)]
+#[derive(Table, Debug, Serialize, Deserialize, Clone)]
 
 #[serde(rename_all = "camelCase")]
 #[derive(sqlx::FromRow)]
@@ -14,21 +18,34 @@ pub struct Transaction {
     pub id: u32,
     #[table(skip)]
     pub external_id: String,
+    #[table(title = "Status")]
+    pub status: String,
     #[table(title = "Creditor Name", display_fn = "display_option")]
     pub creditor_name: Option<String>,
     #[table(title = "Debtor Name", display_fn = "display_option")]
     pub debtor_name: Option<String>,
     #[table(skip)]
+    pub creditor_account: Option<String>,
+    #[table(skip)]
+    pub debtor_account: Option<String>,
+    #[table(skip)]
     pub remittance_information: Option<String>,
     #[table(title = "Booking Date")]
     pub booking_date: chrono::NaiveDate,
     #[table(skip)]
     #[table(title = "Booking Datetime", display_fn = "display_option")]
     pub booking_datetime: Option<chrono::NaiveDateTime>,
+    #[table(skip)]
+    pub value_date: Option<chrono::NaiveDate>,
     #[table(title = "Amount")]
-    pub transaction_amount: String,
+    pub transaction_amount: Amount,
     #[table(title = "Currency")]
     pub transaction_amount_currency: Currency,
+    /// In the user's primary currency.
+    #[table(title = "Converted", display_fn = "display_option")]
+    pub converted_amount: Option<Amount>,
+    #[table(skip)]
+    pub converted_currency: Option<Currency>,
     #[table(skip)]
     pub proprietary_bank_transaction_code: Option<String>,
     #[table(skip)]
@@ -39,6 +56,15 @@ pub struct Transaction {
     pub currency_exchange_target_currency: Option<String>,
     #[table(skip)]
     pub merchant_id: Option<u32>,
+    /// Comma separated categories from the merchant enricher.
+    #[table(skip)]
+    pub labels: Option<String>,
+    /// `one-off`, `recurring` or `subscription`, when the enricher reports it.
+    #[table(skip)]
+    pub recurrence: Option<String>,
+    /// Comma separated merchant category codes.
+    #[table(skip)]
+    pub mcc: Option<String>,
     #[table(title = "Account ID")]
     pub account_id: u32,
     #[table(title = "User ID")]
@@ -51,15 +77,27 @@ pub struct Transaction {
 }
 
 impl Transaction {
+    pub const PENDING: &'static str = "pending";
+    pub const BOOKED: &'static str = "booked";
+
+    pub fn amount(&self) -> Money {
+        Money::new(self.transaction_amount, self.transaction_amount_currency)
+    }
+
+    pub fn converted(&self) -> Option<Money> {
+        Some(Money::new(self.converted_amount?, self.converted_currency?))
+    }
+
     pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM transactions")
+        sqlx::query_as::<_, Self>("SELECT * FROM transactions")
             .fetch_all(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))
     }
 
     pub async fn sqlx_by_id(id: u32, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM transactions WHERE id = ?", id)
+        sqlx::query_as::<_, Self>("SELECT * FROM transactions WHERE id = ?")
+            .bind(id)
             .fetch_one(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))
@@ -70,12 +108,9 @@ impl Transaction {
         user_id: u32,
         db: &sqlx::MySqlPool,
     ) -> Result<Self, anyhow::Error> {
-        sqlx::query_as!(
-            Self,
-            "SELECT * FROM transactions WHERE id = ? AND user_id = ?",
-            id,
-            user_id
-        )
+        sqlx::query_as::<_, Self>("SELECT * FROM transactions WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(user_id)
         .fetch_one(db)
         .await
         .map_err(|e| anyhow::anyhow!(e))
@@ -93,16 +128,36 @@ impl Transaction {
         account_id: u32,
         db: &sqlx::MySqlPool,
     ) -> Result<Vec<Self>, anyhow::Error> {
-        sqlx::query_as!(
-            Self,
-            "SELECT * FROM transactions WHERE account_id = ?",
-            account_id
+        sqlx::query_as::<_, Self>("SELECT * FROM transactions WHERE account_id = ?")
+        .bind(account_id)
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_user(
+        user_id: u32,
+        limit: u32,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT * FROM transactions WHERE user_id = ? ORDER BY booking_date DESC LIMIT ?",
         )
+        .bind(user_id)
+        .bind(limit)
         .fetch_all(db)
         .await
         .map_err(|e| anyhow::anyhow!(e))
     }
 
+    pub async fn sqlx_merchant(&self, db: &sqlx::MySqlPool) -> Result<Option<Merchant>, anyhow::Error> {
+        match self.merchant_id {
+            Some(merchant_id) => Ok(Some(Merchant::sqlx_by_id(merchant_id, db).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Pages start at 1. The offset is computed in 64 bits, so far out pages are just empty.
     pub async fn sqlx_by_user_by_search(
         user_id: u32,
         search: &str,
@@ -125,7 +180,7 @@ impl Transaction {
         .bind(format!("%{}%", search))
         .bind(format!("%{}%", search))
         .bind(format!("%{}%", search))
-        .bind((page-1) * per_page)
+        .bind(u64::from(page.saturating_sub(1)) * u64::from(per_page))
         .bind(per_page)
         .fetch_all(db)
         .await
@@ -135,28 +190,72 @@ impl Transaction {
     pub async fn sqlx_without_merchant_limit_100(
         db: &sqlx::MySqlPool,
     ) -> Result<Vec<Self>, anyhow::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM transactions WHERE merchant_id IS NULL ORDER BY booking_date DESC LIMIT 100")
+        sqlx::query_as::<_, Self>("SELECT * FROM transactions WHERE merchant_id IS NULL ORDER BY booking_date DESC LIMIT 100")
             .fetch_all(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))
     }
 
+    /// Transactions without a merchant that haven't been tried since `tried_before`, the ones
+    /// never tried first (newest first), then the ones tried longest ago.
+    pub async fn sqlx_to_enrich(
+        limit: u32,
+        tried_before: chrono::NaiveDateTime,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT * FROM transactions WHERE merchant_id IS NULL \
+            AND (enrichment_attempted_at IS NULL OR enrichment_attempted_at < ?) \
+            ORDER BY enrichment_attempted_at IS NOT NULL, enrichment_attempted_at, booking_date DESC LIMIT ?",
+        )
+        .bind(tried_before)
+        .bind(limit)
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_mark_enrichment_attempted(ids: &[u32], db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        let mut query = sqlx::QueryBuilder::new("UPDATE transactions SET enrichment_attempted_at = ");
+        query.push_bind(chrono::Local::now().naive_local());
+        query.push(" WHERE id IN (");
+        let mut separated = query.separated(", ");
+        for id in ids {
+            separated.push_bind(*id);
+        }
+        separated.push_unseparated(")");
+        query.build().execute(db).await.map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
     pub async fn sqlx_update(&mut self, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
         self.updated_at = chrono::Local::now().naive_local();
-        sqlx::query("UPDATE transactions SET external_id = ?, creditor_name = ?, debtor_name = ?, remittance_information = ?, booking_date = ?, booking_datetime = ?, transaction_amount = ?, transaction_amount_currency = ?, proprietary_bank_transaction_code = ?, currency_exchange_rate = ?, currency_exchange_source_currency = ?, currency_exchange_target_currency = ?, merchant_id = ?, account_id = ?, user_id = ?, created_at = ?, updated_at = ? WHERE id = ?")
+        sqlx::query("UPDATE transactions SET external_id = ?, status = ?, creditor_name = ?, debtor_name = ?, creditor_account = ?, debtor_account = ?, remittance_information = ?, booking_date = ?, booking_datetime = ?, value_date = ?, transaction_amount = ?, transaction_amount_currency = ?, converted_amount = ?, converted_currency = ?, proprietary_bank_transaction_code = ?, currency_exchange_rate = ?, currency_exchange_source_currency = ?, currency_exchange_target_currency = ?, merchant_id = ?, labels = ?, recurrence = ?, mcc = ?, account_id = ?, user_id = ?, created_at = ?, updated_at = ? WHERE id = ?")
             .bind(&self.external_id)
+            .bind(&self.status)
             .bind(&self.creditor_name)
             .bind(&self.debtor_name)
+            .bind(&self.creditor_account)
+            .bind(&self.debtor_account)
             .bind(&self.remittance_information)
             .bind(&self.booking_date)
             .bind(&self.booking_datetime)
-            .bind(&self.transaction_amount)
-            .bind(&self.transaction_amount_currency)
+            .bind(self.value_date)
+            .bind(self.transaction_amount)
+            .bind(self.transaction_amount_currency)
+            .bind(self.converted_amount)
+            .bind(self.converted_currency)
             .bind(&self.proprietary_bank_transaction_code)
             .bind(&self.currency_exchange_rate)
             .bind(&self.currency_exchange_source_currency)
             .bind(&self.currency_exchange_target_currency)
             .bind(&self.merchant_id)
+            .bind(&self.labels)
+            .bind(&self.recurrence)
+            .bind(&self.mcc)
             .bind(&self.account_id)
             .bind(&self.user_id)
             .bind(&self.created_at)
@@ -166,18 +265,154 @@ impl Transaction {
             .await?;
         Self::sqlx_by_id(self.id, db).await
     }
+
+    pub async fn sqlx_set_converted(&mut self, converted: Option<Money>, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        self.converted_amount = converted.as_ref().map(|c| c.amount);
+        self.converted_currency = converted.map(|c| c.currency);
+        sqlx::query("UPDATE transactions SET converted_amount = ?, converted_currency = ? WHERE id = ?")
+            .bind(self.converted_amount)
+            .bind(self.converted_currency)
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    pub async fn sqlx_by_external_id(
+        account_id: u32,
+        external_id: &str,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Option<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM transactions WHERE account_id = ? AND external_id = ?")
+            .bind(account_id)
+            .bind(external_id)
+            .fetch_optional(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_pending_by_account(
+        account_id: u32,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM transactions WHERE account_id = ? AND status = ? ORDER BY booking_date")
+            .bind(account_id)
+            .bind(Self::PENDING)
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Replace a pending transaction with its booked counterpart, keeping the id (and so the
+    /// merchant, trigger logs etc.) of the pending one.
+    pub async fn sqlx_book(&mut self, booked: NewTransaction, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        self.external_id = booked.external_id;
+        self.status = Self::BOOKED.to_string();
+        self.creditor_name = booked.creditor_name.or(self.creditor_name.take());
+        self.debtor_name = booked.debtor_name.or(self.debtor_name.take());
+        self.creditor_account = booked.creditor_account.or(self.creditor_account.take());
+        self.debtor_account = booked.debtor_account.or(self.debtor_account.take());
+        self.remittance_information = booked.remittance_information.or(self.remittance_information.take());
+        self.booking_date = booked.booking_date;
+        self.booking_datetime = booked.booking_datetime;
+        self.value_date = booked.value_date.or(self.value_date);
+        self.transaction_amount = booked.transaction_amount.amount;
+        self.transaction_amount_currency = booked.transaction_amount.currency;
+        self.converted_amount = booked.converted_amount.as_ref().map(|c| c.amount);
+        self.converted_currency = booked.converted_amount.map(|c| c.currency);
+        self.proprietary_bank_transaction_code = booked.proprietary_bank_transaction_code;
+        self.currency_exchange_rate = booked.currency_exchange_rate;
+        self.currency_exchange_source_currency = booked.currency_exchange_source_currency;
+        self.currency_exchange_target_currency = booked.currency_exchange_target_currency;
+        self.sqlx_update(db).await
+    }
+
+    /// How likely a booked transaction is to be the settlement of this pending one, or `None` if
+    /// it can't be. Card payments can settle days later, for a slightly different amount (tips,
+    /// exchange rates) and with a different description than the pending transaction had.
+    pub fn pending_match_score(&self, booked: &NewTransaction) -> Option<f64> {
+        let days = (booked.booking_date - self.booking_date).num_days();
+        if !(-PENDING_MATCH_DAYS_BEFORE..=PENDING_MATCH_DAYS_AFTER).contains(&days) {
+            return None;
+        }
+        let (pending, amount) = (self.amount(), &booked.transaction_amount);
+        if pending.is_negative() != amount.is_negative() {
+            return None;
+        }
+        // Amounts in another currency can't be the same payment.
+        let difference = (pending.clone() - amount.clone()).ok()?.amount.abs().to_f64()
+            / pending.amount.abs().max(amount.amount.abs()).to_f64().max(0.01);
+        let similarity = description_similarity(
+            &[&self.creditor_name, &self.debtor_name, &self.remittance_information],
+            &[&booked.creditor_name, &booked.debtor_name, &booked.remittance_information],
+        );
+        // A different amount needs a similar description to be the same payment.
+        let exact = difference < 0.005;
+        if difference > PENDING_MATCH_MAX_AMOUNT_DIFFERENCE || (!exact && similarity < PENDING_MATCH_MIN_SIMILARITY) {
+            return None;
+        }
+        Some((1.0 - difference) + similarity - days.abs() as f64 / PENDING_MATCH_DAYS_AFTER as f64)
+    }
+}
+
+const PENDING_MATCH_DAYS_BEFORE: i64 = 2;
+const PENDING_MATCH_DAYS_AFTER: i64 = 10;
+const PENDING_MATCH_MAX_AMOUNT_DIFFERENCE: f64 = 0.2;
+const PENDING_MATCH_MIN_SIMILARITY: f64 = 0.3;
+
+/// Jaccard similarity of the words (of three letters or more) in two descriptions.
+fn description_similarity(a: &[&Option<String>], b: &[&Option<String>]) -> f64 {
+    let words = |fields: &[&Option<String>]| {
+        fields
+            .iter()
+            .filter_map(|f| f.as_deref())
+            .flat_map(|f| f.split(|c: char| !c.is_alphabetic()))
+            .filter(|w| w.chars().count() >= 3)
+            .map(|w| w.to_lowercase())
+            .collect::<std::collections::HashSet<_>>()
+    };
+    let (a, b) = (words(a), words(b));
+    if a.is_empty() || b.is_empty() {
+        return 0.0;
+    }
+    a.intersection(&b).count() as f64 / a.union(&b).count() as f64
+}
+
+#[derive(Serialize, Debug)]
+pub struct TransactionWithMerchant {
+    #[serde(flatten)]
+    pub transaction: Transaction,
+    pub merchant: Option<Merchant>,
+}
+
+impl TransactionWithMerchant {
+    pub async fn sqlx_from_transaction(
+        transaction: Transaction,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Self, anyhow::Error> {
+        let merchant = transaction.sqlx_merchant(db).await?;
+        Ok(Self {
+            transaction,
+            merchant,
+        })
+    }
 }
 
 #[derive(Debug)]
 pub struct NewTransaction {
     pub external_id: String,
+    pub status: String,
     pub creditor_name: Option<String>,
     pub debtor_name: Option<String>,
+    pub creditor_account: Option<String>,
+    pub debtor_account: Option<String>,
     pub remittance_information: Option<String>,
     pub booking_date: chrono::NaiveDate,
     pub booking_datetime: Option<chrono::NaiveDateTime>,
-    pub transaction_amount: String,
-    pub transaction_amount_currency: String,
+    pub value_date: Option<chrono::NaiveDate>,
+    pub transaction_amount: Money,
+    pub converted_amount: Option<Money>,
     pub proprietary_bank_transaction_code: Option<String>,
     pub currency_exchange_rate: Option<String>,
     pub currency_exchange_source_currency: Option<String>,
@@ -190,13 +425,20 @@ impl From<SourceTransaction> for NewTransaction {
     fn from(transaction: SourceTransaction) -> Self {
         Self {
             external_id: transaction.id,
+            status: match transaction.pending {
+                true => Transaction::PENDING.to_string(),
+                false => Transaction::BOOKED.to_string(),
+            },
             creditor_name: transaction.creditor_name,
             debtor_name: transaction.debtor_name,
+            creditor_account: transaction.creditor_account,
+            debtor_account: transaction.debtor_account,
             remittance_information: transaction.remittance_information,
             booking_date: transaction.booking_date,
             booking_datetime: transaction.booking_datetime,
-            transaction_amount: transaction.transaction_amount.amount,
-            transaction_amount_currency: transaction.transaction_amount.currency,
+            value_date: transaction.value_date,
+            transaction_amount: transaction.transaction_amount,
+            converted_amount: None,
             proprietary_bank_transaction_code: transaction.proprietary_bank_transaction_code,
             currency_exchange_rate: transaction.currency_exchange_rate,
             currency_exchange_source_currency: transaction.currency_exchange_source_currency,
@@ -206,3 +448,103 @@ impl From<SourceTransaction> for NewTransaction {
         }
     }
 }
+
+/// A booked GBP transaction for user 1, for tests elsewhere that only care about its text and amount.
+#[cfg(test)]
+pub fn test_transaction(creditor_name: Option<&str>, remittance_information: Option<&str>, amount: &str) -> Transaction {
+    let date = chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap();
+    Transaction {
+        id: 1,
+        external_id: "abc".into(),
+        status: Transaction::BOOKED.into(),
+        creditor_name: creditor_name.map(String::from),
+        debtor_name: None,
+        creditor_account: None,
+        debtor_account: None,
+        remittance_information: remittance_information.map(String::from),
+        booking_date: date,
+        booking_datetime: None,
+        value_date: None,
+        transaction_amount: amount.parse().unwrap(),
+        transaction_amount_currency: Currency::from("GBP".to_string()),
+        converted_amount: None,
+        converted_currency: None,
+        proprietary_bank_transaction_code: None,
+        currency_exchange_rate: None,
+        currency_exchange_source_currency: None,
+        currency_exchange_target_currency: None,
+        merchant_id: None,
+        labels: None,
+        recurrence: None,
+        mcc: None,
+        account_id: 1,
+        user_id: 1,
+        created_at: date.and_hms_opt(0, 0, 0).unwrap(),
+        updated_at: date.and_hms_opt(0, 0, 0).unwrap(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+
+    fn pending(amount: &str, creditor: &str, day: u32) -> Transaction {
+        let date = NaiveDate::from_ymd_opt(2024, 3, day).unwrap();
+        Transaction {
+            external_id: "pending:1".into(),
+            status: Transaction::PENDING.into(),
+            booking_date: date,
+            transaction_amount_currency: Currency::from("EUR".to_string()),
+            account_id: 3,
+            created_at: date.and_hms_opt(0, 0, 0).unwrap(),
+            updated_at: date.and_hms_opt(0, 0, 0).unwrap(),
+            ..test_transaction(Some(creditor), None, amount)
+        }
+    }
+
+    fn booked(amount: &str, creditor: &str, day: u32) -> NewTransaction {
+        NewTransaction {
+            external_id: "booked:1".into(),
+            status: Transaction::BOOKED.into(),
+            creditor_name: Some(creditor.into()),
+            debtor_name: None,
+            creditor_account: None,
+            debtor_account: None,
+            remittance_information: None,
+            booking_date: NaiveDate::from_ymd_opt(2024, 3, day).unwrap(),
+            booking_datetime: None,
+            value_date: None,
+            transaction_amount: Money::parse(amount, "EUR").unwrap(),
+            converted_amount: None,
+            proprietary_bank_transaction_code: None,
+            currency_exchange_rate: None,
+            currency_exchange_source_currency: None,
+            currency_exchange_target_currency: None,
+            account_id: 3,
+            user_id: 1,
+        }
+    }
+
+    #[test]
+    fn test_pending_match_score() {
+        let coffee = pending("-12.50", "COFFEE CO LONDON", 1);
+        // Same amount, settled a few days later under a different name.
+        assert!(coffee.pending_match_score(&booked("-12.50", "Card payment", 3)).is_some());
+        // A tip was added, the description still matches.
+        assert!(coffee.pending_match_score(&booked("-14.00", "Coffee Co", 3)).is_some());
+        // A different amount with an unrelated description isn't the same payment.
+        assert!(coffee.pending_match_score(&booked("-14.00", "Bakery", 3)).is_none());
+        // Too long after the pending transaction, or in the other direction.
+        assert!(coffee.pending_match_score(&booked("-12.50", "Coffee Co", 20)).is_none());
+        assert!(coffee.pending_match_score(&booked("12.50", "Coffee Co", 3)).is_none());
+        // Or in another currency.
+        let mut dollars = booked("-12.50", "Coffee Co", 3);
+        dollars.transaction_amount.currency = Currency::from("USD".to_string());
+        assert!(coffee.pending_match_score(&dollars).is_none());
+
+        let exact = coffee.pending_match_score(&booked("-12.50", "Coffee Co", 2)).unwrap();
+        let later = coffee.pending_match_score(&booked("-12.50", "Coffee Co", 6)).unwrap();
+        assert!(exact > later);
+    }
+}
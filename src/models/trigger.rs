@@ -1,20 +1,57 @@
+use chrono::{Datelike, NaiveDate, Weekday};
 use cli_table::Table;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Instant};
 
 use anyhow::Result;
 
-use crate::models::Transaction;
+use crate::models::{Merchant, Transaction};
+use crate::ultrafinance::Currency;
 
-use super::Function;
+use super::{Function, NewTriggerLog, TriggerLog};
 
-#[derive(Deserialize, Serialize, Debug)]
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq)]
+
+pub enum TextField {
+    Creditor,
+    Debtor,
+    Remittance,
+    /// Any of the creditor, debtor or remittance text.
+    Any,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 
 pub enum TriggerFilterPredicate {
     Account(Vec<u32>),
+    /// Money coming into the account.
+    Incoming,
+    /// Money leaving the account.
+    Outgoing,
+    /// Signed amount, inclusive on both ends.
+    Amount { min: Option<f64>, max: Option<f64> },
+    /// Amount ignoring its sign, inclusive on both ends.
+    AbsoluteAmount { min: Option<f64>, max: Option<f64> },
+    Currency(Vec<Currency>),
+    /// Case-insensitive substring match.
+    Contains { field: TextField, text: String },
+    Regex { field: TextField, pattern: RegexPattern },
+    MerchantId(Vec<u32>),
+    /// Case-insensitive match against the merchant's labels.
+    MerchantLabel(String),
+    Weekday(Vec<Weekday>),
+    BookingDate {
+        from: Option<NaiveDate>,
+        to: Option<NaiveDate>,
+    },
+    Any(Vec<TriggerFilterPredicate>),
+    All(Vec<TriggerFilterPredicate>),
+    Not(Box<TriggerFilterPredicate>),
 }
 
+/// Predicates that must all match. Stored as JSON in the `triggers.filter` column.
 #[derive(Deserialize, Serialize, Debug, Default)]
 
 pub struct TriggerFilter(pub Vec<TriggerFilterPredicate>);
@@ -29,19 +66,147 @@ impl From<String> for TriggerParams {
     }
 }
 
-impl TriggerFilter {
-    pub fn matches(&self, transaction: &Transaction) -> bool {
-        for filter in &self.0 {
-            let matches = match filter {
-                TriggerFilterPredicate::Account(account_ids) => {
-                    account_ids.contains(&transaction.account_id)
+/// A regex pattern, compiled once when the filter is loaded rather than for every transaction.
+/// Stored as the pattern text, and kept even when it doesn't compile so `validate` can report it.
+#[derive(Debug, Clone)]
+pub struct RegexPattern {
+    pattern: String,
+    regex: Result<Regex, regex::Error>,
+}
+
+impl From<&str> for RegexPattern {
+    fn from(pattern: &str) -> Self {
+        Self {
+            pattern: pattern.to_string(),
+            regex: Regex::new(pattern),
+        }
+    }
+}
+
+impl PartialEq for RegexPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.pattern == other.pattern
+    }
+}
+
+impl Serialize for RegexPattern {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.pattern)
+    }
+}
+
+impl<'de> Deserialize<'de> for RegexPattern {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.as_str().into())
+    }
+}
+
+impl TextField {
+    fn values<'a>(&self, transaction: &'a Transaction) -> Vec<&'a str> {
+        let creditor = transaction.creditor_name.as_deref();
+        let debtor = transaction.debtor_name.as_deref();
+        let remittance = transaction.remittance_information.as_deref();
+        match self {
+            TextField::Creditor => vec![creditor],
+            TextField::Debtor => vec![debtor],
+            TextField::Remittance => vec![remittance],
+            TextField::Any => vec![creditor, debtor, remittance],
+        }
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+impl TriggerFilterPredicate {
+    pub fn matches(&self, transaction: &Transaction, merchant: Option<&Merchant>) -> bool {
+        let amount = || transaction.transaction_amount.to_f64();
+        let in_range = |value: f64, min: &Option<f64>, max: &Option<f64>| {
+            min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+        };
+
+        match self {
+            TriggerFilterPredicate::Account(account_ids) => {
+                account_ids.contains(&transaction.account_id)
+            }
+            TriggerFilterPredicate::Incoming => amount() > 0.0,
+            TriggerFilterPredicate::Outgoing => amount() < 0.0,
+            TriggerFilterPredicate::Amount { min, max } => in_range(amount(), min, max),
+            TriggerFilterPredicate::AbsoluteAmount { min, max } => {
+                in_range(amount().abs(), min, max)
+            }
+            TriggerFilterPredicate::Currency(currencies) => {
+                currencies.contains(&transaction.transaction_amount_currency)
+            }
+            TriggerFilterPredicate::Contains { field, text } => {
+                let text = text.to_lowercase();
+                field
+                    .values(transaction)
+                    .iter()
+                    .any(|value| value.to_lowercase().contains(&text))
+            }
+            TriggerFilterPredicate::Regex { field, pattern } => match &pattern.regex {
+                Ok(regex) => field
+                    .values(transaction)
+                    .iter()
+                    .any(|value| regex.is_match(value)),
+                Err(_) => false,
+            },
+            TriggerFilterPredicate::MerchantId(merchant_ids) => transaction
+                .merchant_id
+                .is_some_and(|id| merchant_ids.contains(&id)),
+            TriggerFilterPredicate::MerchantLabel(label) => merchant
+                .and_then(|merchant| merchant.labels.as_ref())
+                .is_some_and(|labels| {
+                    labels.to_lowercase().contains(&label.to_lowercase())
+                }),
+            TriggerFilterPredicate::Weekday(weekdays) => {
+                weekdays.contains(&transaction.booking_date.weekday())
+            }
+            TriggerFilterPredicate::BookingDate { from, to } => {
+                from.is_none_or(|from| transaction.booking_date >= from)
+                    && to.is_none_or(|to| transaction.booking_date <= to)
+            }
+            TriggerFilterPredicate::Any(predicates) => predicates
+                .iter()
+                .any(|predicate| predicate.matches(transaction, merchant)),
+            TriggerFilterPredicate::All(predicates) => predicates
+                .iter()
+                .all(|predicate| predicate.matches(transaction, merchant)),
+            TriggerFilterPredicate::Not(predicate) => !predicate.matches(transaction, merchant),
+        }
+    }
+
+    fn validate(&self) -> Result<()> {
+        match self {
+            TriggerFilterPredicate::Regex { pattern, .. } => {
+                pattern.regex.as_ref().map_err(|e| e.clone())?;
+            }
+            TriggerFilterPredicate::Any(predicates) | TriggerFilterPredicate::All(predicates) => {
+                for predicate in predicates {
+                    predicate.validate()?;
                 }
-            };
-            if !matches {
-                return false;
             }
+            TriggerFilterPredicate::Not(predicate) => predicate.validate()?,
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+impl TriggerFilter {
+    pub fn matches(&self, transaction: &Transaction, merchant: Option<&Merchant>) -> bool {
+        self.0
+            .iter()
+            .all(|predicate| predicate.matches(transaction, merchant))
+    }
+
+    /// Check the filter can be evaluated, e.g. that all regexes compile.
+    pub fn validate(&self) -> Result<()> {
+        for predicate in &self.0 {
+            predicate.validate()?;
         }
-        true
+        Ok(())
     }
 }
 
@@ -76,7 +241,23 @@ pub struct Trigger {
     pub updated_at: chrono::NaiveDateTime,
 }
 
+#[derive(Clone, Copy)]
+enum TriggerPayload<'a> {
+    Transaction(&'a Transaction),
+    Event(&'a serde_json::Value),
+}
+
 impl Trigger {
+    pub const TRANSACTION_CREATED: &'static str = "transaction_created";
+    /// A pending transaction was imported, such as a card payment that hasn't settled.
+    pub const TRANSACTION_PENDING: &'static str = "transaction_pending";
+    /// A transaction was booked, either imported as booked or a pending transaction settling.
+    pub const TRANSACTION_BOOKED: &'static str = "transaction_booked";
+    /// A requisition's consent is about to lapse and it needs renewing to keep importing.
+    pub const REQUISITION_EXPIRING: &'static str = "requisition_expiring";
+    /// Any of an account's balances changed since they were last fetched.
+    pub const ACCOUNT_BALANCE_UPDATED: &'static str = "account_balance_updated";
+
     pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
         sqlx::query_as!(Self, "SELECT * FROM triggers")
             .fetch_all(db)
@@ -141,17 +322,58 @@ impl Trigger {
         Ok(())
     }
 
+    /// Run the trigger's function for the transaction, recording the outcome in the trigger log.
     pub async fn sqlx_run(&self, transaction: &Transaction, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
-        let function = Function::sqlx_by_id(self.function_id, db).await?;
-        let destination = function.get_destination(serde_json::to_string(&self.params).unwrap().as_str())?;
-          // let log = NewTriggerLog {
-        //     payload: payload.0,
-        //     status: payload.1.to_owned(),
-        //     console: deno_runtime.get_output().await,
-        //     user_id: user.id,
-        //     trigger_id: trigger.id,
-        // }
-        destination.transaction_created(transaction).await
+        self.sqlx_run_payload(TriggerPayload::Transaction(transaction), db).await
+    }
+
+    /// Run the trigger's function for a non-transaction event with its JSON payload.
+    pub async fn sqlx_run_event(&self, payload: &serde_json::Value, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        self.sqlx_run_payload(TriggerPayload::Event(payload), db).await
+    }
+
+    async fn sqlx_run_payload(&self, payload: TriggerPayload<'_>, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        let started = Instant::now();
+        let mut console = vec![];
+        let result = async {
+            let function = Function::sqlx_by_id(self.function_id, db).await?;
+            let destination = function.get_destination(serde_json::to_string(&self.params).unwrap().as_str(), db)?;
+            let result = match payload {
+                TriggerPayload::Transaction(transaction) => destination.transaction_created(transaction).await,
+                TriggerPayload::Event(payload) => destination.event(&self.event, payload).await,
+            };
+            console = destination.get_output();
+            result
+        }
+        .await;
+
+        let log = NewTriggerLog {
+            payload: match payload {
+                TriggerPayload::Transaction(transaction) => serde_json::to_string(transaction)?,
+                TriggerPayload::Event(payload) => payload.to_string(),
+            },
+            console,
+            status: match result {
+                Ok(_) => TriggerLog::SUCCESS.to_string(),
+                Err(_) => TriggerLog::ERROR.to_string(),
+            },
+            duration_ms: started.elapsed().as_millis() as u32,
+            error: result.as_ref().err().map(|e| {
+                e.chain()
+                    .map(|cause| cause.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }),
+            user_id: self.user_id,
+            trigger_id: self.id,
+        }
+        .sqlx_create(db)
+        .await;
+
+        if let Err(e) = log {
+            log::error!("Failed to write trigger log for trigger {}: {:?}", self.id, e);
+        }
+        result
     }
 }
 
@@ -180,6 +402,14 @@ impl NewTrigger {
     }
 }
 
+#[derive(Deserialize, Debug)]
+pub struct CreateTrigger {
+    pub function_id: u32,
+    pub params: HashMap<String, String>,
+    pub event: String,
+    pub name: String,
+}
+
 #[derive(Deserialize)]
 
 pub struct UpdateTrigger {
@@ -193,15 +423,111 @@ pub struct UpdateTrigger {
 
 impl UpdateTrigger {
     pub async fn sqlx_update(self, db: &sqlx::MySqlPool) -> Result<Trigger, anyhow::Error> {
-        let _ = sqlx::query("UPDATE triggers SET name = ?, params = ?, event = ?, filter = ?, function_id = ?, updated_at = ? WHERE id = ?")
-            .bind(&self.name)
-            .bind(&self.params)
-            .bind(&self.event)
-            .bind(serde_json::to_string(&self.filter).unwrap())
-            .bind(&self.function_id)
-            .bind(&self.id)
+        let id = self.id.ok_or(anyhow::anyhow!("No id found"))?;
+        let trigger = Trigger::sqlx_by_id(id, db).await?;
+        let params = match self.params {
+            Some(params) => serde_json::from_str::<TriggerParams>(&params)?,
+            None => trigger.params,
+        };
+        let filter = self.filter.unwrap_or(trigger.filter);
+        filter.validate()?;
+        sqlx::query("UPDATE triggers SET name = ?, params = ?, event = ?, filter = ?, function_id = ?, updated_at = ? WHERE id = ?")
+            .bind(self.name.unwrap_or(trigger.name))
+            .bind(serde_json::to_string(&params)?)
+            .bind(self.event.unwrap_or(trigger.event))
+            .bind(serde_json::to_string(&filter)?)
+            .bind(self.function_id.unwrap_or(trigger.function_id))
+            .bind(chrono::Local::now().naive_local())
+            .bind(id)
             .execute(db)
             .await.map_err(|e| anyhow::anyhow!(e))?;
-        Trigger::sqlx_by_id(self.id.unwrap(), db).await
+        Trigger::sqlx_by_id(id, db).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::transaction::test_transaction;
+
+    fn transaction(amount: &str, creditor: &str, date: NaiveDate) -> Transaction {
+        Transaction {
+            external_id: "ext".into(),
+            booking_date: date,
+            transaction_amount_currency: Currency::from("EUR".to_string()),
+            account_id: 3,
+            created_at: date.and_hms_opt(0, 0, 0).unwrap(),
+            updated_at: date.and_hms_opt(0, 0, 0).unwrap(),
+            ..test_transaction(Some(creditor), Some("Card payment 1234"), amount)
+        }
+    }
+
+    #[test]
+    fn test_filter_from_json() {
+        let filter: TriggerFilter = r#"[
+            {"Account": [3]},
+            "Outgoing",
+            {"AbsoluteAmount": {"min": 100, "max": null}},
+            {"Any": [
+                {"Contains": {"field": "Creditor", "text": "tesco"}},
+                {"Regex": {"field": "Any", "pattern": "^SAINSBURY"}}
+            ]},
+            {"Not": {"Weekday": ["Sun"]}}
+        ]"#
+        .to_string()
+        .into();
+        filter.validate().unwrap();
+
+        // Wednesday
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        assert!(filter.matches(&transaction("-120.50", "TESCO STORES 2341", date), None));
+        assert!(filter.matches(&transaction("-120.50", "SAINSBURYS", date), None));
+        assert!(!filter.matches(&transaction("-20.00", "TESCO STORES 2341", date), None));
+        assert!(!filter.matches(&transaction("120.50", "TESCO STORES 2341", date), None));
+        assert!(!filter.matches(&transaction("-120.50", "ALDI", date), None));
+
+        // Sunday
+        let date = NaiveDate::from_ymd_opt(2024, 5, 5).unwrap();
+        assert!(!filter.matches(&transaction("-120.50", "TESCO STORES 2341", date), None));
+    }
+
+    #[test]
+    fn test_merchant_predicates() {
+        let date = NaiveDate::from_ymd_opt(2024, 5, 1).unwrap();
+        let mut transaction = transaction("-5.00", "Coffee", date);
+        transaction.merchant_id = Some(7);
+        let merchant = Merchant {
+            id: 7,
+            name: "Coffee Shop".into(),
+            logo_url: None,
+            location: None,
+            location_structured: None,
+            labels: Some("Food,Coffee Shops".into()),
+            external_id: None,
+            website: None,
+            created_at: date.and_hms_opt(0, 0, 0).unwrap(),
+        };
+
+        let by_label = TriggerFilterPredicate::MerchantLabel("coffee".into());
+        assert!(by_label.matches(&transaction, Some(&merchant)));
+        assert!(!by_label.matches(&transaction, None));
+        assert!(TriggerFilterPredicate::MerchantId(vec![7]).matches(&transaction, None));
+        assert!(TriggerFilterPredicate::Currency(vec![Currency::from("EUR".to_string())])
+            .matches(&transaction, None));
+    }
+
+    #[test]
+    fn test_validate_rejects_invalid_regex() {
+        let filter = TriggerFilter(vec![TriggerFilterPredicate::Not(Box::new(
+            TriggerFilterPredicate::Regex {
+                field: TextField::Any,
+                pattern: "(".into(),
+            },
+        ))]);
+        assert!(filter.validate().is_err());
+        // Stored as written, so it can still be loaded and fixed.
+        let json = serde_json::to_string(&filter).unwrap();
+        assert_eq!(json, r#"[{"Not":{"Regex":{"field":"Any","pattern":"("}}}]"#);
+        assert!(TriggerFilter::from(json).validate().is_err());
     }
 }
@@ -1,3 +1,5 @@
+use crate::utils::display_option;
+use chrono::NaiveDate;
 use cli_table::Table;
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
@@ -14,7 +16,7 @@ impl From<Option<String>> for Console {
     }
 }
 
-#[derive(Table, Debug, Serialize)]
+#[derive(Table, Debug, Serialize, sqlx::FromRow)]
 
 pub struct TriggerLog {
     #[table(title = "Log ID")]
@@ -22,9 +24,14 @@ pub struct TriggerLog {
     #[table(title = "Payload")]
     pub payload: String,
     #[table(skip)]
+    #[sqlx(try_from = "Option<String>")]
     pub console: Console,
     #[table(title = "Status")]
     pub status: String,
+    #[table(title = "Duration (ms)")]
+    pub duration_ms: u32,
+    #[table(title = "Error", display_fn = "display_option")]
+    pub error: Option<String>,
     #[table(title = "User ID")]
     pub user_id: u32,
     #[table(title = "Trigger ID")]
@@ -35,16 +42,32 @@ pub struct TriggerLog {
     pub updated_at: chrono::NaiveDateTime,
 }
 
+#[derive(Default, Debug, Deserialize)]
+pub struct TriggerLogFilter {
+    pub trigger_id: Option<u32>,
+    pub status: Option<String>,
+    pub user_id: Option<u32>,
+    /// Only logs created on or after this date.
+    pub from: Option<NaiveDate>,
+    /// Only logs created on or before this date.
+    pub to: Option<NaiveDate>,
+    pub limit: Option<u32>,
+}
+
 impl TriggerLog {
+    pub const SUCCESS: &'static str = "success";
+    pub const ERROR: &'static str = "error";
+
     pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM trigger_log")
+        sqlx::query_as::<_, Self>("SELECT * FROM trigger_log")
             .fetch_all(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))
     }
 
     pub async fn sqlx_by_id(id: u32, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM trigger_log WHERE id = ?", id)
+        sqlx::query_as::<_, Self>("SELECT * FROM trigger_log WHERE id = ?")
+            .bind(id)
             .fetch_one(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))
@@ -54,7 +77,36 @@ impl TriggerLog {
         user_id: u32,
         db: &sqlx::MySqlPool,
     ) -> Result<Vec<Self>, anyhow::Error> {
-        sqlx::query_as!(Self, "SELECT * FROM trigger_log WHERE user_id = ?", user_id)
+        sqlx::query_as::<_, Self>("SELECT * FROM trigger_log WHERE user_id = ?")
+            .bind(user_id)
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_filter(
+        filter: &TriggerLogFilter,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        let mut qb = sqlx::QueryBuilder::new("SELECT * FROM trigger_log WHERE 1 = 1");
+        if let Some(trigger_id) = filter.trigger_id {
+            qb.push(" AND trigger_id = ").push_bind(trigger_id);
+        }
+        if let Some(status) = &filter.status {
+            qb.push(" AND status = ").push_bind(status);
+        }
+        if let Some(user_id) = filter.user_id {
+            qb.push(" AND user_id = ").push_bind(user_id);
+        }
+        if let Some(from) = filter.from {
+            qb.push(" AND created_at >= ").push_bind(from);
+        }
+        if let Some(to) = filter.to.and_then(|to| to.succ_opt()) {
+            qb.push(" AND created_at < ").push_bind(to);
+        }
+        qb.push(" ORDER BY created_at DESC LIMIT ")
+            .push_bind(filter.limit.unwrap_or(100));
+        qb.build_query_as::<Self>()
             .fetch_all(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))
@@ -66,20 +118,24 @@ pub struct NewTriggerLog {
     pub payload: String,
     pub console: Vec<String>,
     pub status: String,
+    pub duration_ms: u32,
+    pub error: Option<String>,
     pub user_id: u32,
     pub trigger_id: u32,
 }
 
 impl NewTriggerLog {
     pub async fn sqlx_create(self, db: &sqlx::MySqlPool) -> Result<TriggerLog, anyhow::Error> {
-        let result = sqlx::query!(
-            "INSERT INTO trigger_log (payload, console, status, user_id, trigger_id) VALUES (?, ?, ?, ?, ?)",
-            self.payload,
-            serde_json::json!(Console(self.console)).to_string(),
-            self.status,
-            self.user_id,
-            self.trigger_id
+        let result = sqlx::query(
+            "INSERT INTO trigger_log (payload, console, status, duration_ms, error, user_id, trigger_id) VALUES (?, ?, ?, ?, ?, ?, ?)",
         )
+        .bind(self.payload)
+        .bind(serde_json::json!(Console(self.console)).to_string())
+        .bind(self.status)
+        .bind(self.duration_ms)
+        .bind(self.error)
+        .bind(self.user_id)
+        .bind(self.trigger_id)
         .execute(db)
         .await?;
         TriggerLog::sqlx_by_id(result.last_insert_id() as u32, db).await
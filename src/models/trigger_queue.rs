@@ -0,0 +1,305 @@
+use crate::ultrafinance::Money;
+use crate::utils::display_option;
+use anyhow::Result;
+use chrono::{Duration, NaiveDateTime};
+use cli_table::Table;
+use serde::{Deserialize, Serialize};
+
+use super::BalanceChange;
+
+/// Number of times an entry is attempted before it is parked as failed.
+pub const MAX_ATTEMPTS: u32 = 8;
+const BACKOFF_BASE_SECONDS: i64 = 60;
+/// How long a claimed entry may run before another processor may pick it up again.
+const CLAIM_TIMEOUT_SECONDS: i64 = 15 * 60;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TriggerQueuePayload {
+    Transaction { transaction_id: u32 },
+    Requisition { requisition_id: u32 },
+    AccountBalance {
+        account_id: u32,
+        old_balance: Money,
+        new_balance: Money,
+        balances: Vec<BalanceChange>,
+    },
+}
+
+#[derive(Table, Debug, Serialize, sqlx::FromRow)]
+pub struct TriggerQueue {
+    #[table(title = "Queue ID")]
+    pub id: u32,
+    #[table(title = "Payload")]
+    pub payload: String,
+    #[table(title = "Status")]
+    pub status: String,
+    #[table(title = "Attempts")]
+    pub attempts: u32,
+    #[table(title = "Next Attempt")]
+    pub next_attempt_at: NaiveDateTime,
+    #[table(title = "Last Error", display_fn = "display_option")]
+    pub last_error: Option<String>,
+    #[table(title = "User ID")]
+    pub user_id: u32,
+    #[table(title = "Trigger ID")]
+    pub trigger_id: u32,
+    #[table(title = "Date Created")]
+    pub created_at: NaiveDateTime,
+    #[table(title = "Updated At")]
+    pub updated_at: NaiveDateTime,
+}
+
+/// Delay before the next attempt, doubling with every failed attempt.
+pub fn backoff(attempts: u32) -> Duration {
+    Duration::seconds(BACKOFF_BASE_SECONDS * 2i64.pow(attempts.saturating_sub(1).min(16)))
+}
+
+impl TriggerQueue {
+    pub const PENDING: &'static str = "pending";
+    pub const RUNNING: &'static str = "running";
+    pub const FAILED: &'static str = "failed";
+
+    pub fn payload(&self) -> Result<TriggerQueuePayload> {
+        serde_json::from_str(&self.payload).map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM trigger_queue ORDER BY next_attempt_at")
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_status(
+        status: &str,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT * FROM trigger_queue WHERE status = ? ORDER BY next_attempt_at",
+        )
+        .bind(status)
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_id(id: u32, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        sqlx::query_as::<_, Self>("SELECT * FROM trigger_queue WHERE id = ?")
+            .bind(id)
+            .fetch_one(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    pub async fn sqlx_by_user(
+        user_id: u32,
+        db: &sqlx::MySqlPool,
+    ) -> Result<Vec<Self>, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT * FROM trigger_queue WHERE user_id = ? ORDER BY next_attempt_at",
+        )
+        .bind(user_id)
+        .fetch_all(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Pending entries whose next attempt is due, and running ones whose claim has gone stale.
+    /// Both selecting and claiming entries use it, so an entry claimed in the meantime is skipped.
+    fn push_due<'a>(query: &mut sqlx::QueryBuilder<'a, sqlx::MySql>, now: NaiveDateTime) {
+        query
+            .push("status IN (")
+            .push_bind(Self::PENDING)
+            .push(", ")
+            .push_bind(Self::RUNNING)
+            .push(") AND next_attempt_at <= ")
+            .push_bind(now);
+    }
+
+    fn due_query<'a>(now: NaiveDateTime, limit: u32) -> sqlx::QueryBuilder<'a, sqlx::MySql> {
+        let mut query = sqlx::QueryBuilder::new("SELECT * FROM trigger_queue WHERE ");
+        Self::push_due(&mut query, now);
+        query.push(" ORDER BY next_attempt_at LIMIT ").push_bind(limit);
+        query
+    }
+
+    /// Mark the entry as running, if it's still due. `next_attempt_at` doubles as the claim's
+    /// expiry, so entries left behind by a processor that died are picked up again.
+    fn claim_query<'a>(&mut self, now: NaiveDateTime) -> sqlx::QueryBuilder<'a, sqlx::MySql> {
+        self.status = Self::RUNNING.to_string();
+        self.next_attempt_at = now + Duration::seconds(CLAIM_TIMEOUT_SECONDS);
+        self.updated_at = now;
+        let mut query = sqlx::QueryBuilder::new("UPDATE trigger_queue SET status = ");
+        query
+            .push_bind(self.status.clone())
+            .push(", next_attempt_at = ")
+            .push_bind(self.next_attempt_at)
+            .push(", updated_at = ")
+            .push_bind(self.updated_at)
+            .push(" WHERE id = ")
+            .push_bind(self.id)
+            .push(" AND ");
+        Self::push_due(&mut query, now);
+        query
+    }
+
+    /// Claim up to `limit` due entries, oldest first. Entries claimed by another processor in
+    /// the meantime are skipped, so every entry is run once.
+    pub async fn sqlx_due(limit: u32, db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
+        let now = chrono::Local::now().naive_local();
+        let candidates = Self::due_query(now, limit)
+            .build_query_as::<Self>()
+            .fetch_all(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+
+        let mut claimed = vec![];
+        for mut entry in candidates {
+            let result = entry.claim_query(now).build().execute(db).await?;
+            if result.rows_affected() == 1 {
+                claimed.push(entry);
+            }
+        }
+        Ok(claimed)
+    }
+
+    /// Record a failed attempt, scheduling a retry or parking the entry as failed.
+    pub async fn sqlx_record_failure(
+        &mut self,
+        error: &anyhow::Error,
+        db: &sqlx::MySqlPool,
+    ) -> Result<(), anyhow::Error> {
+        let now = chrono::Local::now().naive_local();
+        self.attempts += 1;
+        self.last_error = Some(format!("{:#}", error));
+        if self.attempts >= MAX_ATTEMPTS {
+            self.status = Self::FAILED.to_string();
+        } else {
+            self.status = Self::PENDING.to_string();
+            self.next_attempt_at = now + backoff(self.attempts);
+        }
+        self.updated_at = now;
+        self.sqlx_update(db).await
+    }
+
+    /// Put a (failed) entry back in the queue to be attempted straight away.
+    pub async fn sqlx_retry(&mut self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        let now = chrono::Local::now().naive_local();
+        self.status = Self::PENDING.to_string();
+        self.attempts = 0;
+        self.next_attempt_at = now;
+        self.updated_at = now;
+        self.sqlx_update(db).await
+    }
+
+    async fn sqlx_update(&self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        sqlx::query("UPDATE trigger_queue SET status = ?, attempts = ?, next_attempt_at = ?, last_error = ?, updated_at = ? WHERE id = ?")
+            .bind(&self.status)
+            .bind(self.attempts)
+            .bind(self.next_attempt_at)
+            .bind(&self.last_error)
+            .bind(self.updated_at)
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    pub async fn sqlx_delete(&self, db: &sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+        sqlx::query("DELETE FROM trigger_queue WHERE id = ?")
+            .bind(self.id)
+            .execute(db)
+            .await
+            .map_err(|e| anyhow::anyhow!(e))?;
+        Ok(())
+    }
+
+    /// Delete all entries with the given status, or every entry when `status` is `None`.
+    pub async fn sqlx_purge(status: Option<&str>, db: &sqlx::MySqlPool) -> Result<u64, anyhow::Error> {
+        let result = match status {
+            Some(status) => {
+                sqlx::query("DELETE FROM trigger_queue WHERE status = ?")
+                    .bind(status)
+                    .execute(db)
+                    .await?
+            }
+            None => sqlx::query("DELETE FROM trigger_queue").execute(db).await?,
+        };
+        Ok(result.rows_affected())
+    }
+}
+
+#[derive(Debug)]
+pub struct NewTriggerQueue {
+    pub payload: TriggerQueuePayload,
+    pub user_id: u32,
+    pub trigger_id: u32,
+}
+
+impl NewTriggerQueue {
+    pub async fn sqlx_create(self, db: &sqlx::MySqlPool) -> Result<TriggerQueue, anyhow::Error> {
+        let result = sqlx::query("INSERT INTO trigger_queue (payload, status, attempts, next_attempt_at, user_id, trigger_id) VALUES (?, ?, 0, ?, ?, ?)")
+            .bind(serde_json::to_string(&self.payload)?)
+            .bind(TriggerQueue::PENDING)
+            .bind(chrono::Local::now().naive_local())
+            .bind(self.user_id)
+            .bind(self.trigger_id)
+            .execute(db)
+            .await?;
+        TriggerQueue::sqlx_by_id(result.last_insert_id() as u32, db).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_doubles() {
+        assert_eq!(backoff(1), Duration::seconds(60));
+        assert_eq!(backoff(2), Duration::seconds(120));
+        assert_eq!(backoff(5), Duration::seconds(960));
+    }
+
+    #[test]
+    fn test_payload_round_trip() {
+        let payload = TriggerQueuePayload::Transaction { transaction_id: 12 };
+        let json = serde_json::to_string(&payload).unwrap();
+        assert_eq!(json, r#"{"type":"transaction","transaction_id":12}"#);
+        assert_eq!(serde_json::from_str::<TriggerQueuePayload>(&json).unwrap(), payload);
+    }
+
+    #[test]
+    fn test_claimed_entries_are_not_due() {
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 1, 2).unwrap().and_hms_opt(12, 0, 0).unwrap();
+        let due = "status IN (?, ?) AND next_attempt_at <= ?";
+        assert_eq!(
+            TriggerQueue::due_query(now, 10).sql(),
+            format!("SELECT * FROM trigger_queue WHERE {} ORDER BY next_attempt_at LIMIT ?", due)
+        );
+
+        let mut entry = TriggerQueue {
+            id: 1,
+            payload: r#"{"type":"transaction","transaction_id":12}"#.to_string(),
+            status: TriggerQueue::PENDING.to_string(),
+            attempts: 0,
+            next_attempt_at: now,
+            last_error: None,
+            user_id: 1,
+            trigger_id: 1,
+            created_at: now,
+            updated_at: now,
+        };
+        // Claiming only updates the entry while it's still due, as another processor may have
+        // claimed it since it was selected.
+        assert_eq!(
+            entry.claim_query(now).sql(),
+            format!("UPDATE trigger_queue SET status = ?, next_attempt_at = ?, updated_at = ? WHERE id = ? AND {}", due)
+        );
+        assert_eq!(entry.status, TriggerQueue::RUNNING);
+        // Which it isn't until the claim expires, for when a processor died mid-run.
+        assert_eq!(entry.next_attempt_at, now + Duration::seconds(CLAIM_TIMEOUT_SECONDS));
+    }
+}
@@ -1,7 +1,28 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
 use cli_table::Table;
 use serde::{Deserialize, Serialize};
 use anyhow::Result;
 
+use crate::ultrafinance::Currency;
+
+pub fn hash_password(password: &str) -> Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| anyhow::anyhow!(e))
+}
+
+/// Hashes that can't be parsed (e.g. users created without a password) never verify.
+pub fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
 #[derive(Table, Debug, Serialize, Clone, sqlx::FromRow)]
 pub struct User {
     #[table(title = "User ID")]
@@ -12,10 +33,9 @@ pub struct User {
     pub email: String,
     #[serde(skip_serializing)]
     #[table(skip)]
-    #[allow(dead_code)]
     pub(crate) password: String,
-    // #[table(title = "Currency")]
-    // pub primary_currency: Currency,
+    #[table(title = "Currency")]
+    pub primary_currency: Currency,
     #[table(title = "Date Created")]
     pub created_at: chrono::NaiveDateTime,
     #[table(title = "Updated At")]
@@ -23,6 +43,13 @@ pub struct User {
 }
 
 impl User {
+    /// Used for new users that don't pick a primary currency.
+    pub const DEFAULT_CURRENCY: &'static str = "EUR";
+
+    pub fn verify_password(&self, password: &str) -> bool {
+        verify_password(password, &self.password)
+    }
+
     pub async fn sqlx_all(db: &sqlx::MySqlPool) -> Result<Vec<Self>, anyhow::Error> {
         sqlx::query_as!(Self, "SELECT * FROM users")
             .fetch_all(db)
@@ -43,6 +70,27 @@ impl User {
             .await
             .map_err(|e| anyhow::anyhow!(e))
     }
+
+    pub async fn sqlx_by_api_key(api_key: &str, db: &sqlx::MySqlPool) -> Result<Self, anyhow::Error> {
+        sqlx::query_as::<_, Self>(
+            "SELECT * FROM users WHERE id = (SELECT user_id FROM user_api_keys WHERE api_key = ?)",
+        )
+        .bind(api_key)
+        .fetch_one(db)
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+    }
+
+    /// Create a new random API key for the user, used to authenticate with the server.
+    pub async fn sqlx_create_api_key(&self, db: &sqlx::MySqlPool) -> Result<String, anyhow::Error> {
+        let api_key = uuid::Uuid::new_v4().simple().to_string();
+        sqlx::query("INSERT INTO user_api_keys (api_key, user_id) VALUES (?, ?)")
+            .bind(&api_key)
+            .bind(self.id)
+            .execute(db)
+            .await?;
+        Ok(api_key)
+    }
 }
 
 #[derive(Default, Debug, Deserialize)]
@@ -50,13 +98,21 @@ impl User {
 pub struct NewUser {
     pub name: String,
     pub email: String,
+    pub password: String,
+    pub primary_currency: Option<Currency>,
 }
 
 impl NewUser {
     pub async fn sqlx_create(self, db: &sqlx::MySqlPool) -> Result<User, anyhow::Error> {
-        let result = sqlx::query("INSERT INTO users (name, email, password) VALUES (?, ?, ?)")
+        let primary_currency = match self.primary_currency {
+            Some(currency) => currency,
+            None => User::DEFAULT_CURRENCY.parse()?,
+        };
+        let result = sqlx::query("INSERT INTO users (name, email, password, primary_currency) VALUES (?, ?, ?, ?)")
             .bind(self.name)
             .bind(self.email)
+            .bind(hash_password(&self.password)?)
+            .bind(primary_currency)
             .execute(db)
             .await?;
         User::sqlx_by_id(result.last_insert_id() as u32, db).await
@@ -70,21 +126,39 @@ pub struct UpdateUser {
     pub name: Option<String>,
     pub email: Option<String>,
     pub password: Option<String>,
-    // pub primary_currency: Option<Currency>,
+    pub primary_currency: Option<Currency>,
 }
 
 impl UpdateUser {
     pub async fn sqlx_update(self, db: &sqlx::MySqlPool) -> Result<User, anyhow::Error> {
-        //let _ = sqlx::query("UPDATE users SET name = ?, email = ?, password = ?, primary_currency = ? WHERE id = ?")
-        let _ = sqlx::query("UPDATE users SET name = ?, email = ?, password = ? WHERE id = ?")
-            .bind(&self.name)
-            .bind(&self.email)
-            .bind(&self.password)
-            // .bind(&self.primary_currency)
-            .bind(&self.id)
+        let id = self.id.ok_or(anyhow::anyhow!("No id found"))?;
+        let user = User::sqlx_by_id(id, db).await?;
+        let _ = sqlx::query("UPDATE users SET name = ?, email = ?, password = ?, primary_currency = ?, updated_at = ? WHERE id = ?")
+            .bind(self.name.unwrap_or(user.name))
+            .bind(self.email.unwrap_or(user.email))
+            .bind(match self.password {
+                Some(password) => hash_password(&password)?,
+                None => user.password,
+            })
+            .bind(self.primary_currency.unwrap_or(user.primary_currency))
+            .bind(chrono::Local::now().naive_local())
+            .bind(id)
             .execute(db)
             .await
             .map_err(|e| anyhow::anyhow!(e))?;
-        User::sqlx_by_id(self.id.unwrap(), db).await
+        User::sqlx_by_id(id, db).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_verify_password() {
+        let hash = hash_password("hunter2").unwrap();
+        assert!(verify_password("hunter2", &hash));
+        assert!(!verify_password("hunter3", &hash));
+        assert!(!verify_password("", ""));
     }
 }
@@ -1,11 +1,17 @@
+use std::collections::HashMap;
+
 use anyhow::Result;
 use log::info;
 use serde::{Deserialize, Serialize};
 
+use crate::merchant_enricher::Enrichment;
 use crate::models;
 use crate::NewMerchant;
 use crate::Transaction;
 
+/// The most transactions the sync endpoint accepts in one request.
+pub const BATCH_SIZE: usize = 4000;
+
 pub struct ApiClient {
     async_client: reqwest::Client,
 }
@@ -57,6 +63,21 @@ impl ApiClient {
             serde_path_to_error::deserialize(jd).map_err(|e| e.into());
         result
     }
+
+    /// Enrichments for the transactions Ntropy found a merchant for, sent in batches of up to
+    /// `BATCH_SIZE`.
+    pub async fn get_merchants(&self, transactions: &[Transaction]) -> Result<HashMap<u32, Enrichment>> {
+        let mut enriched = HashMap::new();
+        for batch in transactions.chunks(BATCH_SIZE) {
+            let inputs = batch.iter().cloned().map(TransactionInput::from).collect();
+            for output in self.async_enrich_transactions(inputs).await? {
+                if let Ok((id, enrichment)) = output.try_into() {
+                    enriched.insert(id, enrichment);
+                }
+            }
+        }
+        Ok(enriched)
+    }
 }
 
 #[derive(Serialize, Debug)]
@@ -66,7 +87,7 @@ pub struct TransactionInput {
     /// Direction of the flow of money from the perspective of the account holder. Possible values are incoming and outgoing.
     pub entry_type: String,
     /// Amount of the transaction.
-    pub amount: f32,
+    pub amount: f64,
     /// ISO currency code for the transaction.
     pub iso_currency_code: String,
     /// Date of the transaction.
@@ -143,7 +164,6 @@ pub struct LocationStructured {
 
 impl From<Transaction> for TransactionInput {
     fn from(transaction: Transaction) -> Self {
-        let amount = transaction.transaction_amount.parse::<f32>().unwrap_or(0.0);
         TransactionInput {
             description: format!(
                 "{} {} {}",
@@ -151,18 +171,19 @@ impl From<Transaction> for TransactionInput {
                 transaction.debtor_name.unwrap_or("".to_string()),
                 transaction.remittance_information.unwrap_or("".to_string())
             ),
-            entry_type: if amount < 0 as f32 {
+            entry_type: if transaction.transaction_amount.is_negative() {
                 "outgoing".to_string()
             } else {
                 "incoming".to_string()
             },
-            amount: amount.abs(),
+            amount: transaction.transaction_amount.abs().to_f64(),
             iso_currency_code: transaction.transaction_amount_currency.to_string(),
             date: transaction.booking_date,
             transaction_id: transaction.id.to_string(),
             country: None,
-            account_holder_id: None,
-            account_holder_type: None,
+            // Ntropy keeps a ledger per account holder, which helps it spot recurring payments.
+            account_holder_id: Some(transaction.user_id.to_string()),
+            account_holder_type: Some("consumer".to_string()),
         }
     }
 }
@@ -203,3 +224,51 @@ impl TryFrom<&TransactionOutput> for NewMerchant {
         })
     }
 }
+
+impl TryFrom<TransactionOutput> for (u32, Enrichment) {
+    type Error = anyhow::Error;
+    fn try_from(value: TransactionOutput) -> Result<Self> {
+        Ok((
+            value.transaction_id.parse()?,
+            Enrichment {
+                merchant: NewMerchant::try_from(&value)?,
+                labels: value.labels,
+                recurrence: value.recurrence,
+                mcc: value.mcc.unwrap_or_default(),
+                confidence: None,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enrichment_from_output() {
+        let output: TransactionOutput = serde_json::from_str(
+            r#"{
+                "labels": ["subscriptions", "software"],
+                "label_group": "Essential Expenses",
+                "recurrence": "subscription",
+                "location": null,
+                "location_structured": null,
+                "logo": "https://logos.ntropy.com/netflix.com",
+                "merchant": "Netflix",
+                "merchant_id": "abc",
+                "person": null,
+                "transaction_id": "42",
+                "website": "netflix.com",
+                "mcc": [4899]
+            }"#,
+        )
+        .unwrap();
+        let (id, enrichment) = <(u32, Enrichment)>::try_from(output).unwrap();
+        assert_eq!(id, 42);
+        assert_eq!(enrichment.merchant.name, "Netflix");
+        assert_eq!(enrichment.labels, vec!["subscriptions", "software"]);
+        assert_eq!(enrichment.recurrence.as_deref(), Some("subscription"));
+        assert_eq!(enrichment.mcc, vec![4899]);
+    }
+}
@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::{exchange_rate::ExchangeRate, ultrafinance::Currency};
+
+pub const DAILY_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-daily.xml";
+pub const HISTORY_URL: &str = "https://www.ecb.europa.eu/stats/eurofxref/eurofxref-hist.xml";
+
+/// The ECB euro foreign exchange reference rates, from their feed or a downloaded copy of it.
+pub struct Feed {
+    /// A local eurofxref file (daily or history) to read instead of downloading the feed.
+    pub path: Option<String>,
+}
+
+// The eurofxref envelope nests each day's rates in `Cube` elements:
+// `<Cube><Cube time="2024-01-02"><Cube currency="USD" rate="1.0956"/>...</Cube></Cube>`
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Envelope {
+    Cube: Days,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Days {
+    #[serde(default)]
+    Cube: Vec<Day>,
+}
+
+#[derive(Deserialize, Debug)]
+#[allow(non_snake_case)]
+struct Day {
+    #[serde(rename = "@time")]
+    time: NaiveDate,
+    #[serde(default)]
+    Cube: Vec<Rate>,
+}
+
+#[derive(Deserialize, Debug)]
+struct Rate {
+    #[serde(rename = "@currency")]
+    currency: String,
+    #[serde(rename = "@rate")]
+    rate: f64,
+}
+
+/// One snapshot per day in the feed, based in euros. Currencies we don't know are skipped.
+pub fn parse(data: &str) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+    let envelope: Envelope =
+        quick_xml::de::from_str(data).map_err(|e| anyhow!("Invalid ECB reference rates: {}", e))?;
+    let euro = "EUR".parse::<Currency>()?;
+    let last_update = Utc::now().naive_utc();
+    Ok(envelope
+        .Cube
+        .Cube
+        .into_iter()
+        .map(|day| ExchangeRate {
+            base_code: euro,
+            date: day.time,
+            conversion_rates: day
+                .Cube
+                .into_iter()
+                .filter_map(|rate| Some((rate.currency.parse::<Currency>().ok()?, rate.rate)))
+                .collect::<HashMap<_, _>>(),
+            last_update,
+        })
+        .collect())
+}
+
+impl Feed {
+    async fn fetch(&self, url: &str) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        let data = match &self.path {
+            Some(path) => std::fs::read_to_string(path).map_err(|e| anyhow!("Unable to read {}: {}", path, e))?,
+            None => reqwest::get(url).await?.error_for_status()?.text().await?,
+        };
+        parse(&data)
+    }
+
+    pub async fn latest(&self) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        let mut days = self.fetch(DAILY_URL).await?;
+        days.sort_by_key(|rate| rate.date);
+        Ok(days.pop().into_iter().collect())
+    }
+
+    /// The ECB only publishes rates on TARGET working days, so weekends and holidays are missing.
+    pub async fn history(&self, dates: &[NaiveDate]) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        let mut days = self.fetch(HISTORY_URL).await?;
+        days.retain(|rate| dates.contains(&rate.date));
+        Ok(days)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FEED: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<gesmes:Envelope xmlns:gesmes="http://www.gesmes.org/xml/2002-08-01" xmlns="http://www.ecb.int/vocabulary/2002-08-01/eurofxref">
+	<gesmes:subject>Reference rates</gesmes:subject>
+	<gesmes:Sender>
+		<gesmes:name>European Central Bank</gesmes:name>
+	</gesmes:Sender>
+	<Cube>
+		<Cube time='2024-01-03'>
+			<Cube currency='USD' rate='1.0919'/>
+			<Cube currency='GBP' rate='0.86265'/>
+			<Cube currency='XXY' rate='1.5'/>
+		</Cube>
+		<Cube time='2024-01-02'>
+			<Cube currency='USD' rate='1.0956'/>
+		</Cube>
+	</Cube>
+</gesmes:Envelope>"#;
+
+    #[test]
+    fn test_parse() {
+        let days = parse(FEED).unwrap();
+        assert_eq!(days.len(), 2);
+        assert_eq!(days[0].base_code.to_string(), "EUR");
+        assert_eq!(days[0].date, NaiveDate::from_ymd_opt(2024, 1, 3).unwrap());
+        assert_eq!(days[0].conversion_rates.len(), 2);
+        assert_eq!(days[0].conversion_rates.get(&"GBP".parse().unwrap()), Some(&0.86265));
+        assert_eq!(days[1].conversion_rates.get(&"USD".parse().unwrap()), Some(&1.0956));
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+use chrono::{NaiveDate, Utc};
+use serde::Deserialize;
+
+use crate::{exchange_rate::ExchangeRate, ultrafinance::Currency};
+
+/// Rates exported from elsewhere, as CSV with `date,base,currency,rate` columns or JSON.
+pub struct RatesFile {
+    pub path: String,
+}
+
+#[derive(Deserialize)]
+struct CsvRow {
+    date: NaiveDate,
+    base: Currency,
+    currency: Currency,
+    rate: f64,
+}
+
+/// A day's rates in JSON, in the same shape `exchange_rates` are stored.
+#[derive(Deserialize)]
+struct JsonRates {
+    #[serde(alias = "base")]
+    base_code: Currency,
+    date: NaiveDate,
+    #[serde(alias = "rates")]
+    conversion_rates: HashMap<Currency, f64>,
+}
+
+pub fn parse_csv(data: &str) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+    let last_update = Utc::now().naive_utc();
+    let mut rates: Vec<ExchangeRate> = vec![];
+    for (line, row) in csv::Reader::from_reader(data.as_bytes()).deserialize::<CsvRow>().enumerate() {
+        let row = row.map_err(|e| anyhow!("Invalid rate on line {}: {}", line + 2, e))?;
+        let index = match rates.iter().position(|r| r.base_code == row.base && r.date == row.date) {
+            Some(index) => index,
+            None => {
+                rates.push(ExchangeRate {
+                    base_code: row.base,
+                    date: row.date,
+                    conversion_rates: HashMap::new(),
+                    last_update,
+                });
+                rates.len() - 1
+            }
+        };
+        rates[index].conversion_rates.insert(row.currency, row.rate);
+    }
+    Ok(rates)
+}
+
+/// A single day's rates or a list of them.
+pub fn parse_json(data: &str) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Json {
+        One(JsonRates),
+        Many(Vec<JsonRates>),
+    }
+    let days = match serde_json::from_str(data).map_err(|e| anyhow!("Invalid rates: {}", e))? {
+        Json::One(day) => vec![day],
+        Json::Many(days) => days,
+    };
+    let last_update = Utc::now().naive_utc();
+    Ok(days
+        .into_iter()
+        .map(|day| ExchangeRate {
+            base_code: day.base_code,
+            date: day.date,
+            conversion_rates: day.conversion_rates,
+            last_update,
+        })
+        .collect())
+}
+
+impl RatesFile {
+    /// Everything in the file, since there's nothing newer to tell apart.
+    pub fn rates(&self) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        let data = std::fs::read_to_string(&self.path).map_err(|e| anyhow!("Unable to read {}: {}", self.path, e))?;
+        match self.path.to_lowercase().ends_with(".json") {
+            true => parse_json(&data),
+            false => parse_csv(&data),
+        }
+    }
+
+    pub fn history(&self, dates: &[NaiveDate]) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        let mut rates = self.rates()?;
+        rates.retain(|rate| dates.contains(&rate.date));
+        Ok(rates)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_csv_and_json() {
+        let csv = "date,base,currency,rate\n2024-01-02,USD,EUR,0.91\n2024-01-02,USD,GBP,0.79\n2024-01-03,USD,EUR,0.92\n";
+        let rates = parse_csv(csv).unwrap();
+        assert_eq!(rates.len(), 2);
+        assert_eq!(rates[0].conversion_rates.len(), 2);
+        assert_eq!(rates[1].conversion_rates.get(&"EUR".parse().unwrap()), Some(&0.92));
+        assert!(parse_csv("date,base,currency,rate\n2024-01-02,USD,NOPE,0.91\n").is_err());
+
+        let json = r#"[{"base": "EUR", "date": "2024-01-02", "rates": {"USD": 1.09}}]"#;
+        let rates = parse_json(json).unwrap();
+        assert_eq!(rates[0].base_code.to_string(), "EUR");
+        assert_eq!(rates[0].conversion_rates.get(&"USD".parse().unwrap()), Some(&1.09));
+        let json = r#"{"base_code": "EUR", "date": "2024-01-02", "conversion_rates": {"USD": 1.09}}"#;
+        assert_eq!(parse_json(json).unwrap().len(), 1);
+    }
+}
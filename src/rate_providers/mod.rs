@@ -0,0 +1,78 @@
+use chrono::NaiveDate;
+
+use crate::{exchange_rate::ExchangeRate, exchangerate_api, ultrafinance::Currency};
+
+pub mod ecb;
+pub mod file;
+
+pub trait RateProvider {
+    /// The most recent rates the provider has.
+    fn latest(&self) -> impl std::future::Future<Output = Result<Vec<ExchangeRate>, anyhow::Error>> + Send;
+    /// Rates for any of `dates` the provider has them for.
+    fn history(
+        &self,
+        dates: &[NaiveDate],
+    ) -> impl std::future::Future<Output = Result<Vec<ExchangeRate>, anyhow::Error>> + Send;
+}
+
+/// Where exchange rates come from, by the `--provider` name.
+pub enum Provider {
+    ExchangerateApi(exchangerate_api::Client, Currency),
+    Ecb(ecb::Feed),
+    File(file::RatesFile),
+}
+
+impl RateProvider for Provider {
+    async fn latest(&self) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        match self {
+            Provider::ExchangerateApi(client, code) => Ok(vec![client.get_exchange_rate(code).await?]),
+            Provider::Ecb(feed) => feed.latest().await,
+            Provider::File(file) => file.rates(),
+        }
+    }
+
+    async fn history(&self, dates: &[NaiveDate]) -> Result<Vec<ExchangeRate>, anyhow::Error> {
+        match self {
+            Provider::ExchangerateApi(client, code) => {
+                let mut rates = vec![];
+                for date in dates {
+                    rates.push(client.get_historical_exchange_rate(code, *date).await?);
+                }
+                Ok(rates)
+            }
+            Provider::Ecb(feed) => feed.history(dates).await,
+            Provider::File(file) => file.history(dates),
+        }
+    }
+}
+
+impl Provider {
+    /// The base currency of the provider's rates, `None` when it depends on the data.
+    pub fn base_code(&self) -> Option<Currency> {
+        match self {
+            Provider::ExchangerateApi(_, code) => Some(*code),
+            Provider::Ecb(_) => "EUR".parse().ok(),
+            Provider::File(_) => None,
+        }
+    }
+}
+
+/// `code` is the base currency to fetch for exchangerate-api, and `path` a local file to read
+/// for the ECB and file providers.
+pub fn get_provider(name: &str, code: Option<Currency>, path: Option<String>) -> Result<Provider, anyhow::Error> {
+    let provider = match name {
+        "exchangerate-api" => Provider::ExchangerateApi(
+            exchangerate_api::Client::new(
+                std::env::var("EXCHANGERATE_API_KEY")
+                    .map_err(|_| anyhow::anyhow!("EXCHANGERATE_API_KEY is needed for the exchangerate-api provider"))?,
+            ),
+            code.ok_or(anyhow::anyhow!("--code is needed for the exchangerate-api provider"))?,
+        ),
+        "ecb" => Provider::Ecb(ecb::Feed { path }),
+        "file" => Provider::File(file::RatesFile {
+            path: path.ok_or(anyhow::anyhow!("--path is needed for the file provider"))?,
+        }),
+        _ => return Err(anyhow::anyhow!("No exchange rate provider called {}", name)),
+    };
+    Ok(provider)
+}
@@ -0,0 +1,52 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use super::{ApiError, AppState, AuthUser};
+use crate::{Account, Transaction, UpdateAccount};
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<Vec<Account>>, ApiError> {
+    Ok(Json(Account::sqlx_by_user(user.id, &state.db).await?))
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Account>, ApiError> {
+    Ok(Json(Account::sqlx_by_id(id, user.id, &state.db).await?))
+}
+
+pub async fn update(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+    Json(mut update): Json<UpdateAccount>,
+) -> Result<Json<Account>, ApiError> {
+    let account = Account::sqlx_by_id(id, user.id, &state.db).await?;
+    update.id = Some(account.id);
+    Ok(Json(update.sqlx_update(&state.db).await?))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<(), ApiError> {
+    let account = Account::sqlx_by_id(id, user.id, &state.db).await?;
+    account.sqlx_delete(&state.db).await?;
+    Ok(())
+}
+
+pub async fn transactions(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Vec<Transaction>>, ApiError> {
+    let account = Account::sqlx_by_id(id, user.id, &state.db).await?;
+    Ok(Json(Transaction::sqlx_by_account(account.id, &state.db).await?))
+}
@@ -0,0 +1,57 @@
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header::AUTHORIZATION, request::Parts},
+};
+
+use super::{ApiError, AppState};
+use crate::{Session, User};
+
+fn bearer_token(parts: &Parts) -> Result<&str, ApiError> {
+    parts
+        .headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .ok_or(ApiError::unauthorized())
+}
+
+/// The user making the request, authenticated with an `Authorization: Bearer <token>` header
+/// holding either a session token or an API key.
+pub struct AuthUser(pub User);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthUser {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = bearer_token(parts)?.to_string();
+        if let Ok(AuthSession(_, user)) = AuthSession::from_request_parts(parts, state).await {
+            return Ok(AuthUser(user));
+        }
+
+        User::sqlx_by_api_key(&token, &state.db)
+            .await
+            .map(AuthUser)
+            .map_err(|_| ApiError::unauthorized())
+    }
+}
+
+/// Like [`AuthUser`], but only for requests made with a session token.
+pub struct AuthSession(pub Session, pub User);
+
+#[async_trait]
+impl FromRequestParts<AppState> for AuthSession {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let session = Session::sqlx_by_token(bearer_token(parts)?, &state.db)
+            .await
+            .map_err(|_| ApiError::unauthorized())?;
+        let user = User::sqlx_by_id(session.user_id, &state.db)
+            .await
+            .map_err(|_| ApiError::unauthorized())?;
+        Ok(AuthSession(session, user))
+    }
+}
@@ -0,0 +1,106 @@
+use axum::{
+    extract::{Query, State},
+    routing::get,
+    Router,
+};
+use serde::Deserialize;
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::oneshot;
+
+/// Query string the bank redirects back with once the end user has finished (or abandoned)
+/// authorising a requisition.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Callback {
+    #[serde(rename = "ref")]
+    pub reference: Option<String>,
+    pub error: Option<String>,
+    pub details: Option<String>,
+}
+
+type Sender = Arc<Mutex<Option<oneshot::Sender<Callback>>>>;
+
+/// A short lived server on localhost that receives the requisition redirect, so the CLI
+/// can carry on as soon as the user is sent back from their bank.
+pub struct CallbackListener {
+    addr: SocketAddr,
+    callback: oneshot::Receiver<Callback>,
+    shutdown: oneshot::Sender<()>,
+}
+
+impl CallbackListener {
+    /// Start listening on `port`, or any free port when it's 0.
+    pub fn bind(port: u16) -> Result<Self, anyhow::Error> {
+        let (sender, callback) = oneshot::channel();
+        let (shutdown, shutdown_signal) = oneshot::channel::<()>();
+        let app = Router::new()
+            .route("/callback", get(receive))
+            .with_state(Arc::new(Mutex::new(Some(sender))));
+
+        let server = axum::Server::try_bind(&SocketAddr::from(([127, 0, 0, 1], port)))?
+            .serve(app.into_make_service());
+        let addr = server.local_addr();
+        tokio::spawn(server.with_graceful_shutdown(async {
+            shutdown_signal.await.ok();
+        }));
+
+        Ok(Self {
+            addr,
+            callback,
+            shutdown,
+        })
+    }
+
+    /// The URL to pass as the requisition's redirect.
+    pub fn redirect_url(&self) -> String {
+        format!("http://localhost:{}/callback", self.addr.port())
+    }
+
+    /// Wait for the redirect, failing if the bank reported an error or nothing arrives in time.
+    pub async fn wait(self, timeout: Duration) -> Result<Callback, anyhow::Error> {
+        let callback = tokio::time::timeout(timeout, self.callback).await;
+        self.shutdown.send(()).ok();
+        let callback = callback
+            .map_err(|_| anyhow::anyhow!("Timed out waiting to be redirected back from the bank"))?
+            .map_err(|e| anyhow::anyhow!(e))?;
+
+        if let Some(error) = &callback.error {
+            anyhow::bail!(
+                "Linking failed: {} {}",
+                error,
+                callback.details.as_deref().unwrap_or_default()
+            );
+        }
+        Ok(callback)
+    }
+}
+
+async fn receive(State(sender): State<Sender>, Query(callback): Query<Callback>) -> &'static str {
+    let message = match callback.error {
+        Some(_) => "Linking failed, check the terminal for details.",
+        None => "Account linked, you can close this window.",
+    };
+    if let Some(sender) = sender.lock().unwrap().take() {
+        sender.send(callback).ok();
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_callback_listener() {
+        let listener = CallbackListener::bind(0).unwrap();
+        let url = format!("{}?ref=abc", listener.redirect_url());
+        let request = tokio::spawn(async move { reqwest::get(url).await.unwrap().text().await.unwrap() });
+
+        let callback = listener.wait(Duration::from_secs(5)).await.unwrap();
+        assert_eq!(callback.reference.as_deref(), Some("abc"));
+        assert_eq!(request.await.unwrap(), "Account linked, you can close this window.");
+    }
+}
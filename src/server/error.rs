@@ -0,0 +1,64 @@
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+
+/// Error returned from handlers, rendered as `{"error": "..."}` with a matching status code.
+#[derive(Debug)]
+pub struct ApiError {
+    pub status: StatusCode,
+    pub error: anyhow::Error,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, error: impl Into<anyhow::Error>) -> Self {
+        Self {
+            status,
+            error: error.into(),
+        }
+    }
+
+    pub fn not_found() -> Self {
+        Self::new(StatusCode::NOT_FOUND, anyhow::anyhow!("Not found"))
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, anyhow::anyhow!("Unauthorized"))
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(error: E) -> Self {
+        let error = error.into();
+        let status = match error.downcast_ref::<sqlx::Error>() {
+            Some(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            _ => StatusCode::INTERNAL_SERVER_ERROR,
+        };
+        Self { status, error }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        if self.status.is_server_error() {
+            log::error!("{:?}", self.error);
+        }
+        let body = serde_json::json!({ "error": format!("{:#}", self.error) });
+        (self.status, Json(body)).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_status_from_error() {
+        assert_eq!(ApiError::from(sqlx::Error::RowNotFound).status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            ApiError::from(anyhow::anyhow!("boom")).status,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+}
@@ -0,0 +1,80 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use super::{ApiError, AppState, AuthUser};
+use crate::{CreateFunction, Function, NewFunction, TestFunction, TestFunctionResult, UpdateFunction};
+
+async fn with_params(mut function: Function) -> Function {
+    function.params = function.get_params().await.ok();
+    function
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<Vec<Function>>, ApiError> {
+    let mut functions = vec![];
+    for function in Function::sqlx_by_user(user.id, &state.db).await? {
+        functions.push(with_params(function).await);
+    }
+    Ok(Json(functions))
+}
+
+/// Functions created through the API are always user scripts.
+pub async fn create(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Json(create): Json<CreateFunction>,
+) -> Result<Json<Function>, ApiError> {
+    let function = NewFunction {
+        name: create.name,
+        function_type: "script".to_string(),
+        source: create.source,
+        user_id: user.id,
+    }
+    .sqlx_create(&state.db)
+    .await?;
+    Ok(Json(with_params(function).await))
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Function>, ApiError> {
+    let function = Function::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    Ok(Json(with_params(function).await))
+}
+
+pub async fn update(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+    Json(mut update): Json<UpdateFunction>,
+) -> Result<Json<Function>, ApiError> {
+    let function = Function::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    update.id = Some(function.id);
+    Ok(Json(with_params(update.sqlx_update(&state.db).await?).await))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<(), ApiError> {
+    let function = Function::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    function.sqlx_delete(&state.db).await?;
+    Ok(())
+}
+
+pub async fn test(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+    Json(test): Json<TestFunction>,
+) -> Result<Json<TestFunctionResult>, ApiError> {
+    let function = Function::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    Ok(Json(function.test(test).await?))
+}
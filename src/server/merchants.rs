@@ -0,0 +1,16 @@
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use super::{ApiError, AppState, AuthUser};
+use crate::Merchant;
+
+/// Merchants are shared between users, so any authenticated user can look one up.
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser(_): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Merchant>, ApiError> {
+    Ok(Json(Merchant::sqlx_by_id(id, &state.db).await?))
+}
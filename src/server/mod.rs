@@ -0,0 +1,85 @@
+use axum::{
+    routing::{delete, get, post, put},
+    Router,
+};
+use std::{env, net::SocketAddr, path::PathBuf};
+use tower_http::{
+    cors::CorsLayer,
+    services::{ServeDir, ServeFile},
+    trace::TraceLayer,
+};
+
+mod accounts;
+mod auth;
+mod callback;
+mod error;
+mod functions;
+mod merchants;
+mod sessions;
+mod transactions;
+mod trigger_log;
+mod triggers;
+mod users;
+
+pub use auth::{AuthSession, AuthUser};
+pub use callback::CallbackListener;
+pub use error::ApiError;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub db: sqlx::MySqlPool,
+}
+
+pub fn router(db: sqlx::MySqlPool) -> Router {
+    let api = Router::new()
+        .route("/sessions", get(sessions::list).post(sessions::create))
+        .route("/sessions/current", delete(sessions::delete_current))
+        .route("/users/me", get(users::me).put(users::update_me))
+        .route("/accounts", get(accounts::list))
+        .route(
+            "/accounts/:id",
+            get(accounts::get).put(accounts::update).delete(accounts::delete),
+        )
+        .route("/accounts/:id/transactions", get(accounts::transactions))
+        .route("/transactions", get(transactions::list))
+        .route(
+            "/transactions/:id",
+            get(transactions::get).delete(transactions::delete),
+        )
+        .route("/transactions/:id/merchant", put(transactions::set_merchant))
+        .route("/merchants/:id", get(merchants::get))
+        .route("/functions", get(functions::list).post(functions::create))
+        .route(
+            "/functions/:id",
+            get(functions::get).put(functions::update).delete(functions::delete),
+        )
+        .route("/functions/:id/test", post(functions::test))
+        .route("/triggers", get(triggers::list).post(triggers::create))
+        .route(
+            "/triggers/:id",
+            get(triggers::get).put(triggers::update).delete(triggers::delete),
+        )
+        .route("/triggers/:id/log", get(triggers::log))
+        .route("/trigger-log", get(trigger_log::list))
+        .route("/trigger-log/:id", get(trigger_log::get));
+
+    // The frontend is a single page app, so unknown paths fall back to its index.
+    let frontend = PathBuf::from(env::var("FRONTEND_DIR").unwrap_or("frontend/dist".to_string()));
+    let frontend = ServeDir::new(&frontend).fallback(ServeFile::new(frontend.join("index.html")));
+
+    Router::new()
+        .nest("/api", api)
+        .fallback_service(frontend)
+        .layer(CorsLayer::permissive())
+        .layer(TraceLayer::new_for_http())
+        .with_state(AppState { db })
+}
+
+pub async fn start(port: u16, db: sqlx::MySqlPool) -> Result<(), anyhow::Error> {
+    let addr = SocketAddr::from(([0, 0, 0, 0], port));
+    log::info!("Listening on {}", addr);
+    axum::Server::bind(&addr)
+        .serve(router(db).into_make_service())
+        .await
+        .map_err(|e| anyhow::anyhow!(e))
+}
@@ -0,0 +1,38 @@
+use axum::{extract::State, Json};
+
+use super::{ApiError, AppState, AuthSession, AuthUser};
+use crate::{CreateSession, NewSession, Session, SessionToken, User};
+
+pub async fn create(
+    State(state): State<AppState>,
+    Json(create): Json<CreateSession>,
+) -> Result<Json<SessionToken>, ApiError> {
+    let user = User::sqlx_by_email(&create.email, &state.db)
+        .await
+        .ok()
+        .filter(|user| user.verify_password(&create.password))
+        .ok_or(ApiError::unauthorized())?;
+
+    let (session, token) = NewSession { user_id: user.id }.sqlx_create(&state.db).await?;
+    Ok(Json(SessionToken {
+        token,
+        expires_at: session.expires_at,
+        user,
+    }))
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<Vec<Session>>, ApiError> {
+    Ok(Json(Session::sqlx_by_user(user.id, &state.db).await?))
+}
+
+/// Log out, revoking the session used to make the request.
+pub async fn delete_current(
+    State(state): State<AppState>,
+    AuthSession(session, _): AuthSession,
+) -> Result<(), ApiError> {
+    session.sqlx_delete(&state.db).await?;
+    Ok(())
+}
@@ -0,0 +1,74 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+use serde::Deserialize;
+
+use super::{ApiError, AppState, AuthUser};
+use crate::{ultrafinance, Transaction, TransactionWithMerchant};
+
+#[derive(Deserialize)]
+pub struct TransactionsQuery {
+    #[serde(default)]
+    search: String,
+    page: Option<u32>,
+    per_page: Option<u32>,
+}
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Query(query): Query<TransactionsQuery>,
+) -> Result<Json<Vec<TransactionWithMerchant>>, ApiError> {
+    let transactions = Transaction::sqlx_by_user_by_search(
+        user.id,
+        &query.search,
+        query.per_page.unwrap_or(50).clamp(1, 500),
+        query.page.unwrap_or(1).max(1),
+        &state.db,
+    )
+    .await?;
+
+    let mut with_merchants = vec![];
+    for transaction in transactions {
+        with_merchants.push(TransactionWithMerchant::sqlx_from_transaction(transaction, &state.db).await?);
+    }
+    Ok(Json(with_merchants))
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<TransactionWithMerchant>, ApiError> {
+    let transaction = Transaction::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    Ok(Json(TransactionWithMerchant::sqlx_from_transaction(transaction, &state.db).await?))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SetMerchant {
+    merchant_id: u32,
+}
+
+/// Correct the transaction's merchant. Later transactions like it are matched to the same one.
+pub async fn set_merchant(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+    Json(body): Json<SetMerchant>,
+) -> Result<Json<TransactionWithMerchant>, ApiError> {
+    let transaction = Transaction::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    let (transaction, _) = ultrafinance::sqlx_confirm_merchant(transaction, body.merchant_id, &state.db).await?;
+    Ok(Json(TransactionWithMerchant::sqlx_from_transaction(transaction, &state.db).await?))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<(), ApiError> {
+    let transaction = Transaction::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    transaction.sqlx_delete(&state.db).await?;
+    Ok(())
+}
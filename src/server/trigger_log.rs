@@ -0,0 +1,28 @@
+use axum::{
+    extract::{Path, Query, State},
+    Json,
+};
+
+use super::{ApiError, AppState, AuthUser};
+use crate::{TriggerLog, TriggerLogFilter};
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Query(mut filter): Query<TriggerLogFilter>,
+) -> Result<Json<Vec<TriggerLog>>, ApiError> {
+    filter.user_id = Some(user.id);
+    Ok(Json(TriggerLog::sqlx_by_filter(&filter, &state.db).await?))
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<TriggerLog>, ApiError> {
+    let log = TriggerLog::sqlx_by_id(id, &state.db).await?;
+    if log.user_id != user.id {
+        return Err(ApiError::not_found());
+    }
+    Ok(Json(log))
+}
@@ -0,0 +1,89 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Json,
+};
+
+use super::{ApiError, AppState, AuthUser};
+use crate::{
+    CreateTrigger, Function, NewTrigger, Trigger, TriggerFilter, TriggerLog, TriggerLogFilter,
+    UpdateTrigger,
+};
+
+pub async fn list(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+) -> Result<Json<Vec<Trigger>>, ApiError> {
+    Ok(Json(Trigger::sqlx_by_user(user.id, &state.db).await?))
+}
+
+pub async fn create(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Json(create): Json<CreateTrigger>,
+) -> Result<Json<Trigger>, ApiError> {
+    let function = Function::sqlx_by_id_by_user(create.function_id, user.id, &state.db)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.context("Function not found")))?;
+    let trigger = NewTrigger {
+        event: create.event,
+        name: create.name,
+        filter: TriggerFilter::default(),
+        params: serde_json::to_string(&create.params)?,
+        user_id: user.id,
+        function_id: function.id,
+    }
+    .sqlx_create(&state.db)
+    .await?;
+    Ok(Json(trigger))
+}
+
+pub async fn get(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<Json<Trigger>, ApiError> {
+    Ok(Json(Trigger::sqlx_by_id_by_user(id, user.id, &state.db).await?))
+}
+
+pub async fn update(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+    Json(mut update): Json<UpdateTrigger>,
+) -> Result<Json<Trigger>, ApiError> {
+    let trigger = Trigger::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    if let Some(function_id) = update.function_id {
+        Function::sqlx_by_id_by_user(function_id, user.id, &state.db)
+            .await
+            .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e.context("Function not found")))?;
+    }
+    update.id = Some(trigger.id);
+    let trigger = update
+        .sqlx_update(&state.db)
+        .await
+        .map_err(|e| ApiError::new(StatusCode::BAD_REQUEST, e))?;
+    Ok(Json(trigger))
+}
+
+pub async fn delete(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+) -> Result<(), ApiError> {
+    let trigger = Trigger::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    trigger.sqlx_delete(&state.db).await?;
+    Ok(())
+}
+
+pub async fn log(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    Path(id): Path<u32>,
+    Query(mut filter): Query<TriggerLogFilter>,
+) -> Result<Json<Vec<TriggerLog>>, ApiError> {
+    let trigger = Trigger::sqlx_by_id_by_user(id, user.id, &state.db).await?;
+    filter.trigger_id = Some(trigger.id);
+    filter.user_id = Some(user.id);
+    Ok(Json(TriggerLog::sqlx_by_filter(&filter, &state.db).await?))
+}
@@ -0,0 +1,46 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde::Deserialize;
+
+use super::{ApiError, AppState, AuthSession, AuthUser};
+use crate::{Session, UpdateUser, User};
+
+/// Changes to the signed in user. Changing the email or password needs the current password.
+#[derive(Deserialize, Debug)]
+pub struct UpdateMe {
+    #[serde(flatten)]
+    pub user: UpdateUser,
+    pub current_password: Option<String>,
+}
+
+pub async fn me(AuthUser(user): AuthUser) -> Json<User> {
+    Json(user)
+}
+
+/// Changing the password signs the user out everywhere else.
+pub async fn update_me(
+    State(state): State<AppState>,
+    AuthUser(user): AuthUser,
+    session: Option<AuthSession>,
+    Json(UpdateMe { user: mut update, current_password }): Json<UpdateMe>,
+) -> Result<Json<User>, ApiError> {
+    let changes_password = update.password.is_some();
+    let changes_email = update.email.as_ref().is_some_and(|email| *email != user.email);
+    if (changes_password || changes_email)
+        && !current_password.is_some_and(|password| user.verify_password(&password))
+    {
+        return Err(ApiError::new(
+            StatusCode::FORBIDDEN,
+            anyhow::anyhow!("The current password is needed to change the email or password"),
+        ));
+    }
+
+    update.id = Some(user.id);
+    let updated = update.sqlx_update(&state.db).await?;
+    if changes_password {
+        match session {
+            Some(AuthSession(session, _)) => Session::sqlx_delete_other_by_user(user.id, session.id, &state.db).await?,
+            None => Session::sqlx_delete_by_user(user.id, &state.db).await?,
+        };
+    }
+    Ok(Json(updated))
+}
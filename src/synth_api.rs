@@ -84,11 +84,11 @@ impl Client {
 
     pub async fn get_merchants(
         &self,
-        transactions: &Vec<Transaction>,
+        transactions: &[Transaction],
     ) -> Result<HashMap<u32, NewMerchant>, anyhow::Error> {
         let max_concurrent_requests = 10; // Limit the number of concurrent requests
 
-        let requests = stream::iter(transactions.iter().map(|transaction| {
+        let requests = transactions.iter().map(|transaction| {
             let url = format!("https://api.synthfinance.com/enrich?description");
             let reqwest_client = self.reqwest.clone();
             let api_key = self.api_key.clone();
@@ -106,7 +106,7 @@ impl Client {
                             .unwrap_or("".to_string())
                     ),
                 ),
-                ("amount", transaction.transaction_amount.clone()),
+                ("amount", transaction.transaction_amount.to_string()),
                 (
                     "country",
                     transaction
@@ -146,8 +146,9 @@ impl Client {
                     }
                 }
             }
-        }))
-        .buffer_unordered(max_concurrent_requests);
+        });
+        // Collected first so the returned future is Send.
+        let requests = stream::iter(requests.collect::<Vec<_>>()).buffer_unordered(max_concurrent_requests);
 
         let results = requests.collect::<Vec<_>>().await;
         let mut enriched = HashMap::new();
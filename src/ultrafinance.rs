@@ -1,13 +1,18 @@
-use crate::accounts::SourceAccount;
-use crate::{models::*, synth_api};
+use crate::accounts::{nordigen::Nordigen, SourceAccount};
+use crate::merchant_enricher::{EnricherChain, MerchantEnricher};
+use crate::{models::exchange_rate::Converter, models::*};
 use anyhow::anyhow;
 use async_trait::async_trait;
-use chrono::Duration;
+use chrono::{Duration, NaiveDate};
 use log::info;
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{hash_map::Entry, HashMap};
 use std::fmt::Display;
 use std::hash::Hash;
+use std::ops::{Add, Neg, Sub};
+use std::str::FromStr;
 use std::{env};
 
 pub fn is_dev() -> bool {
@@ -19,19 +24,44 @@ pub fn is_dev() -> bool {
 pub async fn sqlx_import_transactions(
     account: &Account,
     db: &sqlx::MySqlPool,
+) -> anyhow::Result<Vec<Transaction>> {
+    sqlx_import_transactions_since(account, None, true, db).await
+}
+
+/// Import the account's history from `from_date`, such as when it's first linked. Triggers
+/// aren't run for the backfilled transactions.
+pub async fn sqlx_backfill_transactions(
+    account: &Account,
+    from_date: NaiveDate,
+    db: &sqlx::MySqlPool,
+) -> anyhow::Result<Vec<Transaction>> {
+    sqlx_import_transactions_since(account, Some(from_date), false, db).await
+}
+
+async fn sqlx_import_transactions_since(
+    account: &Account,
+    since: Option<NaiveDate>,
+    queue_triggers: bool,
+    db: &sqlx::MySqlPool,
 ) -> anyhow::Result<Vec<Transaction>> {
     info!("Importing transactions for account: {}", account.id);
-    let latest_transaction = sqlx::query_as!(
-        Transaction,
-        "SELECT * FROM transactions WHERE account_id = ? ORDER BY booking_date DESC LIMIT 1",
-        account.id
+    let latest_transaction = sqlx::query_as::<_, Transaction>(
+        "SELECT * FROM transactions WHERE account_id = ? AND status = ? ORDER BY booking_date DESC LIMIT 1",
     )
+    .bind(account.id)
+    .bind(Transaction::BOOKED)
     .fetch_one(db)
     .await;
-    // Get all transactions from 7 days before the last transaction to account for slow to confirm transaction.
+    let mut pending = Transaction::sqlx_pending_by_account(account.id, db).await?;
+    // Get all transactions from 7 days before the last transaction to account for slow to confirm transaction,
+    // and from the oldest pending transaction so it can be matched to its booked counterpart.
     let from_date = latest_transaction
         .map(|t| t.booking_date - Duration::days(7))
-        .ok();
+        .ok()
+        .into_iter()
+        .chain(pending.first().map(|t| t.booking_date))
+        .chain(since)
+        .min();
 
     info!(
         "Date of latest transaction for account minus 1 week to account for pending: {:?}",
@@ -50,40 +80,87 @@ pub async fn sqlx_import_transactions(
         account.id
     );
 
+    // Pending transactions the source still lists as pending can't have been booked yet.
+    let still_pending = other_transactions
+        .iter()
+        .filter(|t| t.pending)
+        .map(|t| t.id.clone())
+        .collect::<std::collections::HashSet<_>>();
+
+    let since = other_transactions.iter().map(|t| t.booking_date).min();
+    let converter = Converter::sqlx_for_user(account.user_id, since, db).await?;
     let mut new_transactions: Vec<transaction::NewTransaction> = vec![];
+    let mut booked_transactions: Vec<Transaction> = vec![];
     for transaction in other_transactions {
-        let exists = sqlx::query_scalar!(
-            "SELECT EXISTS(SELECT 1 FROM transactions WHERE external_id = ? AND account_id = ?)",
-            &transaction.id,
-            &account.id
-        )
-        .fetch_one(db)
-        .await?;
-        if exists > 0 {
-            info!("Transaction {} already exists", transaction.id);
-            continue;
-        }
-
         let mut new_transaction = NewTransaction::from(transaction);
         new_transaction.account_id = account.id;
         new_transaction.user_id = account.user_id;
+        new_transaction.converted_amount =
+            converter.convert_on(&new_transaction.transaction_amount, new_transaction.booking_date);
+
+        if let Some(mut existing) = Transaction::sqlx_by_external_id(account.id, &new_transaction.external_id, db).await? {
+            // Some banks keep the id when a pending transaction is booked.
+            if existing.status == Transaction::PENDING && new_transaction.status == Transaction::BOOKED {
+                info!("Pending transaction {} was booked", existing.id);
+                pending.retain(|t| t.id != existing.id);
+                booked_transactions.push(existing.sqlx_book(new_transaction, db).await?);
+            } else {
+                info!("Transaction {} already exists", existing.external_id);
+            }
+            continue;
+        }
+
+        if new_transaction.status == Transaction::BOOKED {
+            let matched = pending
+                .iter()
+                .enumerate()
+                .filter(|(_, t)| !still_pending.contains(&t.external_id))
+                .filter_map(|(i, t)| t.pending_match_score(&new_transaction).map(|score| (i, score)))
+                .max_by(|(_, a), (_, b)| a.total_cmp(b))
+                .map(|(i, _)| i);
+            if let Some(i) = matched {
+                let mut existing = pending.remove(i);
+                info!("Pending transaction {} was booked as {}", existing.id, new_transaction.external_id);
+                booked_transactions.push(existing.sqlx_book(new_transaction, db).await?);
+                continue;
+            }
+        }
         new_transactions.push(new_transaction);
     }
 
+    // Pending transactions that are no longer listed and weren't booked were cancelled, like
+    // a released card authorization.
+    for transaction in pending.into_iter().filter(|t| !still_pending.contains(&t.external_id)) {
+        info!("Removing pending transaction {} that is no longer listed", transaction.id);
+        transaction.sqlx_delete(db).await?;
+    }
+
+    for transaction in booked_transactions.iter().filter(|_| queue_triggers) {
+        queue_triggers_for_transaction(transaction, Trigger::TRANSACTION_BOOKED, db).await?;
+    }
+
     if new_transactions.is_empty() {
-        return Ok(vec![]);
+        return Ok(booked_transactions);
     }
+    // Before inserting, so a misconfigured enricher doesn't leave transactions without triggers.
+    let enricher = EnricherChain::from_env(db)?;
 
-    let mut qb = sqlx::QueryBuilder::new("INSERT INTO transactions (external_id, creditor_name, debtor_name, remittance_information, booking_date, booking_datetime, transaction_amount, transaction_amount_currency, proprietary_bank_transaction_code, currency_exchange_rate, currency_exchange_source_currency, currency_exchange_target_currency, account_id, user_id)");
+    let mut qb = sqlx::QueryBuilder::new("INSERT INTO transactions (external_id, status, creditor_name, debtor_name, creditor_account, debtor_account, remittance_information, booking_date, booking_datetime, value_date, transaction_amount, transaction_amount_currency, converted_amount, converted_currency, proprietary_bank_transaction_code, currency_exchange_rate, currency_exchange_source_currency, currency_exchange_target_currency, account_id, user_id)");
     qb.push_values(new_transactions, |mut b, t| {
         b.push_bind(t.external_id);
+        b.push_bind(t.status);
         b.push_bind(t.creditor_name);
         b.push_bind(t.debtor_name);
+        b.push_bind(t.creditor_account);
+        b.push_bind(t.debtor_account);
         b.push_bind(t.remittance_information);
         b.push_bind(t.booking_date);
         b.push_bind(t.booking_datetime);
-        b.push_bind(t.transaction_amount);
-        b.push_bind(t.transaction_amount_currency);
+        b.push_bind(t.value_date);
+        b.push_bind(t.transaction_amount.amount);
+        b.push_bind(t.transaction_amount.currency);
+        b.push_bind(t.converted_amount.as_ref().map(|c| c.amount));
+        b.push_bind(t.converted_amount.map(|c| c.currency));
         b.push_bind(t.proprietary_bank_transaction_code);
         b.push_bind(t.currency_exchange_rate);
         b.push_bind(t.currency_exchange_source_currency);
@@ -95,12 +172,11 @@ pub async fn sqlx_import_transactions(
     let insert = query.execute(db).await?;
 
     // Hack to get all the transactions inserted
-    let inserted_transactions: Vec<Transaction> = sqlx::query_as!(
-        Transaction,
+    let inserted_transactions: Vec<Transaction> = sqlx::query_as::<_, Transaction>(
         "SELECT * FROM transactions WHERE account_id = ? ORDER BY id DESC LIMIT ?",
-        account.id,
-        insert.rows_affected()
     )
+    .bind(account.id)
+    .bind(insert.rows_affected())
     .fetch_all(db)
     .await?;
 
@@ -111,7 +187,7 @@ pub async fn sqlx_import_transactions(
     );
 
     // Enrich the transactions that were inserted
-    let inserted_transactions = sqlx_enrich_transactions(inserted_transactions, db).await?;
+    let inserted_transactions = sqlx_enrich_transactions(inserted_transactions, &enricher, db).await?;
     // TODO: reenable when we have credits.
 
     info!(
@@ -120,23 +196,30 @@ pub async fn sqlx_import_transactions(
         account.id
     );
 
-    // Create the triggers
-    for transaction in &inserted_transactions {
-        run_triggers_for_transaction(transaction, db).await?;
+    // Queue the triggers, they are run by `process_trigger_queue`.
+    for transaction in inserted_transactions.iter().filter(|_| queue_triggers) {
+        queue_triggers_for_transaction(transaction, Trigger::TRANSACTION_CREATED, db).await?;
+        let event = match transaction.status.as_str() {
+            Transaction::PENDING => Trigger::TRANSACTION_PENDING,
+            _ => Trigger::TRANSACTION_BOOKED,
+        };
+        queue_triggers_for_transaction(transaction, event, db).await?;
     }
-    Ok(inserted_transactions)
+    Ok(inserted_transactions.into_iter().chain(booked_transactions).collect())
 }
 
-pub async fn run_triggers_for_transaction(
+pub async fn queue_triggers_for_transaction(
     transaction: &Transaction,
+    event: &str,
     db: &sqlx::MySqlPool,
-) -> anyhow::Result<()> {
+) -> anyhow::Result<Vec<TriggerQueue>> {
     let transaction_triggers: Vec<Trigger> =
-        Trigger::sqlx_for_user_for_event(transaction.user_id, "transaction_created", db).await?;
+        Trigger::sqlx_for_user_for_event(transaction.user_id, event, db).await?;
+    let merchant = transaction.sqlx_merchant(db).await?;
 
     let transaction_triggers = transaction_triggers
         .into_iter()
-        .filter(|trigger| trigger.filter.matches(transaction))
+        .filter(|trigger| trigger.filter.matches(transaction, merchant.as_ref()))
         .collect::<Vec<Trigger>>();
 
     info!(
@@ -144,16 +227,212 @@ pub async fn run_triggers_for_transaction(
         transaction_triggers.len()
     );
 
-    use futures::stream::{self, StreamExt};
+    let mut queued = vec![];
+    for trigger in transaction_triggers {
+        let entry = NewTriggerQueue {
+            payload: TriggerQueuePayload::Transaction {
+                transaction_id: transaction.id,
+            },
+            user_id: transaction.user_id,
+            trigger_id: trigger.id,
+        }
+        .sqlx_create(db)
+        .await?;
+        queued.push(entry);
+    }
+    Ok(queued)
+}
+
+pub async fn queue_triggers_for_requisition(
+    requisition: &Requisition,
+    event: &str,
+    db: &sqlx::MySqlPool,
+) -> anyhow::Result<Vec<TriggerQueue>> {
+    // Filters only apply to transactions, so every trigger for the event runs.
+    let triggers = Trigger::sqlx_for_user_for_event(requisition.user_id, event, db).await?;
+    info!("Adding {} triggers for requisition.", triggers.len());
+
+    let mut queued = vec![];
+    for trigger in triggers {
+        let entry = NewTriggerQueue {
+            payload: TriggerQueuePayload::Requisition {
+                requisition_id: requisition.id,
+            },
+            user_id: requisition.user_id,
+            trigger_id: trigger.id,
+        }
+        .sqlx_create(db)
+        .await?;
+        queued.push(entry);
+    }
+    Ok(queued)
+}
+
+/// Fetch the account's balances into its balance history, and queue `account_balance_updated`
+/// triggers when any of them changed since the last fetch.
+pub async fn sqlx_update_balances(account: &mut Account, db: &sqlx::MySqlPool) -> anyhow::Result<Vec<AccountBalance>> {
+    let previous = AccountBalance::sqlx_latest_by_account(account.id, db).await?;
+    let old_balance = account.current_balance();
+    let source_balances = account.update_balance().await?;
+    account.sqlx_update(db).await?;
+
+    let since = source_balances.iter().filter_map(|b| b.reference_date).min();
+    let converter = Converter::sqlx_for_user(account.user_id, since, db).await?;
+    let mut balances = vec![];
+    for balance in source_balances {
+        balances.push(NewAccountBalance::from_source(account.id, balance, &converter).sqlx_create(db).await?);
+    }
+
+    // On the first fetch there's nothing to have changed from.
+    if previous.is_empty() {
+        return Ok(balances);
+    }
+    let changes = BalanceChange::between(&previous, &balances);
+    if !changes.iter().any(BalanceChange::is_changed) {
+        return Ok(balances);
+    }
+
+    let triggers = Trigger::sqlx_for_user_for_event(account.user_id, Trigger::ACCOUNT_BALANCE_UPDATED, db).await?;
+    info!("Adding {} triggers for account balance.", triggers.len());
+    for trigger in triggers {
+        NewTriggerQueue {
+            payload: TriggerQueuePayload::AccountBalance {
+                account_id: account.id,
+                old_balance: old_balance.clone(),
+                new_balance: account.current_balance(),
+                balances: changes.clone(),
+            },
+            user_id: account.user_id,
+            trigger_id: trigger.id,
+        }
+        .sqlx_create(db)
+        .await?;
+    }
+    Ok(balances)
+}
+
+/// Recalculate the user's converted transaction amounts and balances, such as after changing
+/// their primary currency or fetching exchange rates. Returns how many couldn't be converted.
+pub async fn sqlx_convert_amounts(user_id: u32, db: &sqlx::MySqlPool) -> anyhow::Result<usize> {
+    let transactions = Transaction::sqlx_by_user(user_id, u32::MAX, db).await?;
+    let balances = AccountBalance::sqlx_by_user(user_id, db).await?;
+    let balance_date = |balance: &AccountBalance| balance.reference_date.unwrap_or(balance.created_at.date());
+    let since = transactions
+        .iter()
+        .map(|t| t.booking_date)
+        .chain(balances.iter().map(balance_date))
+        .min();
+    let converter = Converter::sqlx_for_user(user_id, since, db).await?;
+    let mut unconverted = 0;
+    for mut transaction in transactions {
+        let converted = converter.convert_on(&transaction.amount(), transaction.booking_date);
+        unconverted += usize::from(converted.is_none());
+        transaction.sqlx_set_converted(converted, db).await?;
+    }
+    for mut balance in balances {
+        let date = balance_date(&balance);
+        let converted = converter.convert_on(&Money::new(balance.amount, balance.currency), date);
+        unconverted += usize::from(converted.is_none());
+        balance.sqlx_set_converted(converted, db).await?;
+    }
+    Ok(unconverted)
+}
 
-    stream::iter(&transaction_triggers)
-        .for_each_concurrent(None, |trigger| async move {
-            if let Err(e) = trigger.sqlx_run(transaction, db).await {
-                eprintln!("Failed to run trigger: {:?}", e);
+/// Refresh the status of linked requisitions and queue `requisition_expiring` triggers
+/// for those whose consent lapses within `days`. Each requisition is only notified once.
+pub async fn sqlx_check_requisitions(days: u32, db: &sqlx::MySqlPool) -> anyhow::Result<Vec<Requisition>> {
+    let client = Nordigen::new();
+    for mut requisition in Requisition::sqlx_all(db).await? {
+        if requisition.status != Requisition::LINKED {
+            continue;
+        }
+        let status = match client.get_requisition(&requisition.nordigen_id).await {
+            Ok(nordigen_requisition) => nordigen_requisition.status,
+            Err(err) => {
+                log::error!("Failed to check requisition {}: {:?}", requisition.id, err);
+                continue;
             }
-        })
-        .await;
-    Ok(())
+        };
+        if status != requisition.status {
+            info!("Requisition {} is now {}.", requisition.id, status);
+            requisition.sqlx_update_status(&status, None, db).await?;
+        }
+    }
+
+    let before = chrono::Local::now().naive_local() + Duration::days(days.into());
+    let mut expiring = Requisition::sqlx_expiring(before, db).await?;
+    for requisition in expiring.iter_mut() {
+        queue_triggers_for_requisition(requisition, Trigger::REQUISITION_EXPIRING, db).await?;
+        requisition.sqlx_mark_expiring_notified(db).await?;
+    }
+    Ok(expiring)
+}
+
+/// Run the queue entries that are due. Failed entries are rescheduled with
+/// exponential backoff until they run out of attempts.
+pub async fn process_trigger_queue(
+    limit: u32,
+    db: &sqlx::MySqlPool,
+) -> anyhow::Result<HashMap<u32, anyhow::Result<()>>> {
+    let entries = TriggerQueue::sqlx_due(limit, db).await?;
+    info!("Processing {} trigger queue entries.", entries.len());
+
+    let results = futures::future::join_all(entries.into_iter().map(|mut entry| async move {
+        let result = match run_trigger_queue_entry(&entry, db).await {
+            // The trigger or what it's for has been deleted since, so there's nothing to retry.
+            Err(err) if matches!(err.downcast_ref::<sqlx::Error>(), Some(sqlx::Error::RowNotFound)) => {
+                info!("Dropping trigger queue entry {}: {:#}", entry.id, err);
+                Ok(())
+            }
+            result => result,
+        };
+        let recorded = match &result {
+            Ok(()) => entry.sqlx_delete(db).await,
+            Err(err) => {
+                log::error!("Failed to run trigger queue entry {}: {:?}", entry.id, err);
+                entry.sqlx_record_failure(err, db).await
+            }
+        };
+        if let Err(err) = recorded {
+            log::error!("Failed to update trigger queue entry {}: {:?}", entry.id, err);
+        }
+        (entry.id, result)
+    }))
+    .await;
+
+    Ok(results.into_iter().collect())
+}
+
+async fn run_trigger_queue_entry(entry: &TriggerQueue, db: &sqlx::MySqlPool) -> anyhow::Result<()> {
+    let trigger = Trigger::sqlx_by_id(entry.trigger_id, db).await?;
+    match entry.payload()? {
+        TriggerQueuePayload::Transaction { transaction_id } => {
+            let transaction = Transaction::sqlx_by_id(transaction_id, db).await?;
+            trigger.sqlx_run(&transaction, db).await
+        }
+        TriggerQueuePayload::Requisition { requisition_id } => {
+            let requisition = Requisition::sqlx_by_id(requisition_id, db).await?;
+            let payload = RequisitionWithAccounts {
+                accounts: requisition.sqlx_accounts(db).await?,
+                requisition,
+            };
+            trigger.sqlx_run_event(&serde_json::to_value(payload)?, db).await
+        }
+        TriggerQueuePayload::AccountBalance {
+            account_id,
+            old_balance,
+            new_balance,
+            balances,
+        } => {
+            let payload = serde_json::json!({
+                "account": Account::sqlx_by_id_only(account_id, db).await?,
+                "old_balance": old_balance,
+                "new_balance": new_balance,
+                "balances": balances,
+            });
+            trigger.sqlx_run_event(&payload, db).await
+        }
+    }
 }
 
 pub async fn sqlx_sync_accounts(
@@ -180,17 +459,48 @@ pub async fn sqlx_sync_accounts(
 
 pub async fn sqlx_enrich_transactions(
     transactions: Vec<Transaction>,
+    enricher: &impl MerchantEnricher,
     db: &sqlx::MySqlPool,
 ) -> anyhow::Result<Vec<Transaction>> {
-    let enriched_transactions = synth_api::Client::new(env::var("SYNTH_API_KEY").unwrap())
-        .get_merchants(&transactions)
-        .await?;
     let mut returned_transactions: Vec<Transaction> = vec![];
     let mut matched_enriched_transactions: Vec<u32> = vec![];
 
-    for (t_id, merchant) in enriched_transactions {
+    // Merchants we already know by a rule don't need an enricher.
+    let mut rules: HashMap<u32, Vec<MerchantRule>> = HashMap::new();
+    let mut to_enrich = vec![];
+    for mut transaction in transactions.iter().cloned() {
+        let user_rules = match rules.entry(transaction.user_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(MerchantRule::sqlx_for_user(transaction.user_id, db).await?),
+        };
+        match MerchantRule::find_match(user_rules, &transaction) {
+            Some(rule) => {
+                info!("Transaction {} matched merchant rule {}", transaction.id, rule.id);
+                transaction.merchant_id = Some(rule.merchant_id);
+                matched_enriched_transactions.push(transaction.id);
+                returned_transactions.push(transaction.sqlx_update(db).await?);
+            }
+            None => to_enrich.push(transaction),
+        }
+    }
+
+    let enriched_transactions = match to_enrich.is_empty() {
+        true => HashMap::new(),
+        false => enricher.enrich(&to_enrich).await?,
+    };
+
+    for (t_id, enrichment) in enriched_transactions {
         let mut transaction: Transaction = Transaction::sqlx_by_id(t_id, db).await?;
         matched_enriched_transactions.push(t_id);
+        let labels = Some(enrichment.labels.join(",")).filter(|labels| !labels.is_empty());
+        let mut merchant = enrichment.merchant;
+        if merchant.labels.is_none() {
+            merchant.labels = labels.clone();
+        }
+        transaction.labels = labels;
+        transaction.recurrence = enrichment.recurrence;
+        transaction.mcc = Some(enrichment.mcc.iter().map(i32::to_string).collect::<Vec<_>>().join(","))
+            .filter(|mcc| !mcc.is_empty());
         match merchant.sqlx_create_or_fetch(db).await {
             Ok(merchant) => {
                 transaction.merchant_id = Some(merchant.id);
@@ -215,54 +525,24 @@ pub async fn sqlx_enrich_transactions(
     Ok(returned_transactions)
 }
 
-mod tests {
+/// Set the merchant the user says the transaction is for, and learn a rule so their future
+/// transactions from the same counterparty get it without an enricher.
+pub async fn sqlx_confirm_merchant(
+    mut transaction: Transaction,
+    merchant_id: u32,
+    db: &sqlx::MySqlPool,
+) -> anyhow::Result<(Transaction, Option<MerchantRule>)> {
+    let merchant = Merchant::sqlx_by_id(merchant_id, db).await?;
+    transaction.merchant_id = Some(merchant.id);
+    let transaction = transaction.sqlx_update(db).await?;
+    let rule = match MerchantRule::learn_from(&transaction, merchant.id) {
+        Some(rule) => Some(rule.sqlx_create_or_update(db).await?),
+        None => None,
+    };
+    Ok((transaction, rule))
+}
 
-    // use crate::deno::FunctionRuntime;
-
-    // #[test]
-    // pub fn test_parallel() {
-    //     let rt = tokio::runtime::Runtime::new().unwrap();
-    //     let local = tokio::task::LocalSet::new();
-    //     local.block_on(&rt, async {
-    //         let mut handles = Vec::new();
-    //         let rt_handle = tokio::runtime::Handle::current();
-
-    //         let handle = thread::spawn(move || {
-    //             // Use the handle to the runtime to block on the future
-    //             let result = rt_handle.block_on(async {
-    //                 let function = Function {
-    //                     id: 1,
-    //                     name: "Test".into(),
-    //                     function_type: "source".into(),
-    //                     source: "export default async function () {
-    //                         return new Promise((resolve, reject) => {
-    //                             setTimeout(() => {
-    //                                 resolve('Hello');
-    //                             }, 1000);
-    //                         });
-    //                     }"
-    //                     .into(),
-    //                     user_id: 1,
-    //                     created_at: NaiveDateTime::from_timestamp_opt(0, 0).unwrap(),
-    //                     updated_at: NaiveDateTime::from_timestamp_opt(0, 0).unwrap(),
-    //                 };
-    //                 let mut function_runtime = FunctionRuntime::new(&function).await.unwrap();
-    //                 let r = function_runtime.run("{}", "{}").await;
-    //                 r
-    //             });
-    //             result
-    //         });
-    //         handles.push(handle);
-
-    //         for handle in handles {
-    //             let result = handle.join().unwrap().unwrap();
-    //             assert_eq!(result, r#""Hello""#.to_string())
-    //         }
-    //     });
-    // }
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Eq, PartialEq)]
 pub struct Currency(iso_currency::Currency);
 
 impl Display for Currency {
@@ -317,6 +597,14 @@ impl sqlx::Type<sqlx::MySql> for Currency {
     }
 }
 
+impl FromStr for Currency {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Currency::try_from(s.trim().to_uppercase())
+    }
+}
+
 impl Currency {
     fn try_from(s: String) -> Result<Self, anyhow::Error> {
         Ok(Currency(
@@ -327,6 +615,202 @@ impl Currency {
     pub fn used_by(&self) -> Vec<&str> {
         self.0.used_by().iter().map(|c| c.name()).collect()
     }
+
+    /// Number of decimal places amounts are usually given with, like 2 for EUR or 0 for JPY.
+    pub fn minor_units(&self) -> u32 {
+        self.0.exponent().unwrap_or(2).into()
+    }
+}
+
+/// An exact decimal amount. Stored as text, so amounts keep the precision (and number of
+/// decimal places) the bank reported them with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Amount(Decimal);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(Decimal::ZERO);
+
+    pub fn is_negative(&self) -> bool {
+        self.0.is_sign_negative() && !self.0.is_zero()
+    }
+
+    pub fn abs(&self) -> Self {
+        Amount(self.0.abs())
+    }
+
+    /// For APIs and scores that only take floats.
+    pub fn to_f64(&self) -> f64 {
+        self.0.to_f64().unwrap_or_default()
+    }
+}
+
+impl FromStr for Amount {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let trimmed = s.trim();
+        Decimal::from_str_exact(trimmed.strip_prefix('+').unwrap_or(trimmed))
+            .map(Amount)
+            .map_err(|_| anyhow!("Invalid amount \"{}\"", s))
+    }
+}
+
+impl Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Add for Amount {
+    type Output = Amount;
+
+    fn add(self, other: Amount) -> Amount {
+        Amount(self.0 + other.0)
+    }
+}
+
+impl Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, other: Amount) -> Amount {
+        Amount(self.0 - other.0)
+    }
+}
+
+impl Neg for Amount {
+    type Output = Amount;
+
+    fn neg(self) -> Amount {
+        Amount(-self.0)
+    }
+}
+
+impl<'r> sqlx::Decode<'r, sqlx::MySql> for Amount {
+    fn decode(
+        value: <sqlx::MySql as sqlx::database::HasValueRef<'r>>::ValueRef,
+    ) -> std::result::Result<Amount, Box<dyn std::error::Error + Send + Sync + 'static>> {
+        let s: String = sqlx::Decode::<'r, sqlx::MySql>::decode(value)?;
+        Amount::from_str(&s).map_err(|e| e.into())
+    }
+}
+
+impl<'a> sqlx::Encode<'a, sqlx::MySql> for Amount {
+    fn size_hint(&self) -> usize {
+        0
+    }
+
+    fn encode_by_ref(
+        &self,
+        buf: &mut <sqlx::MySql as sqlx::database::HasArguments<'a>>::ArgumentBuffer,
+    ) -> sqlx::encode::IsNull {
+        sqlx::Encode::<'a, sqlx::MySql>::encode_by_ref(&self.to_string(), buf)
+    }
+}
+
+impl sqlx::Type<sqlx::MySql> for Amount {
+    fn type_info() -> <sqlx::MySql as sqlx::Database>::TypeInfo {
+        <String as sqlx::Type<sqlx::MySql>>::type_info()
+    }
+}
+
+/// An amount in a currency. Adding, subtracting or comparing amounts in different
+/// currencies is an error rather than a silently wrong number.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Money {
+    pub amount: Amount,
+    pub currency: Currency,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CurrencyMismatch(pub Currency, pub Currency);
+
+impl Display for CurrencyMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Can't combine amounts in {} and {}", self.0, self.1)
+    }
+}
+
+impl std::error::Error for CurrencyMismatch {}
+
+impl Money {
+    pub fn new(amount: Amount, currency: Currency) -> Self {
+        Self { amount, currency }
+    }
+
+    /// Parse an amount and currency code as sources report them, like `"-12.50"` and `"EUR"`.
+    pub fn parse(amount: &str, currency: &str) -> Result<Self, anyhow::Error> {
+        Ok(Self {
+            amount: amount.parse()?,
+            currency: currency.parse()?,
+        })
+    }
+
+    pub fn zero(currency: Currency) -> Self {
+        Self::new(Amount::ZERO, currency)
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.amount.is_negative()
+    }
+
+    pub fn abs(&self) -> Self {
+        Self::new(self.amount.abs(), self.currency)
+    }
+
+    /// Convert to another currency at `rate` (how many `to` one of this currency is worth),
+    /// rounded to the other currency's minor units.
+    pub fn convert(&self, to: Currency, rate: Decimal) -> Option<Self> {
+        let amount = self.amount.0.checked_mul(rate)?.round_dp(to.minor_units());
+        Some(Self::new(Amount(amount), to))
+    }
+
+    fn same_currency(&self, other: &Money) -> Result<(), CurrencyMismatch> {
+        match self.currency == other.currency {
+            true => Ok(()),
+            false => Err(CurrencyMismatch(self.currency, other.currency)),
+        }
+    }
+}
+
+impl Display for Money {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.amount, self.currency)
+    }
+}
+
+impl Add for Money {
+    type Output = Result<Money, CurrencyMismatch>;
+
+    fn add(self, other: Money) -> Self::Output {
+        self.same_currency(&other)?;
+        Ok(Money::new(self.amount + other.amount, self.currency))
+    }
+}
+
+impl Sub for Money {
+    type Output = Result<Money, CurrencyMismatch>;
+
+    fn sub(self, other: Money) -> Self::Output {
+        self.same_currency(&other)?;
+        Ok(Money::new(self.amount - other.amount, self.currency))
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money::new(-self.amount, self.currency)
+    }
+}
+
+impl PartialOrd for Money {
+    /// Amounts in different currencies aren't comparable.
+    fn partial_cmp(&self, other: &Money) -> Option<std::cmp::Ordering> {
+        self.same_currency(other).ok()?;
+        self.amount.partial_cmp(&other.amount)
+    }
 }
 
 #[async_trait]
@@ -334,4 +818,33 @@ pub trait TransactionDestination {
     // fn new(params: &str) -> Result<Self, anyhow::Error> where Self: Sized;
     async fn transaction_created(&self, transaction: &Transaction) -> Result<(), anyhow::Error>;
     // async fn get_params() -> Result<FunctionParams, anyhow::Error>;
+
+    /// Handle an event that isn't about a single transaction, such as `requisition_expiring`.
+    async fn event(&self, event: &str, _payload: &serde_json::Value) -> Result<(), anyhow::Error> {
+        Err(anyhow!("This function doesn't support {} events", event))
+    }
+
+    /// Console output captured during the last run, stored in the trigger log.
+    fn get_output(&self) -> Vec<String> {
+        vec![]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_money_arithmetic() {
+        let coffee = Money::parse("-3.10", "EUR").unwrap();
+        let refund = Money::parse("+0.20", "eur").unwrap();
+        // Floats would give -2.9000000000000004.
+        assert_eq!((coffee.clone() + refund).unwrap().to_string(), "-2.90 EUR");
+        assert!(Money::parse("12,50", "EUR").is_err());
+
+        let dollars = Money::parse("5.00", "USD").unwrap();
+        assert!((coffee.clone() - dollars.clone()).is_err());
+        assert_eq!(coffee.partial_cmp(&dollars), None);
+        assert!(coffee < Money::zero(coffee.currency));
+    }
 }